@@ -0,0 +1,188 @@
+//! An example implementation of tiered slab caches.
+//!
+//! This example implementation, `Tiered`, shows how to compose
+//! existing slab caches: a small, fast cache is tried first and
+//! a larger cache serves as the fallback once the first is exhausted.
+//! The tier that owns a slot is recorded in the slab extension,
+//! so that a recycled slot is always routed back to its owner.
+
+pub fn init() -> Result<(), Error> {
+    // The caches allocate their first slabs right away.
+    // So the page allocator must be ready before any cache is initialized.
+    ostd::heap::check_page_allocator_ready()?;
+    let slab_allocators = SlabAllocators {
+        size16: new_static_slab_cache!(16),
+        // ...
+        size2048: new_static_slab_cache!(2048),
+    };
+    ostd::heap::inject_slab_allocators(slab_allocators)
+}
+
+macro_rules! new_static_slab_cache {
+    ( $slot_size:expr ) => {
+        {
+            const SLOT_SIZE: usize = $slot_size;
+
+            static SMALL: SinglePageSlabCache<SLOT_SIZE, SlabExt> = SinglePageSlabCache::new();
+            static LARGE: SinglePageSlabCache<SLOT_SIZE, SlabExt> = SinglePageSlabCache::new();
+            static SINGLETON: Tiered<
+                SinglePageSlabCache<SLOT_SIZE, SlabExt>,
+                SinglePageSlabCache<SLOT_SIZE, SlabExt>,
+                SLOT_SIZE,
+            > = Tiered::new(&SMALL, &LARGE);
+
+            fn recycle_slot(
+                slot: FreeSlabSlot<SLOT_SIZE>,
+                extension: &dyn Any,
+                _pin_cpu_guard: &dyn PinCurrentCpu,
+            ) {
+                let extension = extension.downcast_ref::<SlabExt>().unwrap();
+                SINGLETON.recycle_slot(slot, extension.tier);
+            }
+
+            SMALL.init(recycle_slot, SlabExt { tier: Tier::First })?;
+            LARGE.init(recycle_slot, SlabExt { tier: Tier::Second })?;
+            &SINGLETON as &'static dyn SlabSlotAlloc<SLOT_SIZE>
+        }
+    }
+}
+
+/// A slab cache that tries the first tier and falls back to the second.
+pub struct Tiered<A: 'static, B: 'static, const SLOT_SIZE: usize> {
+    first: &'static A,
+    second: &'static B,
+}
+
+/// Identifies the tier of a `Tiered` cache.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Tier {
+    First,
+    Second,
+}
+
+#[derive(Clone)]
+struct SlabExt {
+    tier: Tier,
+}
+
+/// A slab cache that can take back the slots that it has allocated.
+pub trait RecycleSlot<const SLOT_SIZE: usize> {
+    fn recycle_slot(&self, free_slot: FreeSlabSlot<SLOT_SIZE>);
+}
+
+impl<const SLOT_SIZE: usize, Ext: Clone> RecycleSlot<SLOT_SIZE> for SinglePageSlabCache<SLOT_SIZE, Ext> {
+    fn recycle_slot(&self, free_slot: FreeSlabSlot<SLOT_SIZE>) {
+        SinglePageSlabCache::recycle_slot(self, free_slot)
+    }
+}
+
+impl<A, B, const SLOT_SIZE: usize> Tiered<A, B, SLOT_SIZE>
+where
+    A: SlabSlotAlloc<SLOT_SIZE> + RecycleSlot<SLOT_SIZE>,
+    B: SlabSlotAlloc<SLOT_SIZE> + RecycleSlot<SLOT_SIZE>,
+{
+    pub const fn new(first: &'static A, second: &'static B) -> Self {
+        Self {
+            first,
+            second,
+        }
+    }
+
+    /// Recycles a free slot to the tier that owns it.
+    pub fn recycle_slot(&self, free_slot: FreeSlabSlot<SLOT_SIZE>, owner_tier: Tier) {
+        match owner_tier {
+            Tier::First => self.first.recycle_slot(free_slot),
+            Tier::Second => self.second.recycle_slot(free_slot),
+        }
+    }
+}
+
+impl<A, B, const SLOT_SIZE: usize> SlabSlotAlloc<SLOT_SIZE> for Tiered<A, B, SLOT_SIZE>
+where
+    A: SlabSlotAlloc<SLOT_SIZE> + RecycleSlot<SLOT_SIZE>,
+    B: SlabSlotAlloc<SLOT_SIZE> + RecycleSlot<SLOT_SIZE>,
+{
+    fn alloc(&self, pin_cpu_guard: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        // Fast path: allocate from the first tier
+        if let Some(free_slot) = self.first.alloc(pin_cpu_guard) {
+            return Some(free_slot);
+        }
+
+        // Slow path: fall through to the second tier
+        self.second.alloc(pin_cpu_guard)
+    }
+
+    fn alloc_aligned(&self, pin_cpu_guard: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.first.alloc_aligned(pin_cpu_guard)
+            .or_else(|| self.second.alloc_aligned(pin_cpu_guard))
+    }
+
+    fn try_alloc_fast(&self, pin_cpu_guard: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.first.try_alloc_fast(pin_cpu_guard)
+            .or_else(|| self.second.try_alloc_fast(pin_cpu_guard))
+    }
+
+    fn shrink(&self, min_resident_slabs: usize) -> usize {
+        self.first.shrink(min_resident_slabs) + self.second.shrink(min_resident_slabs)
+    }
+
+    fn capacity_hint(&self) -> usize {
+        self.first.capacity_hint() + self.second.capacity_hint()
+    }
+
+    fn health_check(&self, on_corruption: &mut dyn FnMut(*const u8, SlabCorruption)) -> usize {
+        self.first.health_check(on_corruption) + self.second.health_check(on_corruption)
+    }
+
+    fn nr_inuse_slots(&self) -> usize {
+        self.first.nr_inuse_slots() + self.second.nr_inuse_slots()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ostd::heap::TestHeap;
+
+    #[test]
+    fn alloc_falls_through_when_the_first_tier_is_exhausted() {
+        // A slab of 2048-byte slots has a single page, so the first tier is exhausted quickly.
+        type Cache = SinglePageSlabCache<2048, SlabExt>;
+        let _test_heap = TestHeap::lock(64);
+        static FIRST: Cache = SinglePageSlabCache::new();
+        static SECOND: Cache = SinglePageSlabCache::new();
+        static TIERED: Tiered<Cache, Cache, 2048> = Tiered::new(&FIRST, &SECOND);
+        fn recycle_slot(slot: FreeSlabSlot<2048>, extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            let extension = extension.downcast_ref::<SlabExt>().unwrap();
+            TIERED.recycle_slot(slot, extension.tier);
+        }
+        FIRST.init(recycle_slot, SlabExt { tier: Tier::First }).unwrap();
+        SECOND.init(recycle_slot, SlabExt { tier: Tier::Second }).unwrap();
+        let irq_disabled_guard = irq::disable_local();
+        let tier_of = |slot: &FreeSlabSlot<2048>| slot.slab_view().extension().downcast_ref::<SlabExt>().unwrap().tier;
+
+        let mut first_slots = Vec::new();
+        let second_slot = loop {
+            let slot = TIERED.alloc(&irq_disabled_guard).unwrap();
+            if tier_of(&slot) == Tier::Second {
+                break slot;
+            }
+            first_slots.push(slot);
+        };
+        // The first tier only falls through after all its slabs are full.
+        let nr_slots_per_slab = second_slot.slab_view().nr_total_slots();
+        assert_eq!(first_slots.len(), MAX_NR_SLABS * nr_slots_per_slab);
+        assert_eq!(FIRST.capacity_hint(), 0);
+        assert_eq!(SECOND.nr_inuse_slots(), 1);
+
+        // Each slot is recycled to the tier that has allocated it.
+        second_slot.recycle(&irq_disabled_guard);
+        assert_eq!(SECOND.nr_inuse_slots(), 0);
+        assert_eq!(FIRST.nr_inuse_slots(), first_slots.len());
+        for slot in first_slots {
+            slot.recycle(&irq_disabled_guard);
+        }
+        assert_eq!(FIRST.nr_inuse_slots(), 0);
+    }
+}
+
@@ -1,12 +1,15 @@
 // Version 1: An example implementation of naive slab caches of one-page capacity.
 
-pub fn init() {
-    let slab_cache_array = Box::new(SlabAllocators {
+pub fn init() -> Result<(), Error> {
+    // The caches allocate their first slabs right away.
+    // So the page allocator must be ready before any cache is initialized.
+    ostd::heap::check_page_allocator_ready()?;
+    let slab_allocators = SlabAllocators {
         size16: new_static_slab_cache!(16),
         // ...
         size2048: new_static_slab_cache!(2048),
-    }).leak();
-    ostd::heap::inject_slab_cache_array(slab_cache_array);
+    };
+    ostd::heap::inject_slab_allocators(slab_allocators)
 }
 
 macro_rules! new_static_slab_cache {
@@ -24,45 +27,2333 @@ macro_rules! new_static_slab_cache {
                 SINGLETON.recycle_slot(slot);
             }
 
-            SINGLETON.init(());
+            SINGLETON.init(recycle_slot, ())?;
             &SINGLETON as &'static dyn AnySlabCache<SLOT_SIZE> 
         }
     }
 }
 
-pub struct SinglePageSlabCache<const SLOT_SIZE: usize, Ext> {
-    slab: SpinLock<Option<Slab<SLOT_SIZE, Ext>>>,
+/// A slab cache of one slab, or up to `MAX_NR_SLABS` slabs
+/// if it is initialized with a larger capacity (see `init_with_capacity`).
+///
+/// The states of the cache are protected by a lock of type `L`,
+/// which is a `SpinLock` by default, or a `BackoffLock` with
+/// the `slab_lock_backoff` feature. When the caller can guarantee
+/// that the cache is never accessed concurrently (e.g., on a uniprocessor
+/// with IRQs disabled), the lock can be elided with `NoLock`.
+pub struct SinglePageSlabCache<
+    const SLOT_SIZE: usize,
+    Ext,
+    L = DefaultCacheLock<SlabCacheInner<SLOT_SIZE, Ext>>,
+> {
+    inner: L,
+    phantom: PhantomData<SlabCacheInner<SLOT_SIZE, Ext>>,
 }
 
-impl<const SLOT_SIZE: usize, Ext> SinglePageSlabCache<SLOT_SIZE, Ext> {
+/// The maximum number of slabs of a `SinglePageSlabCache`.
+pub const MAX_NR_SLABS: usize = 8;
+
+#[doc(hidden)]
+pub struct SlabCacheInner<const SLOT_SIZE: usize, Ext> {
+    // The slabs are allocated from the first vacant entry,
+    // so the resident slabs are not necessarily contiguous after reclamation.
+    slabs: [Option<Slab<SLOT_SIZE, Ext>>; MAX_NR_SLABS],
+    // The arguments to re-allocate the slab after it has been reclaimed.
+    slot_recycle_fn: Option<SlotRecycleFn<SLOT_SIZE>>,
+    slab_extension: Option<Ext>,
+    // The constructor of the slabs, which is `Slab::alloc`
+    // unless the slabs have hooks (see `SinglePageSlabCache::init_aged`).
+    alloc_slab: fn(SlotRecycleFn<SLOT_SIZE>, Ext) -> Option<Slab<SLOT_SIZE, Ext>>,
+    // The constructor of the slabs of the aligned sub-pool, which is `Slab::alloc_aligned`
+    // unless the slabs have hooks (see `SinglePageSlabCache::init_aged`).
+    alloc_aligned_slab: fn(SlotRecycleFn<SLOT_SIZE>, Ext) -> Option<Slab<SLOT_SIZE, Ext>>,
+}
+
+impl<const SLOT_SIZE: usize, Ext> SlabCacheInner<SLOT_SIZE, Ext> {
+    const fn new() -> Self {
+        Self {
+            slabs: [const { None }; MAX_NR_SLABS],
+            slot_recycle_fn: None,
+            slab_extension: None,
+            alloc_slab: Slab::alloc,
+            alloc_aligned_slab: Slab::alloc_aligned,
+        }
+    }
+}
+
+/// A lock that protects the states of a `SinglePageSlabCache`.
+pub trait CacheLock<T> {
+    type Guard<'a>: DerefMut<Target = T> where Self: 'a;
+
+    fn lock(&self) -> Self::Guard<'_>;
+
+    /// Acquires the lock only if it is free, without waiting.
+    ///
+    /// This is for the code that must not wait on the lock,
+    /// e.g., formatting the cache for diagnostics while the lock may be held.
+    fn try_lock(&self) -> Option<Self::Guard<'_>>;
+}
+
+impl<T> CacheLock<T> for SpinLock<T> {
+    type Guard<'a> = SpinLockGuard<'a, T> where Self: 'a;
+
+    fn lock(&self) -> Self::Guard<'_> {
+        SpinLock::lock(self)
+    }
+
+    fn try_lock(&self) -> Option<Self::Guard<'_>> {
+        SpinLock::try_lock(self)
+    }
+}
+
+/// The default lock of `SinglePageSlabCache`.
+#[cfg(not(feature = "slab_lock_backoff"))]
+pub type DefaultCacheLock<T> = SpinLock<T>;
+/// The default lock of `SinglePageSlabCache`.
+#[cfg(feature = "slab_lock_backoff")]
+pub type DefaultCacheLock<T> = BackoffLock<T>;
+
+/// A spin lock that backs off exponentially under contention.
+///
+/// Instead of spinning tightly on the lock word, a waiter pauses
+/// for exponentially more iterations after each failed attempt,
+/// which reduces the cache-line traffic and the power wasted
+/// when many CPUs contend for the same size class.
+///
+/// Like the locks of the slab caches on the allocation path,
+/// the lock does not disable IRQs by itself.
+pub struct BackoffLock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: The data is only accessed while holding the lock.
+unsafe impl<T: Send> Sync for BackoffLock<T> {}
+
+impl<T> BackoffLock<T> {
+    // The maximum number of pauses between two attempts.
+    const MAX_BACKOFF: u32 = 1 << 10;
+
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> CacheLock<T> for BackoffLock<T> {
+    type Guard<'a> = BackoffLockGuard<'a, T> where Self: 'a;
+
+    fn lock(&self) -> Self::Guard<'_> {
+        let mut backoff = 1;
+        // Only retry the atomic operation when the lock appears to be free,
+        // so that waiters do not steal the cache line from the lock holder.
+        while self.locked.compare_exchange_weak(false, true, Acquire, Relaxed).is_err() {
+            while self.locked.load(Relaxed) {
+                for _ in 0..backoff {
+                    core::hint::spin_loop();
+                }
+                backoff = (backoff * 2).min(Self::MAX_BACKOFF);
+            }
+        }
+        BackoffLockGuard { lock: self }
+    }
+
+    fn try_lock(&self) -> Option<Self::Guard<'_>> {
+        self.locked
+            .compare_exchange(false, true, Acquire, Relaxed)
+            .is_ok()
+            .then(|| BackoffLockGuard { lock: self })
+    }
+}
+
+pub struct BackoffLockGuard<'a, T> {
+    lock: &'a BackoffLock<T>,
+}
+
+impl<T> Deref for BackoffLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: The lock is held.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for BackoffLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The lock is held.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for BackoffLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Release);
+    }
+}
+
+/// A "lock" that does no locking at all.
+///
+/// This is only sound if the protected data is never accessed concurrently.
+pub struct NoLock<T>(UnsafeCell<T>);
+
+// SAFETY: The creator of a `NoLock` guarantees that the data is never
+// accessed concurrently (see `SinglePageSlabCache::new_unlocked`).
+unsafe impl<T: Send> Sync for NoLock<T> {}
+
+impl<T> CacheLock<T> for NoLock<T> {
+    type Guard<'a> = &'a mut T where Self: 'a;
+
+    fn lock(&self) -> Self::Guard<'_> {
+        // SAFETY: The data is never accessed concurrently.
+        unsafe { &mut *self.0.get() }
+    }
+
+    fn try_lock(&self) -> Option<Self::Guard<'_>> {
+        Some(self.lock())
+    }
+}
+
+impl<const SLOT_SIZE: usize, Ext: Clone> SinglePageSlabCache<SLOT_SIZE, Ext> {
     pub const fn new() -> Self {
         Self {
-            slab: SpinLock::new(None),
+            inner: DefaultCacheLock::new(SlabCacheInner::new()),
+            phantom: PhantomData,
         }
     }
+}
+
+impl<const SLOT_SIZE: usize, Ext: Clone>
+    SinglePageSlabCache<SLOT_SIZE, Ext, NoLock<SlabCacheInner<SLOT_SIZE, Ext>>>
+{
+    /// Creates a slab cache without any locking.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the cache is never accessed concurrently,
+    /// including the recycling of slots from other CPUs or IRQ handlers.
+    pub const unsafe fn new_unlocked() -> Self {
+        Self {
+            inner: NoLock(UnsafeCell::new(SlabCacheInner::new())),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<const SLOT_SIZE: usize, Ext: Clone, L> SinglePageSlabCache<SLOT_SIZE, Ext, L>
+where
+    L: CacheLock<SlabCacheInner<SLOT_SIZE, Ext>>,
+{
 
     #[doc(hidden)]
-    pub fn init(&self, recycle_slot_fn: RecycleSlotFn, slab_extension: Ext) {
-        let mut slab_guard = self.slab.lock();
-        let slab = Slab::alloc(recycle_slot_fn, slab_extension).unwrap();
-        *slab_guard = Some(slab);
+    pub fn init(&self, slot_recycle_fn: SlotRecycleFn<SLOT_SIZE>, slab_extension: Ext) -> Result<(), Error> {
+        self.init_with_capacity(slot_recycle_fn, slab_extension, 1)
+    }
+
+    /// Initializes the cache with `nr_slabs` slabs allocated up front,
+    /// so that the first burst of allocations never allocates slabs.
+    ///
+    /// Each slab gets a clone of the extension.
+    ///
+    /// Returns `Error::PageAllocatorNotReady` if the page allocator
+    /// has not been initialized, in which case the cache is left uninitialized,
+    /// or `Error::NoMemory` if the slabs cannot be allocated.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `nr_slabs` is zero or more than `MAX_NR_SLABS`.
+    pub fn init_with_capacity(
+        &self,
+        slot_recycle_fn: SlotRecycleFn<SLOT_SIZE>,
+        slab_extension: Ext,
+        nr_slabs: usize,
+    ) -> Result<(), Error> {
+        assert!(
+            nr_slabs >= 1 && nr_slabs <= MAX_NR_SLABS,
+            "the capacity must be 1 to {} slabs, got {}",
+            MAX_NR_SLABS,
+            nr_slabs
+        );
+        ostd::heap::check_page_allocator_ready()?;
+
+        let mut inner = self.inner.lock();
+        inner.slot_recycle_fn = Some(slot_recycle_fn);
+        inner.slab_extension = Some(slab_extension.clone());
+        let alloc_slab = inner.alloc_slab;
+        for slab in inner.slabs[..nr_slabs].iter_mut() {
+            *slab = Some(alloc_slab(slot_recycle_fn, slab_extension.clone()).ok_or(Error::NoMemory)?);
+        }
+        Ok(())
     }
 
     pub fn new_slot(&self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
-        let mut slab_guad = self.slab.lock();
-        let slab = slab_guard.as_mut().unwrap(); 
+        self.new_slot_in_pool(false)
+    }
+
+    /// Allocates a slot that is aligned to `SLOT_SIZE`.
+    ///
+    /// With the `slab_coloring` feature, the slots of colored slabs are less aligned
+    /// (see `Slab::is_colored`), so the slot comes from the aligned sub-pool
+    /// of the cache, i.e., the resident slabs that are not colored.
+    /// A new slab of the sub-pool is allocated with `Slab::alloc_aligned`.
+    /// The two sub-pools share the capacity of the cache.
+    ///
+    /// Without the feature, no slab is colored, so this is the same as `new_slot`.
+    pub fn new_aligned_slot(&self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.new_slot_in_pool(true)
+    }
+
+    // Allocates a slot from the aligned sub-pool if `is_aligned` is true,
+    // or from any resident slab otherwise.
+    fn new_slot_in_pool(&self, is_aligned: bool) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        let mut inner = self.inner.lock();
+        if let Some(free_slot) = inner.new_slot_from_resident_slabs(is_aligned) {
+            return Some(free_slot);
+        }
+
+        // All the resident slabs are full or have been reclaimed. Allocate a new one,
+        // unless the slabs claimed by others still have free slots while the memory is low.
+        let nr_nonfull_slabs = inner.slabs.iter().flatten().filter(|slab| slab.has_unused_slots()).count();
+        if !ostd::heap::admits_new_slab(nr_nonfull_slabs) {
+            return None;
+        }
+        let slot_recycle_fn = inner.slot_recycle_fn.unwrap();
+        let slab_extension = inner.slab_extension.clone().unwrap();
+        let alloc_slab = if is_aligned { inner.alloc_aligned_slab } else { inner.alloc_slab };
+        let vacant_slab = inner.slabs.iter_mut().find(|slab| slab.is_none())?;
+        let slab = vacant_slab.insert(alloc_slab(slot_recycle_fn, slab_extension)?);
         slab.new_slot()
     }
 
+    /// Allocates a slot from the resident slabs, if any, without allocating a new slab.
+    pub fn new_slot_fast(&self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        let mut inner = self.inner.lock();
+        inner.new_slot_from_resident_slabs(false)
+    }
+
     pub fn recycle_slot(&self, free_slot: FreeSlabSlot<SLOT_SIZE>) {
-        let mut slab_guad = self.slab.lock();
-        let slab = slab_guard.as_mut().unwrap(); 
+        let mut inner = self.inner.lock();
+        let slab = inner.slab_of(&free_slot);
         slab.recycle_slot(free_slot)
     }
+
+    /// Recycles all the slots on a free list, in one lock acquisition.
+    ///
+    /// All the slots must belong to the slabs of this cache.
+    pub fn recycle_batch(&self, mut free_slots: FreeSlabSlotList<SLOT_SIZE>) {
+        let mut inner = self.inner.lock();
+        while let Some(free_slot) = free_slots.pop() {
+            let slab = inner.slab_of(&free_slot);
+            slab.recycle_slot(free_slot);
+        }
+    }
+
+    /// Moves the slabs of this cache to `dst`, re-homing each slab
+    /// with a clone of `extension` (see `Slab::rehome`).
+    ///
+    /// The free slots of the slabs move along with them, and the slots in use
+    /// are recycled to `dst` afterwards. The slabs that do not fit in `dst`
+    /// stay in this cache. Returns the number of the moved slabs.
+    ///
+    /// The two caches are locked in the order of their addresses,
+    /// so that the migrations in opposite directions cannot deadlock.
+    ///
+    /// # Safety
+    ///
+    /// No slot of the slabs of this cache may be recycled concurrently,
+    /// and `dst` must recycle slots with the same `SlotRecycleFn` as this cache.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `dst` is this cache.
+    pub unsafe fn migrate_slabs_to(&self, dst: &Self, extension: Ext) -> usize {
+        assert!(!ptr::eq(self, dst), "a slab cache cannot migrate its slabs to itself");
+        let (mut src_inner, mut dst_inner) = if (self as *const Self) < (dst as *const Self) {
+            let src_inner = self.inner.lock();
+            (src_inner, dst.inner.lock())
+        } else {
+            let dst_inner = dst.inner.lock();
+            (self.inner.lock(), dst_inner)
+        };
+        let slot_recycle_fn = src_inner.slot_recycle_fn.unwrap();
+
+        let mut nr_moved = 0;
+        for src_slab in src_inner.slabs.iter_mut().filter(|slab| slab.is_some()) {
+            let Some(vacant_slab) = dst_inner.slabs.iter_mut().find(|slab| slab.is_none()) else {
+                break;
+            };
+            let mut slab = src_slab.take().unwrap();
+            // SAFETY: The caller guarantees that no slot of the slab
+            // is recycled concurrently.
+            drop(unsafe { slab.rehome(slot_recycle_fn, extension.clone()) });
+            *vacant_slab = Some(slab);
+            nr_moved += 1;
+        }
+        nr_moved
+    }
+
+    /// Moves one unused slab of at most `max_nr_slots` slots to `dst`,
+    /// re-homing the slab with `extension` (see `Slab::rehome`).
+    ///
+    /// Unlike `migrate_slabs_to`, this method is safe: no slot of an unused slab
+    /// is in use or borrowed, so none can be recycled concurrently.
+    /// The two caches are never locked at the same time, so the method
+    /// cannot deadlock with a migration in the opposite direction.
+    ///
+    /// Returns whether a slab has been moved, which requires a vacant entry in `dst`.
+    /// If `dst` has no vacant entry, the slab stays in this cache.
+    /// `dst` must recycle slots with the same `SlotRecycleFn` as this cache.
+    pub fn migrate_unused_slab_to(&self, dst: &Self, extension: Ext, max_nr_slots: usize) -> bool {
+        if dst.inner.lock().slabs.iter().all(|slab| slab.is_some()) {
+            return false;
+        }
+
+        let (mut slab, slot_recycle_fn) = {
+            let mut src_inner = self.inner.lock();
+            let slot_recycle_fn = src_inner.slot_recycle_fn.unwrap();
+            let Some(src_slab) = src_inner.slabs.iter_mut().find(|slab| {
+                slab.as_ref()
+                    .is_some_and(|slab| slab.nr_used_slots() == 0 && slab.nr_total_slots() <= max_nr_slots)
+            }) else {
+                return false;
+            };
+            (src_slab.take().unwrap(), slot_recycle_fn)
+        };
+        // SAFETY: The slab is unused, so no slot of the slab is recycled concurrently.
+        let src_extension = unsafe { slab.rehome(slot_recycle_fn, extension) };
+
+        let mut dst_inner = dst.inner.lock();
+        if let Some(vacant_slab) = dst_inner.slabs.iter_mut().find(|slab| slab.is_none()) {
+            *vacant_slab = Some(slab);
+            return true;
+        }
+        drop(dst_inner);
+
+        // The vacant entry of `dst` has been taken since the check above.
+        // So the slab is put back into this cache, unless its entry has been taken as well,
+        // in which case neither cache has room for the slab, so it is reclaimed.
+        // SAFETY: The slab is still unused.
+        drop(unsafe { slab.rehome(slot_recycle_fn, src_extension) });
+        let mut src_inner = self.inner.lock();
+        if let Some(vacant_slab) = src_inner.slabs.iter_mut().find(|slab| slab.is_none()) {
+            *vacant_slab = Some(slab);
+        }
+        false
+    }
+
+    /// Reclaims the unused slabs, but keeps at least `min_resident_slabs` slabs resident.
+    ///
+    /// Returns the number of the reclaimed slabs.
+    pub fn shrink(&self, min_resident_slabs: usize) -> usize {
+        let mut inner = self.inner.lock();
+        let mut nr_resident_slabs = inner.slabs.iter().flatten().count();
+        let mut nr_reclaimed = 0;
+        for slab in inner.slabs.iter_mut() {
+            if nr_resident_slabs <= min_resident_slabs {
+                break;
+            }
+            // Borrowed slots are counted as in-use slots,
+            // so a slab with slots parked on cache-level free lists is never reclaimed.
+            if slab.as_ref().is_some_and(|slab| slab.try_begin_reclaim()) {
+                drop(slab.take());
+                nr_resident_slabs -= 1;
+                nr_reclaimed += 1;
+            }
+        }
+        nr_reclaimed
+    }
+
+    /// Returns the number of slots that can be allocated
+    /// without allocating a new slab.
+    ///
+    /// The borrowed slots are included, as the slab caches that borrow them
+    /// can allocate them without allocating a new slab either.
+    pub fn capacity_hint(&self) -> usize {
+        let inner = self.inner.lock();
+        inner
+            .slabs
+            .iter()
+            .flatten()
+            .map(|slab| slab.nr_total_slots() - slab.nr_used_slots() + slab.nr_borrowed_slots())
+            .sum()
+    }
+
+    /// Returns the same number as `capacity_hint`, or `None` if the cache is locked.
+    pub fn try_capacity_hint(&self) -> Option<usize> {
+        let inner = self.inner.try_lock()?;
+        Some(
+            inner
+                .slabs
+                .iter()
+                .flatten()
+                .map(|slab| slab.nr_total_slots() - slab.nr_used_slots() + slab.nr_borrowed_slots())
+                .sum(),
+        )
+    }
+
+    /// Returns the number of the slots that are used by objects.
+    pub fn nr_inuse_slots(&self) -> usize {
+        let inner = self.inner.lock();
+        inner
+            .slabs
+            .iter()
+            .flatten()
+            .map(|slab| slab.nr_used_slots() - slab.nr_borrowed_slots())
+            .sum()
+    }
+
+    /// Validates the slabs of the cache.
+    ///
+    /// Returns the number of the corrupted slabs.
+    pub fn health_check(&self, on_corruption: &mut dyn FnMut(*const u8, SlabCorruption)) -> usize {
+        let inner = self.inner.lock();
+        let mut nr_corrupted = 0;
+        for slab in inner.slabs.iter().flatten() {
+            if let Err(corruption) = slab.validate() {
+                on_corruption(slab.base_ptr(), corruption);
+                nr_corrupted += 1;
+            }
+        }
+        nr_corrupted
+    }
+}
+
+impl<const SLOT_SIZE: usize, L> SinglePageSlabCache<SLOT_SIZE, AgeExt, L>
+where
+    L: CacheLock<SlabCacheInner<SLOT_SIZE, AgeExt>>,
+{
+    /// Initializes the cache with the slabs that track their age,
+    /// so that the cold slabs can be reclaimed first (see `shrink_oldest_first`).
+    ///
+    /// Each allocation updates the age of its slab with the clock set by `heap::set_clock`.
+    pub fn init_aged(&self, slot_recycle_fn: SlotRecycleFn<SLOT_SIZE>, slab_extension: AgeExt) -> Result<(), Error> {
+        let mut inner = self.inner.lock();
+        inner.alloc_slab = Slab::alloc_with_slot_hook;
+        inner.alloc_aligned_slab = Slab::alloc_aligned_with_slot_hook;
+        drop(inner);
+        self.init(slot_recycle_fn, slab_extension)
+    }
+
+    /// Reclaims the unused slabs like `shrink`, but the slabs that have been
+    /// allocated from least recently are reclaimed first (see `oldest_slab`).
+    ///
+    /// Returns the number of the reclaimed slabs.
+    pub fn shrink_oldest_first(&self, min_resident_slabs: usize) -> usize {
+        let mut inner = self.inner.lock();
+        let mut nr_resident_slabs = inner.slabs.iter().flatten().count();
+        let mut nr_reclaimed = 0;
+        while nr_resident_slabs > min_resident_slabs {
+            let Some(victim_base) = oldest_slab(
+                inner.slabs.iter().flatten().filter(|slab| slab.nr_used_slots() == 0),
+            )
+            .map(|slab| slab.base_ptr()) else {
+                break;
+            };
+            let victim = inner
+                .slabs
+                .iter_mut()
+                .find(|slab| slab.as_ref().is_some_and(|slab| slab.base_ptr() == victim_base))
+                .unwrap();
+            // An unused slab may still be claimed by a concurrent allocation.
+            if !victim.as_ref().unwrap().try_begin_reclaim() {
+                break;
+            }
+            drop(victim.take());
+            nr_resident_slabs -= 1;
+            nr_reclaimed += 1;
+        }
+        nr_reclaimed
+    }
+}
+
+impl<const SLOT_SIZE: usize, Ext> SlabCacheInner<SLOT_SIZE, Ext> {
+    // Allocates a slot from the resident slabs, or only from the uncolored ones
+    // if `is_aligned` is true (see `SinglePageSlabCache::new_aligned_slot`).
+    fn new_slot_from_resident_slabs(&mut self, is_aligned: bool) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.slabs
+            .iter_mut()
+            .flatten()
+            .filter(|slab| !is_aligned || !slab.is_colored())
+            .find_map(|slab| slab.new_slot())
+    }
+
+    // Returns the slab that the slot belongs to.
+    //
+    // A slot is always returned to its parent slab,
+    // which must be resident as long as the slot is in use.
+    fn slab_of(&mut self, free_slot: &FreeSlabSlot<SLOT_SIZE>) -> &mut Slab<SLOT_SIZE, Ext> {
+        self.slabs
+            .iter_mut()
+            .flatten()
+            .find(|slab| slab.contains_slot(free_slot))
+            .unwrap()
+    }
+}
+
+impl<const SLOT_SIZE: usize, Ext, L> fmt::Debug for SinglePageSlabCache<SLOT_SIZE, Ext, L>
+where
+    L: CacheLock<SlabCacheInner<SLOT_SIZE, Ext>>,
+{
+    // The cache is formatted without waiting for the lock, as the lock may be
+    // held by the current CPU, e.g., when a panic inside the cache is reported.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("SinglePageSlabCache");
+        debug_struct.field("slot_size", &SLOT_SIZE);
+        match self.inner.try_lock() {
+            Some(inner) => debug_struct.field("slabs", &inner.slabs),
+            None => debug_struct.field("slabs", &format_args!("<locked>")),
+        };
+        debug_struct.finish()
+    }
 }
 
-impl<const SLOT_SIZE: usize> SlabSlotAlloc<SLOT_SIZE> for SinglePageSlabCache<SLOT_SIZE> {
+impl<const SLOT_SIZE: usize, Ext: Clone, L> SlabSlotAlloc<SLOT_SIZE> for SinglePageSlabCache<SLOT_SIZE, Ext, L>
+where
+    L: CacheLock<SlabCacheInner<SLOT_SIZE, Ext>>,
+{
     fn alloc(&self, _: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
         self.new_slot()
     }
+
+    fn alloc_aligned(&self, _: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.new_aligned_slot()
+    }
+
+    fn try_alloc_fast(&self, _: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.new_slot_fast()
+    }
+
+    fn shrink(&self, min_resident_slabs: usize) -> usize {
+        SinglePageSlabCache::shrink(self, min_resident_slabs)
+    }
+
+    fn capacity_hint(&self) -> usize {
+        SinglePageSlabCache::capacity_hint(self)
+    }
+
+    fn health_check(&self, on_corruption: &mut dyn FnMut(*const u8, SlabCorruption)) -> usize {
+        SinglePageSlabCache::health_check(self, on_corruption)
+    }
+
+    fn nr_inuse_slots(&self) -> usize {
+        SinglePageSlabCache::nr_inuse_slots(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ostd::heap::{self, TestHeap};
+
+    // Creates a `SinglePageSlabCache` of 64-byte slots, which recycles the slots to itself.
+    //
+    // Each expansion has its own static cache, so that a test never sees
+    // the slabs that a previous test has allocated from its arena.
+    macro_rules! new_fresh_slab_cache {
+        () => {{
+            static CACHE: SinglePageSlabCache<64, ()> = SinglePageSlabCache::new();
+            fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+                CACHE.recycle_slot(slot);
+            }
+            CACHE.init(recycle_slot, ()).unwrap();
+            &CACHE
+        }};
+    }
+
+    // Injects a fresh set of the slab caches of this version.
+    //
+    // Each expansion has its own static caches, so that a test never sees
+    // the slabs that a previous test has allocated from its arena.
+    macro_rules! inject_fresh_slab_caches {
+        () => {
+            (|| -> Result<(), Error> {
+                heap::inject_slab_allocators(SlabAllocators {
+                    size16: new_static_slab_cache!(16),
+                    // ...
+                    size2048: new_static_slab_cache!(2048),
+                })
+            })()
+            .unwrap()
+        };
+    }
+
+    #[test]
+    fn alloc_and_free_across_classes() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        assert_eq!(heap::current_backend_name(), "slab_caches");
+
+        // The sizes span the smallest and the largest size classes, and the large objects.
+        let layouts = [1, 16, 24, 100, 512, 2048, 3 * PAGE_SIZE]
+            .map(|size| Layout::from_size_align(size, 8).unwrap());
+        // SAFETY: The layouts are not zero-sized.
+        let ptrs = layouts.map(|layout| unsafe { alloc::alloc::alloc(layout) });
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+        let report = heap::verify_no_leaks().unwrap_err();
+        assert_eq!(report.nr_leaked_slots[SizeClass::B16.index()], 2);
+        assert_eq!(report.nr_large_objects, 1);
+
+        for (ptr, layout) in ptrs.into_iter().zip(layouts) {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn init_before_page_allocator_is_ready() {
+        let _test_heap = TestHeap::lock_before_page_alloc();
+        assert!(matches!(init(), Err(Error::PageAllocatorNotReady)));
+        assert!(!heap::is_injected());
+        assert_eq!(heap::current_backend_name(), "early_heap");
+    }
+
+    #[test]
+    fn dealloc_routes_by_the_slab_of_the_slot() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+
+        // The layout implies the 16-byte class, but the slot belongs to the 64-byte one.
+        // Opting out of the check of the layout, the slot is freed to the 64-byte class.
+        let smaller_layout = Layout::from_size_align(16, 8).unwrap();
+        // SAFETY: The memory is allocated above, and the layout fits in the slot.
+        unsafe { heap::dealloc_in_larger_slot(ptr, smaller_layout) };
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "mismatched layout (Layout { size: 16, align: 8 }): the slot size is 64 bytes")]
+    fn dealloc_with_a_layout_smaller_than_the_slot() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+
+        // Trusting the layout would free the slot to the cache of 16-byte slots.
+        let wrong_layout = Layout::from_size_align(16, 8).unwrap();
+        // SAFETY: The memory is allocated above. The layout is wrong on purpose.
+        unsafe { alloc::alloc::dealloc(ptr, wrong_layout) };
+    }
+
+    #[test]
+    fn shrink_reclaims_the_oldest_slab_first() {
+        let _test_heap = TestHeap::lock(64);
+        static TICK: AtomicU64 = AtomicU64::new(0);
+        heap::set_clock(|| TICK.load(Relaxed));
+
+        static CACHE: SinglePageSlabCache<64, AgeExt> = SinglePageSlabCache::new();
+        fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            CACHE.recycle_slot(slot);
+        }
+        CACHE.init_aged(recycle_slot, AgeExt::new(0)).unwrap();
+
+        // Fill up the first slab at tick 1, and allocate from the second slab at tick 2.
+        TICK.store(1, Relaxed);
+        let mut slots = FreeSlabSlotList::new();
+        while CACHE.capacity_hint() > 0 {
+            slots.push(CACHE.new_slot().unwrap());
+        }
+        TICK.store(2, Relaxed);
+        let newer_slot = CACHE.new_slot().unwrap();
+        let newer_slab_base = CACHE.inner.lock().slab_of(&newer_slot).base_ptr();
+        CACHE.recycle_batch(slots);
+        CACHE.recycle_slot(newer_slot);
+
+        // Both slabs are unused, and the one of tick 1 is the victim.
+        assert_eq!(CACHE.shrink_oldest_first(1), 1);
+        let inner = CACHE.inner.lock();
+        let resident_slabs: Vec<_> = inner.slabs.iter().flatten().map(|slab| slab.base_ptr()).collect();
+        assert_eq!(resident_slabs, [newer_slab_base]);
+    }
+
+    #[test]
+    fn low_memory_watermark_does_not_starve_other_classes() {
+        let _test_heap = TestHeap::lock(16);
+        static CACHE16: SinglePageSlabCache<16, ()> = SinglePageSlabCache::new();
+        static CACHE32: SinglePageSlabCache<32, ()> = SinglePageSlabCache::new();
+        fn recycle_slot16(slot: FreeSlabSlot<16>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            CACHE16.recycle_slot(slot);
+        }
+        fn recycle_slot32(slot: FreeSlabSlot<32>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            CACHE32.recycle_slot(slot);
+        }
+        CACHE16.init(recycle_slot16, ()).unwrap();
+        CACHE32.init(recycle_slot32, ()).unwrap();
+
+        // The memory is always low from now on.
+        heap::set_low_memory_watermark(TestHeap::MAX_NR_PAGES);
+        assert!(heap::admits_new_slab(0));
+        assert!(!heap::admits_new_slab(1));
+
+        // Each class grows once its own slab is full,
+        // regardless of the non-full slabs of the other class.
+        let mut slots16 = FreeSlabSlotList::new();
+        while CACHE16.capacity_hint() > 0 {
+            slots16.push(CACHE16.new_slot().unwrap());
+        }
+        let slot16 = CACHE16.new_slot().unwrap();
+        let slot32 = CACHE32.new_slot().unwrap();
+        let mut slots32 = FreeSlabSlotList::new();
+        while CACHE32.capacity_hint() > 0 {
+            slots32.push(CACHE32.new_slot().unwrap());
+        }
+        slots32.push(CACHE32.new_slot().unwrap());
+
+        CACHE16.recycle_slot(slot16);
+        CACHE32.recycle_slot(slot32);
+        CACHE16.recycle_batch(slots16);
+        CACHE32.recycle_batch(slots32);
+    }
+
+    #[test]
+    fn alloc_retries_after_shrinking_other_classes() {
+        // The arena has a few pages to spare after the initial slabs of all the classes.
+        let test_heap = TestHeap::lock(20);
+        crate::slab_v1::init().unwrap();
+        // Each class keeps its initial slab resident, so the retries only reclaim the extra slabs.
+        for &size_class in SizeClass::ALL.iter() {
+            heap::set_min_resident(size_class, 1);
+        }
+
+        // Balloon class A (256 bytes) until the page allocator is out of memory.
+        let layout_a = Layout::from_size_align(256, 8).unwrap();
+        let mut ptrs_a = [ptr::null_mut(); 256];
+        let mut nr_ptrs_a = 0;
+        loop {
+            // SAFETY: The layout is not zero-sized.
+            let ptr = unsafe { alloc::alloc::alloc(layout_a) };
+            if ptr.is_null() {
+                break;
+            }
+            ptrs_a[nr_ptrs_a] = ptr;
+            nr_ptrs_a += 1;
+        }
+        // The slabs of A are unused but resident after that.
+        for &ptr in ptrs_a[..nr_ptrs_a].iter() {
+            // SAFETY: The memory is allocated above with the layout.
+            unsafe { alloc::alloc::dealloc(ptr, layout_a) };
+        }
+        let nr_free_pages = test_heap.nr_free_pages();
+
+        // Class B (2048 bytes) fills up its initial slab of one slot, and cannot allocate a new one
+        // until the retry returns the unused slabs of A to the page allocator.
+        let layout_b = Layout::from_size_align(2048, 8).unwrap();
+        let mut ptrs_b = [ptr::null_mut(); 2];
+        for ptr_b in ptrs_b.iter_mut() {
+            // SAFETY: The layout is not zero-sized.
+            *ptr_b = unsafe { alloc::alloc::alloc(layout_b) };
+            assert!(!ptr_b.is_null());
+        }
+        // The extra slabs of A outnumber the new slab of B.
+        assert!(test_heap.nr_free_pages() > nr_free_pages);
+        for &ptr in ptrs_b.iter() {
+            // SAFETY: The memory is allocated above with the layout.
+            unsafe { alloc::alloc::dealloc(ptr, layout_b) };
+        }
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn for_each_used_slot_visits_the_live_slots() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+
+        // Allocate five slots, and free the second and the fourth of them.
+        let mut slots = [const { None }; 5];
+        for slot in slots.iter_mut() {
+            *slot = Some(cache.new_slot().unwrap());
+        }
+        let live_ptrs = [0, 2, 4].map(|i| slots[i].as_ref().unwrap().as_ptr());
+        cache.recycle_slot(slots[1].take().unwrap());
+        cache.recycle_slot(slots[3].take().unwrap());
+
+        let mut visited_ptrs = [ptr::null_mut(); 5];
+        let mut nr_visited = 0;
+        {
+            let inner = cache.inner.lock();
+            let slab = inner.slabs.iter().flatten().next().unwrap();
+            slab.for_each_used_slot(|slot_ptr| {
+                visited_ptrs[nr_visited] = slot_ptr.as_ptr();
+                nr_visited += 1;
+            });
+        }
+        // The slots are visited in the ascending order of addresses.
+        let mut sorted_live_ptrs = live_ptrs;
+        sorted_live_ptrs.sort();
+        assert_eq!(visited_ptrs[..nr_visited], sorted_live_ptrs);
+
+        for slot in slots.into_iter().flatten() {
+            cache.recycle_slot(slot);
+        }
+    }
+
+    #[test]
+    fn try_alloc_fast_never_allocates_slabs() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let irq_disabled_guard = irq::disable_local();
+
+        // Empty the fast path, i.e., the resident slab.
+        let mut slots = FreeSlabSlotList::new();
+        while let Some(slot) = cache.try_alloc_fast(&irq_disabled_guard) {
+            slots.push(slot);
+        }
+        assert!(!slots.is_empty());
+        assert_eq!(cache.capacity_hint(), 0);
+        assert_eq!(cache.inner.lock().slabs.iter().flatten().count(), 1);
+
+        cache.recycle_batch(slots);
+    }
+
+    #[test]
+    fn slab_base_is_recovered_from_any_slot_of_a_multi_page_slab() {
+        let _test_heap = TestHeap::lock(64);
+        static CACHE: SinglePageSlabCache<16, ()> = SinglePageSlabCache::new();
+        fn recycle_slot(slot: FreeSlabSlot<16>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            CACHE.recycle_slot(slot);
+        }
+        CACHE.init(recycle_slot, ()).unwrap();
+        let nr_pages = heap::pages_per_slab(SizeClass::B16);
+        assert!(nr_pages > 1);
+
+        let mut slots = FreeSlabSlotList::new();
+        while let Some(slot) = CACHE.new_slot_fast() {
+            slots.push(slot);
+        }
+        let inner = CACHE.inner.lock();
+        let slab = inner.slabs.iter().flatten().next().unwrap();
+        let slab_base = slab.base_ptr() as usize;
+        let mut pages_seen = [false; MAX_PAGES_PER_SLAB];
+        let mut checked_slots = FreeSlabSlotList::new();
+        while let Some(slot) = slots.pop() {
+            // The slots lie on every page of the slab, but all lead to the same slab.
+            assert!(slab.contains_slot(&slot));
+            let offset = slot.as_ptr() as usize - slab_base;
+            assert!(offset < nr_pages * PAGE_SIZE);
+            pages_seen[offset / PAGE_SIZE] = true;
+            checked_slots.push(slot);
+        }
+        assert!(pages_seen[1..nr_pages].iter().all(|&seen| seen));
+        drop(inner);
+
+        CACHE.recycle_batch(checked_slots);
+    }
+
+    #[test]
+    fn page_pressure_returns_unused_slabs() {
+        let test_heap = TestHeap::lock(64);
+        crate::slab_v1::init().unwrap();
+        static HANDLER: Once<heap::PagePressureHandler> = Once::new();
+        heap::on_page_pressure(|handler| {
+            HANDLER.call_once(|| handler);
+        });
+
+        // Leave an unused slab in the 256-byte class, besides its initial one.
+        let layout = Layout::from_size_align(256, 8).unwrap();
+        let mut ptrs = [ptr::null_mut(); 32];
+        for ptr in ptrs.iter_mut() {
+            // SAFETY: The layout is not zero-sized.
+            *ptr = unsafe { alloc::alloc::alloc(layout) };
+            assert!(!ptr.is_null());
+        }
+        for &ptr in ptrs.iter() {
+            // SAFETY: The memory is allocated above with the layout.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+
+        // Simulate the callback of the page allocator.
+        let handle_page_pressure = HANDLER.get().unwrap();
+        let nr_free_pages = test_heap.nr_free_pages();
+        let nr_freed_pages = handle_page_pressure(1);
+        assert!(nr_freed_pages >= 1);
+        assert_eq!(test_heap.nr_free_pages(), nr_free_pages + nr_freed_pages);
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn alloc_a_tiny_object_in_a_large_class() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let ptr = heap::alloc_in_class(2048, 4).unwrap();
+        assert_eq!(heap::usable_size(ptr.as_ptr()), Some(2048));
+        let report = heap::verify_no_leaks().unwrap_err();
+        assert_eq!(report.nr_leaked_slots[SizeClass::B2048.index()], 1);
+
+        // The object is of 4 bytes, while its slot is of 2048 bytes.
+        let layout = Layout::from_size_align(4, 4).unwrap();
+        // SAFETY: The memory is allocated above, and the object fits in the slot.
+        unsafe { heap::dealloc_in_larger_slot(ptr.as_ptr(), layout) };
+        heap::verify_no_leaks().unwrap();
+
+        assert!(heap::alloc_in_class(100, 4).is_none());
+    }
+
+    #[test]
+    fn alloc_in_class_fails_before_injection() {
+        let _test_heap = TestHeap::lock(64);
+        assert!(!heap::is_injected());
+
+        assert!(heap::alloc_in_class(2048, 4).is_none());
+
+        inject_fresh_slab_caches!();
+        let ptr = heap::alloc_in_class(2048, 4).unwrap();
+        assert_eq!(heap::usable_size(ptr.as_ptr()), Some(2048));
+        // SAFETY: The memory is allocated above, and the object fits in the slot.
+        unsafe { heap::dealloc_in_larger_slot(ptr.as_ptr(), Layout::from_size_align(4, 4).unwrap()) };
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "mismatched layout")]
+    fn dealloc_with_a_layout_larger_than_the_slot() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+
+        let wrong_layout = Layout::from_size_align(32, 8).unwrap();
+        // SAFETY: The memory is allocated above. The layout is wrong on purpose.
+        unsafe { alloc::alloc::dealloc(ptr, wrong_layout) };
+    }
+
+    #[test]
+    fn debug_prints_the_summary_of_a_known_state() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+
+        let slot = cache.new_slot().unwrap();
+        let slot_debug = alloc::format!("{:?}", slot);
+        assert!(slot_debug.contains("ptr"));
+        assert!(slot_debug.contains("slot_size: 64"));
+
+        let cache_debug = alloc::format!("{:?}", cache);
+        assert!(cache_debug.contains("SinglePageSlabCache"));
+        assert!(cache_debug.contains("slot_size: 64"));
+        assert!(cache_debug.contains("nr_total"));
+        assert!(cache_debug.contains("nr_inuse: 1"));
+        assert!(cache_debug.contains("has_free: true"));
+
+        // A held lock is reported instead of waited for.
+        {
+            let _inner = cache.inner.lock();
+            assert!(alloc::format!("{:?}", cache).contains("<locked>"));
+        }
+
+        cache.recycle_slot(slot);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_double_free_check")]
+    #[should_panic(expected = "double free detected")]
+    fn double_free_is_detected() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+
+        let ptr = cache.new_slot().unwrap().into_raw();
+        // SAFETY: The slot is freed twice on purpose.
+        let (slot, same_slot) = unsafe { (FreeSlabSlot::<64>::from_raw(ptr), FreeSlabSlot::<64>::from_raw(ptr)) };
+        cache.recycle_slot(slot);
+        cache.recycle_slot(same_slot);
+    }
+
+    #[test]
+    fn recycling_a_slot_decrements_the_inuse_count_once() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let irq_disabled_guard = irq::disable_local();
+
+        let slot = cache.new_slot().unwrap();
+        assert_eq!(cache.nr_inuse_slots(), 1);
+        slot.recycle(&irq_disabled_guard);
+        // A double decrement would underflow the counter to `u16::MAX`.
+        assert_eq!(cache.nr_inuse_slots(), 0);
+        let inner = cache.inner.lock();
+        let slab = inner.slabs.iter().flatten().next().unwrap();
+        assert_eq!(slab.nr_used_slots(), 0);
+        slab.validate().unwrap();
+    }
+
+    // Returns the fewest TSC cycles that `NR_BENCH_OPS` pairs of allocation and recycling
+    // take on the cache, out of `NR_BENCH_RUNS` runs.
+    fn bench_alloc_and_recycle<L>(cache: &SinglePageSlabCache<64, (), L>) -> u64
+    where
+        L: CacheLock<SlabCacheInner<64, ()>>,
+    {
+        const NR_BENCH_RUNS: usize = 5;
+        const NR_BENCH_OPS: usize = 10_000;
+        (0..NR_BENCH_RUNS)
+            .map(|_| {
+                let start = ostd::arch::read_tsc();
+                for _ in 0..NR_BENCH_OPS {
+                    let slot = cache.new_slot().unwrap();
+                    cache.recycle_slot(slot);
+                }
+                ostd::arch::read_tsc() - start
+            })
+            .min()
+            .unwrap()
+    }
+
+    // The functional parts are covered by `unlocked_cache_serves_one_thread`.
+    #[test]
+    #[ignore = "a benchmark, whose result depends on the host"]
+    fn unlocked_cache_outperforms_locked_cache_in_one_thread() {
+        let _test_heap = TestHeap::lock(64);
+        static LOCKED_CACHE: SinglePageSlabCache<64, ()> = SinglePageSlabCache::new();
+        fn recycle_slot_locked(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            LOCKED_CACHE.recycle_slot(slot);
+        }
+        LOCKED_CACHE.init(recycle_slot_locked, ()).unwrap();
+        // SAFETY: The cache is only accessed by this test, from one thread.
+        static UNLOCKED_CACHE: SinglePageSlabCache<64, (), NoLock<SlabCacheInner<64, ()>>> =
+            unsafe { SinglePageSlabCache::new_unlocked() };
+        fn recycle_slot_unlocked(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            UNLOCKED_CACHE.recycle_slot(slot);
+        }
+        UNLOCKED_CACHE.init(recycle_slot_unlocked, ()).unwrap();
+
+        let locked_cycles = bench_alloc_and_recycle(&LOCKED_CACHE);
+        let unlocked_cycles = bench_alloc_and_recycle(&UNLOCKED_CACHE);
+        assert_eq!(LOCKED_CACHE.nr_inuse_slots(), 0);
+        assert_eq!(UNLOCKED_CACHE.nr_inuse_slots(), 0);
+        // Both variants share the slab logic, so the unlocked one
+        // only saves the cost of the lock.
+        assert!(
+            unlocked_cycles <= locked_cycles,
+            "unlocked: {unlocked_cycles} cycles, locked: {locked_cycles} cycles",
+        );
+    }
+
+    // Returns the fewest TSC cycles that `NR_BENCH_THREADS` threads take to
+    // each acquire the lock `NR_BENCH_OPS` times, out of `NR_BENCH_RUNS` runs.
+    fn bench_contended_lock<L>(lock: &L) -> u64
+    where
+        L: CacheLock<u64> + Sync,
+    {
+        const NR_BENCH_RUNS: usize = 3;
+        const NR_BENCH_THREADS: usize = 4;
+        const NR_BENCH_OPS: usize = 10_000;
+        let cycles = (0..NR_BENCH_RUNS)
+            .map(|_| {
+                let start = ostd::arch::read_tsc();
+                std::thread::scope(|scope| {
+                    for _ in 0..NR_BENCH_THREADS {
+                        scope.spawn(|| {
+                            for _ in 0..NR_BENCH_OPS {
+                                *lock.lock() += 1;
+                            }
+                        });
+                    }
+                });
+                ostd::arch::read_tsc() - start
+            })
+            .min()
+            .unwrap();
+        assert_eq!(*lock.lock(), (NR_BENCH_RUNS * NR_BENCH_THREADS * NR_BENCH_OPS) as u64);
+        cycles
+    }
+
+    // The functional parts are covered by `cache_locks_are_mutually_exclusive`.
+    #[test]
+    #[ignore = "a benchmark, whose result depends on the host"]
+    fn backoff_lock_spins_less_under_contention() {
+        let spin_cycles = bench_contended_lock(&SpinLock::new(0));
+        let backoff_cycles = bench_contended_lock(&BackoffLock::new(0));
+        // The waiters of the backoff lock leave the lock word to the holder,
+        // so the holder releases the lock sooner.
+        assert!(
+            backoff_cycles <= spin_cycles,
+            "backoff: {backoff_cycles} cycles, spin: {spin_cycles} cycles",
+        );
+    }
+
+    #[test]
+    fn unlocked_cache_serves_one_thread() {
+        let _test_heap = TestHeap::lock(64);
+        // SAFETY: The cache is only accessed by this test, from one thread.
+        static UNLOCKED_CACHE: SinglePageSlabCache<64, (), NoLock<SlabCacheInner<64, ()>>> =
+            unsafe { SinglePageSlabCache::new_unlocked() };
+        fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            UNLOCKED_CACHE.recycle_slot(slot);
+        }
+        UNLOCKED_CACHE.init(recycle_slot, ()).unwrap();
+
+        let slots: Vec<_> = (0..8).map(|_| UNLOCKED_CACHE.new_slot().unwrap()).collect();
+        assert_eq!(UNLOCKED_CACHE.nr_inuse_slots(), 8);
+        for slot in slots {
+            UNLOCKED_CACHE.recycle_slot(slot);
+        }
+        assert_eq!(UNLOCKED_CACHE.nr_inuse_slots(), 0);
+        let nr_corrupted = UNLOCKED_CACHE.health_check(&mut |slab_base, corruption| {
+            panic!("slab {slab_base:p} is corrupted: {corruption:?}")
+        });
+        assert_eq!(nr_corrupted, 0);
+
+        // Nothing ever holds a `NoLock`, so acquiring it never fails.
+        let lock = NoLock(UnsafeCell::new(0u64));
+        *lock.try_lock().unwrap() += 1;
+        *lock.lock() += 1;
+        assert_eq!(*lock.try_lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn cache_locks_are_mutually_exclusive() {
+        const NR_THREADS: usize = 4;
+        const NR_OPS: usize = 10_000;
+        fn increment_concurrently<L: CacheLock<u64> + Sync>(lock: &L) {
+            std::thread::scope(|scope| {
+                for _ in 0..NR_THREADS {
+                    scope.spawn(|| {
+                        for _ in 0..NR_OPS {
+                            // A separate load and store lose increments
+                            // unless the lock excludes the other threads.
+                            let mut guard = lock.lock();
+                            let value = *guard;
+                            core::hint::spin_loop();
+                            *guard = value + 1;
+                        }
+                    });
+                }
+            });
+            assert_eq!(*lock.lock(), (NR_THREADS * NR_OPS) as u64);
+        }
+
+        increment_concurrently(&SpinLock::new(0));
+        increment_concurrently(&BackoffLock::new(0));
+    }
+
+    #[test]
+    fn try_lock_fails_while_the_lock_is_held() {
+        fn check_try_lock<L: CacheLock<u64>>(lock: &L) {
+            let guard = lock.lock();
+            assert!(lock.try_lock().is_none());
+            drop(guard);
+            *lock.try_lock().unwrap() += 1;
+            assert_eq!(*lock.lock(), 1);
+        }
+
+        check_try_lock(&SpinLock::new(0));
+        check_try_lock(&BackoffLock::new(0));
+    }
+
+    #[test]
+    fn alloc_conforms_to_every_layout() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        // The zero-sized layouts are covered by the tests of the heap,
+        // as `alloc::alloc::alloc` forbids them.
+        let layouts = [
+            (1, 1),
+            (1, 4096),
+            (8, 64),
+            (24, 8),
+            (2048, 2048),
+            (2048, 4096),
+            (3000, 8),
+            (2 * PAGE_SIZE, PAGE_SIZE),
+        ]
+        .map(|(size, align)| Layout::from_size_align(size, align).unwrap());
+        for layout in layouts {
+            // SAFETY: The layout is not zero-sized.
+            let ptr = unsafe { alloc::alloc::alloc(layout) };
+            assert!(!ptr.is_null(), "{:?}", layout);
+            assert_eq!(ptr as usize % layout.align(), 0, "{:?}", layout);
+            assert!(heap::usable_size(ptr).unwrap() >= layout.size(), "{:?}", layout);
+            // SAFETY: The memory is valid for writes of `layout.size()` bytes.
+            unsafe { ptr.write_bytes(0xAB, layout.size()) };
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn owner_of_classifies_the_pointers_of_every_backend() {
+        let _test_heap = TestHeap::lock(64);
+        #[cfg(not(feature = "no_early_heap"))]
+        let early_boxed = Box::new([0u8; 100]);
+        inject_fresh_slab_caches!();
+        let slab_boxed = Box::new([0u8; 100]);
+        let large_boxed = Box::new([0u8; 3 * PAGE_SIZE]);
+        let mut not_on_heap = 0u8;
+
+        #[cfg(not(feature = "no_early_heap"))]
+        assert_eq!(heap::owner_of(Box::as_ptr(&early_boxed) as *mut u8), Some(heap::HeapRegion::EarlyHeap));
+        assert_eq!(
+            heap::owner_of(Box::as_ptr(&slab_boxed) as *mut u8),
+            Some(heap::HeapRegion::Slab { slot_size: 128 }),
+        );
+        assert_eq!(heap::owner_of(Box::as_ptr(&large_boxed) as *mut u8), Some(heap::HeapRegion::LargeObject));
+        assert_eq!(heap::owner_of(&raw mut not_on_heap), None);
+        assert_eq!(heap::owner_of(ptr::null_mut()), None);
+
+        drop((slab_boxed, large_boxed));
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn recycle_batch_returns_all_the_slots_of_a_list() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let initial_capacity = cache.capacity_hint();
+
+        let mut slots = FreeSlabSlotList::new();
+        for _ in 0..10 {
+            slots.push(cache.new_slot().unwrap());
+        }
+        assert_eq!(slots.len(), 10);
+        {
+            let inner = cache.inner.lock();
+            let slab = inner.slabs.iter().flatten().next().unwrap();
+            assert_eq!(slab.nr_used_slots(), 10);
+            assert_eq!(slab.nr_borrowed_slots(), 10);
+        }
+
+        cache.recycle_batch(slots);
+        let inner = cache.inner.lock();
+        let slab = inner.slabs.iter().flatten().next().unwrap();
+        assert_eq!(slab.nr_used_slots(), 0);
+        assert_eq!(slab.nr_borrowed_slots(), 0);
+        slab.validate().unwrap();
+        drop(inner);
+        assert_eq!(cache.capacity_hint(), initial_capacity);
+    }
+
+    #[test]
+    fn preallocated_slabs_serve_the_first_burst_on_the_fast_path() {
+        let _test_heap = TestHeap::lock(64);
+        static CACHE: SinglePageSlabCache<64, ()> = SinglePageSlabCache::new();
+        fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            CACHE.recycle_slot(slot);
+        }
+        CACHE.init_with_capacity(recycle_slot, (), 3).unwrap();
+        let nr_total_slots = {
+            let inner = CACHE.inner.lock();
+            assert_eq!(inner.slabs.iter().flatten().count(), 3);
+            inner.slabs.iter().flatten().next().unwrap().nr_total_slots()
+        };
+
+        let mut slots = FreeSlabSlotList::new();
+        for _ in 0..3 * nr_total_slots {
+            slots.push(CACHE.new_slot_fast().unwrap());
+        }
+        assert!(CACHE.new_slot_fast().is_none());
+        assert_eq!(CACHE.inner.lock().slabs.iter().flatten().count(), 3);
+
+        CACHE.recycle_batch(slots);
+        assert_eq!(CACHE.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    fn verify_no_leaks_flags_a_leaked_box() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        heap::verify_no_leaks().unwrap();
+
+        let leaked = Box::leak(Box::new([0u8; 100]));
+        let report = heap::verify_no_leaks().unwrap_err();
+        for size_class in SizeClass::ALL {
+            let expected = if size_class == SizeClass::B128 { 1 } else { 0 };
+            assert_eq!(report.nr_leaked_slots[size_class.index()], expected, "{:?}", size_class);
+        }
+        assert_eq!(report.nr_large_objects, 0);
+
+        // SAFETY: The box is leaked above and never used afterwards.
+        drop(unsafe { Box::from_raw(leaked) });
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn min_resident_slab_survives_repeated_alloc_and_free() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        heap::set_min_resident(SizeClass::B128, 1);
+        let slab_base_of = |boxed: &Box<[u8; 100]>| heap::slab_of(Box::as_ptr(boxed) as *mut u8).unwrap().base_ptr();
+
+        let slab_base = slab_base_of(&Box::new([0u8; 100]));
+        for _ in 0..10 {
+            let boxed = Box::new([0u8; 100]);
+            assert_eq!(slab_base_of(&boxed), slab_base);
+            drop(boxed);
+            heap::shrink_to_fit();
+        }
+        assert_eq!(heap::try_reclaim_class(128, 0).unwrap(), 0);
+
+        // Without the minimum, the unused slab is reclaimed.
+        heap::set_min_resident(SizeClass::B128, 0);
+        assert!(heap::try_reclaim_class(128, 0).unwrap() > 0);
+    }
+
+    #[test]
+    #[cfg(feature = "memcg")]
+    fn memcg_hook_rejects_allocations_over_the_limit() {
+        const LIMIT: usize = 4096;
+        static CHARGED: AtomicUsize = AtomicUsize::new(0);
+        fn charge(size: usize, _context: u32) -> bool {
+            CHARGED
+                .fetch_update(Relaxed, Relaxed, |charged| (charged + size <= LIMIT).then_some(charged + size))
+                .is_ok()
+        }
+        fn uncharge(size: usize, _context: u32) {
+            CHARGED.fetch_sub(size, Relaxed);
+        }
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        heap::set_memcg_hooks(heap::MemcgHooks {
+            current_context: || 0,
+            charge,
+            uncharge,
+        });
+
+        // Each object takes a 128-byte slot, which is the size charged.
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        let alloc = || unsafe { alloc::alloc::alloc(layout) };
+        // The pointers are kept in an array, as a `Vec` would be charged as well.
+        let ptrs: [*mut u8; LIMIT / 128] = core::array::from_fn(|_| alloc());
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+        assert_eq!(CHARGED.load(Relaxed), LIMIT);
+        assert!(alloc().is_null());
+
+        for ptr in ptrs {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+        assert_eq!(CHARGED.load(Relaxed), 0);
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn slot_is_recycled_through_the_fn_stored_in_its_slab() {
+        let _test_heap = TestHeap::lock(64);
+        static CACHE: SinglePageSlabCache<64, ()> = SinglePageSlabCache::new();
+        static NR_RECYCLED: AtomicUsize = AtomicUsize::new(0);
+        fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            NR_RECYCLED.fetch_add(1, Relaxed);
+            CACHE.recycle_slot(slot);
+        }
+        CACHE.init(recycle_slot, ()).unwrap();
+        // The slot is freed by the code that knows nothing about the cache.
+        fn free(slot: FreeSlabSlot<64>) {
+            slot.recycle(&irq::disable_local());
+        }
+
+        let slot = CACHE.new_slot().unwrap();
+        free(slot);
+        assert_eq!(NR_RECYCLED.load(Relaxed), 1);
+        assert_eq!(CACHE.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no_early_heap"))]
+    fn hook_that_allocates_does_not_reenter_the_slab_caches() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        static HOOK_BOXED: AtomicPtr<u64> = AtomicPtr::new(ptr::null_mut());
+        // The hook runs with the lock of the slab cache held,
+        // which a re-entrant allocation from the slab caches would deadlock on.
+        heap::set_slab_created_hook(|_slot_size, _base, _nr_pages| {
+            HOOK_BOXED.store(Box::into_raw(Box::new(42u64)), Relaxed);
+        });
+
+        // A slab of 2048-byte slots has a single slot, so the second object
+        // needs a new slab.
+        let boxes = [Box::new([0u8; 2048]), Box::new([0u8; 2048])];
+        let hook_boxed = HOOK_BOXED.load(Relaxed);
+        assert!(!hook_boxed.is_null());
+        assert_eq!(heap::owner_of(hook_boxed as *mut u8), Some(heap::HeapRegion::EarlyHeap));
+        // SAFETY: The box is leaked by the hook and never used afterwards.
+        assert_eq!(*unsafe { Box::from_raw(hook_boxed) }, 42);
+        drop(boxes);
+    }
+
+    #[test]
+    fn slab_with_borrowed_slots_is_never_reclaimed() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+
+        // The slot is parked on a cache-level free list, so no object uses it.
+        // But reclaiming its slab would leave the list with a dangling slot.
+        let mut parked_slots = FreeSlabSlotList::new();
+        parked_slots.push(cache.new_slot().unwrap());
+        assert_eq!(cache.nr_inuse_slots(), 0);
+        assert_eq!(cache.shrink(0), 0);
+
+        cache.recycle_batch(parked_slots);
+        assert_eq!(cache.shrink(0), 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no_early_heap"))]
+    fn early_heap_allocs_racing_with_injection_are_freed_correctly() {
+        const MAX_NR_ALLOCS: usize = 1000;
+        let test_heap = TestHeap::lock(64);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let is_allocating = AtomicBool::new(false);
+
+        let ptr_addrs = std::thread::scope(|scope| {
+            let allocator = scope.spawn(|| {
+                let _attached = test_heap.attach_current_thread();
+                // The vector is allocated up front, so it never
+                // allocates in the middle of the racing allocations.
+                let mut ptr_addrs = Vec::with_capacity(MAX_NR_ALLOCS);
+                // Allocates until an allocation lands in the slab caches.
+                while ptr_addrs.len() < MAX_NR_ALLOCS {
+                    // SAFETY: The layout is not zero-sized.
+                    let ptr = unsafe { alloc::alloc::alloc(layout) };
+                    assert!(!ptr.is_null());
+                    ptr_addrs.push(ptr as usize);
+                    is_allocating.store(true, Release);
+                    if heap::owner_of(ptr) != Some(heap::HeapRegion::EarlyHeap) {
+                        break;
+                    }
+                }
+                ptr_addrs
+            });
+            while !is_allocating.load(Acquire) {
+                core::hint::spin_loop();
+            }
+            inject_fresh_slab_caches!();
+            allocator.join().unwrap()
+        });
+
+        // Each allocation is served by one backend as a whole,
+        // and no allocation is served by the early heap after one is served by the slab caches.
+        let owners = ptr_addrs.iter().map(|&ptr_addr| heap::owner_of(ptr_addr as *mut u8));
+        let nr_early_ptrs = owners.clone().take_while(|owner| *owner == Some(heap::HeapRegion::EarlyHeap)).count();
+        assert!(nr_early_ptrs >= 1);
+        assert!(owners.skip(nr_early_ptrs).all(|owner| owner == Some(heap::HeapRegion::Slab { slot_size: 128 })));
+
+        // The early-heap pointers are never freed to the slab caches,
+        // which would corrupt their free lists.
+        for &ptr_addr in ptr_addrs.iter() {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr_addr as *mut u8, layout) };
+        }
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn arc_slice_in_a_slot_has_the_values_and_the_strong_count() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let cache = new_fresh_slab_cache!();
+
+        // The header of two counters and 8 elements of `u32` fit in 48 bytes,
+        // which is mapped to the slot size of 64.
+        let shared: Arc<[u32]> = cache.new_slot().unwrap().into_arc_slice((0..8).map(|i| i * 10));
+        assert_eq!(&*shared, &[0, 10, 20, 30, 40, 50, 60, 70]);
+        assert_eq!(Arc::strong_count(&shared), 1);
+        let cloned = shared.clone();
+        assert_eq!(Arc::strong_count(&shared), 2);
+        assert!(Arc::ptr_eq(&shared, &cloned));
+        drop(cloned);
+        assert_eq!(Arc::strong_count(&shared), 1);
+
+        // The last reference frees the slot back to its cache.
+        assert_eq!(cache.nr_inuse_slots(), 1);
+        drop(shared);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "the slice of 2 elements does not match the slot size of 64")]
+    fn arc_slice_of_another_class_is_rejected() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        // The 24 bytes would be freed as a slot of 32 bytes.
+        cache.new_slot().unwrap().into_arc_slice([1u32, 2].into_iter());
+    }
+
+    #[test]
+    fn freeing_a_misaligned_pointer_fires_the_bad_free_handler() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        static MISALIGNED_ADDR: AtomicUsize = AtomicUsize::new(0);
+        heap::set_bad_free_handler(|bad_free| match bad_free {
+            heap::BadFree::Misaligned { ptr, slot_size: 64 } => MISALIGNED_ADDR.store(ptr as usize, Relaxed),
+            bad_free => panic!("unexpected bad free: {:?}", bad_free),
+        });
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+        // An interior pointer, as if the pointer were corrupted.
+        let interior_ptr = ptr.wrapping_add(8);
+        let interior_layout = Layout::from_size_align(16, 8).unwrap();
+        // SAFETY: The deallocation is rejected before touching the memory.
+        unsafe { alloc::alloc::dealloc(interior_ptr, interior_layout) };
+        assert_eq!(MISALIGNED_ADDR.load(Relaxed), interior_ptr as usize);
+
+        // The rejected free neither reclaims nor corrupts the slot.
+        assert_eq!(heap::slab_of(ptr).unwrap().nr_inuse_slots(), 1);
+        // SAFETY: The memory is allocated with the layout above.
+        unsafe { alloc::alloc::dealloc(ptr, layout) };
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn capacity_hint_reflects_the_free_slots_of_a_slab() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+
+        // The cache starts with one slab, whose slots are all free.
+        let slot = cache.new_slot().unwrap();
+        let nr_total_slots = cache.inner.lock().slab_of(&slot).nr_total_slots();
+        assert_eq!(cache.capacity_hint(), nr_total_slots - 1);
+        let slots = [cache.new_slot().unwrap(), cache.new_slot().unwrap()];
+        assert_eq!(SlabSlotAlloc::capacity_hint(cache), nr_total_slots - 3);
+
+        for slot in slots.into_iter().chain([slot]) {
+            cache.recycle_slot(slot);
+        }
+        assert_eq!(cache.capacity_hint(), nr_total_slots);
+    }
+
+    #[test]
+    fn resident_capacity_sums_the_injected_caches() {
+        let _test_heap = TestHeap::lock(64);
+        assert_eq!(heap::resident_capacity(), 0);
+        inject_fresh_slab_caches!();
+        // Each cache starts with one slab of free slots.
+        assert!(heap::resident_capacity() >= heap::NR_SLAB_CLASSES);
+    }
+
+    #[test]
+    fn emergency_reserve_serves_when_the_heap_is_exhausted() {
+        const NR_PAGES: usize = 32;
+        let _test_heap = TestHeap::lock(NR_PAGES);
+        inject_fresh_slab_caches!();
+        heap::reserve_emergency(heap::NR_SLAB_CLASSES * 64).unwrap();
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        // The vector can hold more pointers than the arena has slots,
+        // so it never grows while the heap is being exhausted.
+        let mut ptrs = Vec::with_capacity(NR_PAGES * PAGE_SIZE / 64);
+        loop {
+            // SAFETY: The layout is not zero-sized.
+            let ptr = unsafe { alloc::alloc::alloc(layout) };
+            if ptr.is_null() {
+                break;
+            }
+            ptrs.push(ptr);
+        }
+        assert!(!ptrs.is_empty());
+
+        // The reserve is untouched by the main heap.
+        let emergency_ptr = heap::alloc_emergency(layout);
+        assert!(!emergency_ptr.is_null());
+        // SAFETY: The pointer refers to 64 bytes allocated above.
+        unsafe { emergency_ptr.write_bytes(0x5A, 64) };
+        // SAFETY: The layout is not zero-sized.
+        assert!(unsafe { alloc::alloc::alloc(layout) }.is_null());
+
+        // SAFETY: The pointer is allocated by `alloc_emergency` with the layout.
+        unsafe { heap::dealloc_emergency(emergency_ptr, layout) };
+        for ptr in ptrs {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn shrink_to_fit_returns_the_unused_slabs_of_all_classes() {
+        let test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        // Each class takes a few more slabs than the one it starts with.
+        let layouts = [64, 256, 1024].map(|size| Layout::from_size_align(size, 8).unwrap());
+        let mut allocs = Vec::with_capacity(layouts.len() * 3 * PAGE_SIZE / 64);
+        for layout in layouts {
+            for _ in 0..3 * PAGE_SIZE / layout.size() {
+                // SAFETY: The layout is not zero-sized.
+                let ptr = unsafe { alloc::alloc::alloc(layout) };
+                assert!(!ptr.is_null());
+                allocs.push((ptr, layout));
+            }
+        }
+        for (ptr, layout) in allocs.drain(..) {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+
+        let nr_free_pages = test_heap.nr_free_pages();
+        let nr_reclaimed_bytes = heap::shrink_to_fit();
+        assert!(nr_reclaimed_bytes > 0);
+        assert_eq!(nr_reclaimed_bytes % PAGE_SIZE, 0);
+        assert_eq!(test_heap.nr_free_pages(), nr_free_pages + nr_reclaimed_bytes / PAGE_SIZE);
+        // Nothing is left to reclaim.
+        assert_eq!(heap::shrink_to_fit(), 0);
+    }
+
+    #[test]
+    fn arc_in_a_slot_behaves_like_a_std_arc() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let cache = new_fresh_slab_cache!();
+
+        // In debug builds, the layout of `Arc` is checked against `ArcInner` here.
+        let shared = cache.new_slot().unwrap().into_arc([7u64; 4]);
+        assert_eq!(*shared, [7u64; 4]);
+        let weak = Arc::downgrade(&shared);
+        let cloned = shared.clone();
+        assert_eq!((Arc::strong_count(&shared), Arc::weak_count(&shared)), (2, 1));
+        drop((cloned, shared));
+        assert!(weak.upgrade().is_none());
+
+        // The memory is freed back to the slot with the last weak reference.
+        assert_eq!(cache.nr_inuse_slots(), 1);
+        drop(weak);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "mismatched layout (Layout { size: 1024, align: 8 }): the slot size is 64 bytes")]
+    fn dealloc_with_a_layout_of_another_class_reports_both_sizes() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+
+        // Trusting the layout would free the slot to the cache of 1024-byte slots.
+        let wrong_layout = Layout::from_size_align(1024, 8).unwrap();
+        // SAFETY: The memory is allocated above. The layout is wrong on purpose.
+        unsafe { alloc::alloc::dealloc(ptr, wrong_layout) };
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "mismatched layout (Layout { size: 16, align: 64 }): the slot size is 16 bytes")]
+    fn dealloc_with_an_alignment_larger_than_the_slot() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+
+        let wrong_layout = Layout::from_size_align(16, 64).unwrap();
+        // SAFETY: The memory is allocated above. The layout is wrong on purpose.
+        unsafe { alloc::alloc::dealloc(ptr, wrong_layout) };
+    }
+
+    #[test]
+    fn zeroed_slot_yields_an_all_zero_box() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let cache = new_fresh_slab_cache!();
+
+        // Dirty the slot, so that the zeros cannot be stale.
+        let slot_ptr = cache.new_slot().unwrap().into_raw();
+        // SAFETY: The slot is valid for 64 bytes and owned by the test.
+        unsafe { slot_ptr.write_bytes(0xFF, 64) };
+        // SAFETY: The slot is converted back from its raw pointer exactly once.
+        cache.recycle_slot(unsafe { FreeSlabSlot::from_raw(slot_ptr) });
+
+        let slot = cache.new_slot().unwrap();
+        assert_eq!(slot.as_ptr() as *mut u8, slot_ptr);
+        // SAFETY: All the bytes are written by `zeroed`.
+        let bytes: Box<[u8]> = unsafe { slot.zeroed().into_box_slice::<u8>(64).assume_init() };
+        assert_eq!(bytes.len(), 64);
+        assert!(bytes.iter().all(|&byte| byte == 0));
+        drop(bytes);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    fn pool_reuses_the_slots_of_released_objects() {
+        const NR_OBJS: usize = 8;
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let pool = heap::SlabPool::<[u64; 8], 64>::new(cache);
+
+        let acquire_all = |round: u64| -> [heap::PoolBox<'_, [u64; 8], 64>; NR_OBJS] {
+            core::array::from_fn(|i| pool.acquire([round * 100 + i as u64; 8]).unwrap())
+        };
+        let addrs_of = |objs: &[heap::PoolBox<'_, [u64; 8], 64>; NR_OBJS]| {
+            let mut addrs = objs.each_ref().map(|obj| &**obj as *const [u64; 8] as usize);
+            addrs.sort_unstable();
+            addrs
+        };
+
+        let objs = acquire_all(0);
+        let first_addrs = addrs_of(&objs);
+        drop(objs);
+        let nr_cache_inuse_slots = cache.nr_inuse_slots();
+        for round in 1..100 {
+            let objs = acquire_all(round);
+            assert!(objs.iter().enumerate().all(|(i, obj)| **obj == [round * 100 + i as u64; 8]));
+            assert_eq!(addrs_of(&objs), first_addrs);
+            drop(objs);
+        }
+        // The released slots never go back to the slab allocator.
+        assert_eq!(cache.nr_inuse_slots(), nr_cache_inuse_slots);
+    }
+
+    #[test]
+    #[cfg(feature = "heap_profile")]
+    fn profile_attributes_tagged_allocations() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        // The counters outlive the test, so the tags are unique to the test.
+        const HOT_TAG: &str = "profile_test_hot";
+        const COLD_TAG: &str = "profile_test_cold";
+        let small_layout = Layout::from_size_align(64, 8).unwrap();
+        let large_layout = Layout::from_size_align(512, 8).unwrap();
+
+        let mut allocs = Vec::with_capacity(8);
+        let tagged_allocs = [(small_layout, HOT_TAG, 5), (small_layout, COLD_TAG, 1), (large_layout, COLD_TAG, 2)];
+        for (layout, tag, nr_allocs) in tagged_allocs {
+            for _ in 0..nr_allocs {
+                // SAFETY: The layout is not zero-sized.
+                let ptr = unsafe { heap::alloc_tagged(layout, tag) };
+                assert!(!ptr.is_null());
+                allocs.push((ptr, layout));
+            }
+        }
+        assert_eq!(heap::heap_profile_count(HOT_TAG, SizeClass::B64), 5);
+        assert_eq!(heap::heap_profile_count(HOT_TAG, SizeClass::B512), 0);
+        assert_eq!(heap::heap_profile_count(COLD_TAG, SizeClass::B64), 1);
+        assert_eq!(heap::heap_profile_count(COLD_TAG, SizeClass::B512), 2);
+
+        // The untagged allocations of the other code may rank anywhere,
+        // but the hot tag ranks above the cold one.
+        let mut top = [("", 0); 64];
+        let nr_filled = heap::heap_profile_top(&mut top);
+        let rank_of = |tag| top[..nr_filled].iter().position(|&(top_tag, _)| top_tag == tag).unwrap();
+        assert_eq!(top[rank_of(HOT_TAG)], (HOT_TAG, 5));
+        assert_eq!(top[rank_of(COLD_TAG)], (COLD_TAG, 3));
+        assert!(rank_of(HOT_TAG) < rank_of(COLD_TAG));
+
+        for (ptr, layout) in allocs {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn reclaiming_a_class_leaves_the_other_classes_untouched() {
+        let test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        // Balloon two classes with a few slabs each.
+        let layouts = [64, 256].map(|size| Layout::from_size_align(size, 8).unwrap());
+        let mut allocs = Vec::with_capacity(layouts.len() * 3 * PAGE_SIZE / 64);
+        for layout in layouts {
+            for _ in 0..3 * PAGE_SIZE / layout.size() {
+                // SAFETY: The layout is not zero-sized.
+                let ptr = unsafe { alloc::alloc::alloc(layout) };
+                assert!(!ptr.is_null());
+                allocs.push((ptr, layout));
+            }
+        }
+        for (ptr, layout) in allocs.drain(..) {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+
+        let nr_free_pages = test_heap.nr_free_pages();
+        let nr_reclaimed_bytes = heap::try_reclaim_class(256, 0).unwrap();
+        assert!(nr_reclaimed_bytes > 0);
+        assert_eq!(test_heap.nr_free_pages(), nr_free_pages + nr_reclaimed_bytes / PAGE_SIZE);
+        assert_eq!(heap::try_reclaim_class(256, 0).unwrap(), 0);
+        // The unused slabs of the other class are still resident.
+        assert!(heap::try_reclaim_class(64, 0).unwrap() > 0);
+
+        assert!(matches!(heap::try_reclaim_class(100, 0), Err(Error::InvalidSlotSize(100))));
+    }
+
+    #[test]
+    fn health_check_reports_the_base_of_a_corrupted_slab() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+
+        // Fill the first slab, so that the cache allocates a second one.
+        let first_slot = cache.new_slot().unwrap();
+        let first_slab_base = cache.inner.lock().slab_of(&first_slot).base_ptr();
+        let mut slots = Vec::from([first_slot]);
+        let victim_slot = loop {
+            let slot = cache.new_slot().unwrap();
+            if cache.inner.lock().slab_of(&slot).base_ptr() != first_slab_base {
+                break slot;
+            }
+            slots.push(slot);
+        };
+        let victim_slab_base = cache.inner.lock().slab_of(&victim_slot).base_ptr();
+        assert_eq!(cache.health_check(&mut |_, _| unreachable!()), 0);
+
+        // The first word of a free slot links it to the next free slot.
+        // Linking the slot to itself creates a cycle in the free list of its slab.
+        let victim_ptr = victim_slot.into_raw() as *mut usize;
+        // SAFETY: The slot is converted back from its raw pointer exactly once.
+        cache.recycle_slot(unsafe { FreeSlabSlot::from_raw(victim_ptr as *mut u8) });
+        // SAFETY: The slot is free, so its link is not used by anyone but the slab.
+        // The link is restored below.
+        let old_link = unsafe { victim_ptr.replace(victim_ptr as usize) };
+
+        let mut corruptions = Vec::new();
+        let nr_corrupted = cache.health_check(&mut |slab_base, corruption| corruptions.push((slab_base, corruption)));
+        assert_eq!(nr_corrupted, 1);
+        assert_eq!(corruptions, [(victim_slab_base, SlabCorruption::FreeListCycle)]);
+
+        // SAFETY: Same as above.
+        unsafe { victim_ptr.write(old_link) };
+        assert_eq!(cache.health_check(&mut |_, _| unreachable!()), 0);
+        for slot in slots {
+            cache.recycle_slot(slot);
+        }
+    }
+
+    #[test]
+    fn allocations_over_the_maximum_are_rejected() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        heap::set_max_allocation(2 * PAGE_SIZE);
+
+        let over_layout = Layout::from_size_align(2 * PAGE_SIZE + 1, 8).unwrap();
+        // SAFETY: The layout is not zero-sized.
+        assert!(unsafe { alloc::alloc::alloc(over_layout) }.is_null());
+        assert!(heap::alloc_pages(3).is_none());
+
+        // The allocations up to the maximum succeed, of both the slabs and the large objects.
+        for size in [64, 2 * PAGE_SIZE] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            // SAFETY: The layout is not zero-sized.
+            let ptr = unsafe { alloc::alloc::alloc(layout) };
+            assert!(!ptr.is_null());
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+
+        // Lifting the maximum accepts the rejected allocation.
+        heap::set_max_allocation(usize::MAX);
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(over_layout) };
+        assert!(!ptr.is_null());
+        // SAFETY: The memory is allocated with the layout above.
+        unsafe { alloc::alloc::dealloc(ptr, over_layout) };
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn sizes_at_the_largest_slot_size_route_to_slabs_or_pages() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let cases = [
+            (2047, Some(heap::HeapRegion::Slab { slot_size: 2048 })),
+            (2048, Some(heap::HeapRegion::Slab { slot_size: 2048 })),
+            (2049, Some(heap::HeapRegion::LargeObject)),
+        ];
+        for (size, region) in cases {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            // SAFETY: The layout is not zero-sized.
+            let ptr = unsafe { alloc::alloc::alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(heap::owner_of(ptr), region, "size {size}");
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "memcg"))]
+    fn growable_alloc_grows_in_place() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let layout = Layout::from_size_align(40, 8).unwrap();
+
+        // SAFETY: The layout is not zero-sized.
+        let growable_ptr = unsafe { heap::alloc_growable(layout) };
+        assert!(!growable_ptr.is_null());
+        assert_eq!(heap::usable_size(growable_ptr), Some(128));
+        // SAFETY: The memory is valid for 40 bytes.
+        unsafe { growable_ptr.write_bytes(0x5A, 40) };
+        let mut old_layout = layout;
+        for new_size in [48, 64, 100, 128] {
+            // SAFETY: The memory is allocated with `old_layout`.
+            let new_ptr = unsafe { alloc::alloc::realloc(growable_ptr, old_layout, new_size) };
+            // No grow within the slot copies.
+            assert_eq!(new_ptr, growable_ptr, "size {new_size}");
+            old_layout = Layout::from_size_align(new_size, 8).unwrap();
+        }
+        // SAFETY: The first 40 bytes are written above.
+        assert!((0..40).all(|i| unsafe { *growable_ptr.add(i) } == 0x5A));
+        // SAFETY: The memory is reallocated to `old_layout`.
+        unsafe { alloc::alloc::dealloc(growable_ptr, old_layout) };
+
+        // Without the headroom, growing beyond the size class copies.
+        // SAFETY: The layout is not zero-sized.
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert_eq!(heap::usable_size(ptr), Some(64));
+        // SAFETY: The memory is allocated with the layout.
+        let new_ptr = unsafe { alloc::alloc::realloc(ptr, layout, 100) };
+        assert_ne!(new_ptr, ptr);
+        // SAFETY: The memory is reallocated to 100 bytes.
+        unsafe { alloc::alloc::dealloc(new_ptr, Layout::from_size_align(100, 8).unwrap()) };
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn heap_allocator_backs_boxes_and_vectors() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let boxed = Box::new_in([7u32; 20], heap::heap_allocator());
+        assert_eq!(*boxed, [7u32; 20]);
+        assert_eq!(
+            heap::owner_of(&*boxed as *const [u32; 20] as *mut u8),
+            Some(heap::HeapRegion::Slab { slot_size: 128 }),
+        );
+        drop(boxed);
+
+        // The vector grows across the size classes and into the large objects,
+        // then shrinks back to a slab slot.
+        let mut vec = Vec::with_capacity_in(4, heap::heap_allocator());
+        for i in 0..2000u32 {
+            vec.push(i);
+        }
+        assert_eq!(heap::owner_of(vec.as_mut_ptr() as *mut u8), Some(heap::HeapRegion::LargeObject));
+        vec.truncate(10);
+        vec.shrink_to_fit();
+        assert!(matches!(heap::owner_of(vec.as_mut_ptr() as *mut u8), Some(heap::HeapRegion::Slab { .. })));
+        assert!(vec.iter().copied().eq(0..10));
+        drop(vec);
+
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "heap_trace")]
+    fn live_allocations_are_walked_until_freed() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let layouts = [16, 100, 2048, 3 * PAGE_SIZE].map(|size| Layout::from_size_align(size, 8).unwrap());
+        // SAFETY: The layouts are not zero-sized.
+        let ptrs = layouts.map(|layout| unsafe { alloc::alloc::alloc(layout) });
+        assert!(ptrs.iter().all(|ptr| !ptr.is_null()));
+
+        // The other allocations of the process may be live, too.
+        let mut walked = [None; 4];
+        assert!(heap::for_each_live_allocation(|ptr, layout, call_site| {
+            if let Some(i) = ptrs.iter().position(|&our_ptr| our_ptr == ptr) {
+                assert!(walked[i].is_none(), "an allocation is walked twice");
+                walked[i] = Some((layout, call_site));
+            }
+        }));
+        for (walked, layout) in walked.into_iter().zip(layouts) {
+            assert_eq!(walked, Some((layout, heap::CallSite { tag: "" })));
+        }
+
+        for (ptr, layout) in ptrs.into_iter().zip(layouts) {
+            // SAFETY: The memory is allocated with the layout above.
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+        assert!(heap::for_each_live_allocation(|ptr, _, _| {
+            assert!(!ptrs.contains(&ptr), "a freed allocation is walked");
+        }));
+    }
+
+    #[test]
+    fn slab_of_reports_the_class_and_the_inuse_slots() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let boxed = Box::new([0u8; 100]);
+        let ptr = Box::as_ptr(&boxed) as *mut u8;
+
+        let slab = heap::slab_of(ptr).unwrap();
+        assert_eq!(slab.slot_size(), 128);
+        assert!(slab.nr_inuse_slots() >= 1);
+        assert!(slab.nr_inuse_slots() <= slab.nr_total_slots());
+        assert!((slab.base_ptr()..slab.base_ptr().wrapping_add(PAGE_SIZE)).contains(&(ptr as *const u8)));
+        assert!(slab.extension().downcast_ref::<()>().is_some());
+        let nr_inuse_slots = slab.nr_inuse_slots();
+        drop(boxed);
+        assert_eq!(heap::slab_of(ptr).unwrap().nr_inuse_slots(), nr_inuse_slots - 1);
+
+        // The pointers that are not slab slots have no slab.
+        let large_boxed = Box::new([0u8; 3 * PAGE_SIZE]);
+        assert!(heap::slab_of(Box::as_ptr(&large_boxed) as *mut u8).is_none());
+        let mut not_on_heap = 0u8;
+        assert!(heap::slab_of(&raw mut not_on_heap).is_none());
+    }
+
+    #[test]
+    fn class_allocations_pick_the_cache_of_the_class_and_balance() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let guard = irq::disable_local();
+
+        // The slot types are resolved at compile time.
+        let small_slots: [FreeSlabSlot<16>; 4] = core::array::from_fn(|_| heap::alloc_class::<16>(&guard).unwrap());
+        let large_slot: FreeSlabSlot<2048> = heap::alloc_class::<2048>(&guard).unwrap();
+        for slot in small_slots.iter() {
+            assert_eq!(heap::owner_of(slot.as_ptr() as *mut u8), Some(heap::HeapRegion::Slab { slot_size: 16 }));
+        }
+        assert_eq!(heap::owner_of(large_slot.as_ptr() as *mut u8), Some(heap::HeapRegion::Slab { slot_size: 2048 }));
+
+        let report = heap::verify_no_leaks().unwrap_err();
+        assert_eq!(report.nr_leaked_slots[SizeClass::B16.index()], 4);
+        assert_eq!(report.nr_leaked_slots[SizeClass::B2048.index()], 1);
+        assert_eq!(report.nr_leaked_slots.iter().sum::<usize>(), 5);
+
+        for slot in small_slots {
+            heap::dealloc_class(slot, &guard);
+        }
+        heap::dealloc_class(large_slot, &guard);
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn freeing_a_stack_pointer_fires_the_foreign_free_handler() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        static FOREIGN_ADDR: AtomicUsize = AtomicUsize::new(0);
+        heap::set_foreign_free_handler(|ptr, layout| {
+            assert_eq!(layout.size(), 64);
+            FOREIGN_ADDR.store(ptr as usize, Relaxed);
+        });
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let boxed = Box::new([0u8; 64]);
+        let mut on_stack = [0x5Au8; 64];
+        // SAFETY: The deallocation is rejected before touching the memory.
+        unsafe { alloc::alloc::dealloc(on_stack.as_mut_ptr(), layout) };
+        assert_eq!(FOREIGN_ADDR.load(Relaxed), on_stack.as_ptr() as usize);
+
+        // Neither the stack nor the heap is corrupted.
+        assert!(on_stack.iter().all(|&byte| byte == 0x5A));
+        assert!(boxed.iter().all(|&byte| byte == 0));
+        drop(boxed);
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "deallocation of a pointer that does not belong to the heap")]
+    fn freeing_a_stack_pointer_panics_by_default_in_debug_builds() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut on_stack = [0u8; 64];
+        // SAFETY: The deallocation is rejected before touching the memory.
+        unsafe { alloc::alloc::dealloc(on_stack.as_mut_ptr(), layout) };
+    }
+
+    #[test]
+    fn reset_objects_are_reused_without_being_dropped() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        static NR_DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Request {
+            nr_bytes: usize,
+            _payload: [u8; 40],
+        }
+        impl Drop for Request {
+            fn drop(&mut self) {
+                NR_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+        let pool = heap::SlabPool::<Request, 64>::new(cache);
+
+        let mut request = pool.acquire(Request { nr_bytes: 0, _payload: [0; 40] }).unwrap();
+        request.nr_bytes = 100;
+        let request_addr = &*request as *const Request as usize;
+        request.recycle_reset(|request| request.nr_bytes = 0);
+        assert_eq!(NR_DROPS.load(Relaxed), 0);
+
+        // The reset object is handed out again, as is.
+        let request = pool.acquire_with(|| unreachable!("the reset object is not reused")).unwrap();
+        assert_eq!(&*request as *const Request as usize, request_addr);
+        assert_eq!(request.nr_bytes, 0);
+        assert_eq!(NR_DROPS.load(Relaxed), 0);
+
+        // A regular release drops the object and keeps the slot in the pool.
+        drop(request);
+        assert_eq!(NR_DROPS.load(Relaxed), 1);
+        let request = pool.acquire_with(|| Request { nr_bytes: 1, _payload: [0; 40] }).unwrap();
+        assert_eq!(&*request as *const Request as usize, request_addr);
+        assert_eq!(request.nr_bytes, 1);
+    }
+
+    #[test]
+    fn backend_name_flips_on_injection() {
+        let _test_heap = TestHeap::lock(64);
+        assert!(!heap::is_injected());
+        assert_eq!(heap::current_backend_name(), "early_heap");
+
+        inject_fresh_slab_caches!();
+        assert!(heap::is_injected());
+        assert_eq!(heap::current_backend_name(), "slab_caches");
+    }
+
+    #[test]
+    fn alloc_at_least_returns_the_whole_slot() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let (ptr, usable_size) = heap::alloc_at_least(40, 8).unwrap();
+        assert_eq!(usable_size, 64);
+        // SAFETY: The memory is of `usable_size` bytes.
+        unsafe {
+            ptr.as_ptr().write_bytes(0x5A, usable_size);
+            assert!((0..usable_size).all(|i| *ptr.as_ptr().add(i) == 0x5A));
+        }
+        // SAFETY: The memory is allocated above, and it may be freed with the usable size.
+        unsafe { alloc::alloc::dealloc(ptr.as_ptr(), Layout::from_size_align(usable_size, 8).unwrap()) };
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn usable_size_of_an_object_is_its_slot_size() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        let boxed = Box::new([0u8; 40]);
+        assert_eq!(heap::usable_size(Box::as_ptr(&boxed) as *mut u8), Some(64));
+        drop(boxed);
+
+        let mut not_on_heap = 0u64;
+        assert_eq!(heap::usable_size(&mut not_on_heap as *mut u64 as *mut u8), None);
+    }
+
+    #[test]
+    fn pinned_box_in_a_slot_derefs_and_frees() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+        let cache = new_fresh_slab_cache!();
+        static NR_DROPS: AtomicUsize = AtomicUsize::new(0);
+        // A node that must not move, e.g., of an intrusive list.
+        struct Node {
+            value: u64,
+            _pinned: core::marker::PhantomPinned,
+        }
+        impl Drop for Node {
+            fn drop(&mut self) {
+                NR_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+
+        let slot = cache.new_slot().unwrap();
+        let slot_addr = slot.as_ptr() as usize;
+        let node = slot.into_pin_box(Node { value: 42, _pinned: core::marker::PhantomPinned });
+        assert_eq!(node.value, 42);
+        assert_eq!(&*node as *const Node as usize, slot_addr);
+        assert_eq!(cache.nr_inuse_slots(), 1);
+
+        drop(node);
+        assert_eq!(NR_DROPS.load(Relaxed), 1);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "no_early_heap")]
+    #[should_panic(expected = "but the early heap is disabled by the `no_early_heap` feature")]
+    fn allocation_before_injection_panics_without_the_early_heap() {
+        let _test_heap = TestHeap::lock(64);
+        assert!(!heap::is_injected());
+        let _boxed = Box::new(0u64);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_coloring")]
+    fn aligned_allocs_come_from_the_uncolored_slabs() {
+        let _test_heap = TestHeap::lock(64);
+        inject_fresh_slab_caches!();
+
+        // A weakly-aligned allocation of a colorable class may take a colored slab.
+        let weak_layout = Layout::from_size_align(256, 8).unwrap();
+        let weak_ptr = unsafe { alloc::alloc::alloc(weak_layout) };
+        assert!(heap::slab_of(weak_ptr).unwrap().is_colored());
+
+        // The allocations that are aligned to the slot size never do,
+        // even though the colored slab has free slots.
+        for layout in [Layout::from_size_align(64, 64).unwrap(), Layout::from_size_align(256, 256).unwrap()] {
+            let ptr = unsafe { alloc::alloc::alloc(layout) };
+            assert_eq!(ptr as usize % layout.align(), 0);
+            let slab = heap::slab_of(ptr).unwrap();
+            assert_eq!(slab.slot_size(), layout.size());
+            assert!(!slab.is_colored());
+            unsafe { alloc::alloc::dealloc(ptr, layout) };
+        }
+
+        // The slots of a colored slab are aligned to a cache line, but not to their size.
+        assert_eq!(weak_ptr as usize % heap::SLAB_COLOR_ALIGN, 0);
+        assert_ne!(weak_ptr as usize % 256, 0);
+        unsafe { alloc::alloc::dealloc(weak_ptr, weak_layout) };
+    }
+
+    #[test]
+    fn migrating_an_unused_slab_to_a_full_cache_keeps_the_slab() {
+        let test_heap = TestHeap::lock(64);
+        fn recycle_slot(_slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            unreachable!("no slot of the caches is allocated");
+        }
+        static SRC: SinglePageSlabCache<64, u32> = SinglePageSlabCache::new();
+        static DST: SinglePageSlabCache<64, u32> = SinglePageSlabCache::new();
+        SRC.init_with_capacity(recycle_slot, 0, 2).unwrap();
+        DST.init_with_capacity(recycle_slot, 1, MAX_NR_SLABS).unwrap();
+        let nr_free_pages = test_heap.nr_free_pages();
+
+        assert!(!SRC.migrate_unused_slab_to(&DST, 1, usize::MAX));
+        assert_eq!(SRC.inner.lock().slabs.iter().flatten().count(), 2);
+        assert_eq!(DST.inner.lock().slabs.iter().flatten().count(), MAX_NR_SLABS);
+        assert_eq!(test_heap.nr_free_pages(), nr_free_pages);
+
+        // Once `dst` has a vacant entry, the slab is moved and re-homed.
+        assert_eq!(DST.shrink(MAX_NR_SLABS - 1), 1);
+        assert!(SRC.migrate_unused_slab_to(&DST, 1, usize::MAX));
+        assert_eq!(SRC.inner.lock().slabs.iter().flatten().count(), 1);
+        let dst_inner = DST.inner.lock();
+        assert!(dst_inner.slabs.iter().flatten().all(|slab| *slab.slab_extension() == 1));
+    }
+
+    #[test]
+    fn dropping_a_pool_frees_its_objects_and_slots() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        static NR_DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Request {
+            _payload: [u8; 40],
+        }
+        impl Drop for Request {
+            fn drop(&mut self) {
+                NR_DROPS.fetch_add(1, Relaxed);
+            }
+        }
+        let pool = heap::SlabPool::<Request, 64>::new(cache);
+
+        // One object is reset in place, and the slot of the other one is parked.
+        let reset_request = pool.acquire(Request { _payload: [0; 40] }).unwrap();
+        let released_request = pool.acquire(Request { _payload: [0; 40] }).unwrap();
+        reset_request.recycle_reset(|_| {});
+        drop(released_request);
+        assert_eq!(NR_DROPS.load(Relaxed), 1);
+        assert_eq!(cache.nr_inuse_slots(), 2);
+
+        drop(pool);
+        assert_eq!(NR_DROPS.load(Relaxed), 2);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+    }
 }
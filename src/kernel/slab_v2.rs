@@ -4,13 +4,16 @@
 //! 1. Creating per-CPU slab caches to reduce lock contention.
 //! 2. Making use of the custom metadata associated with a `Slab`.
 
-pub fn init() {
-    let slab_cache_array = Box::new(SlabAllocators {
+pub fn init() -> Result<(), Error> {
+    // The caches allocate their first slabs right away.
+    // So the page allocator must be ready before any cache is initialized.
+    ostd::heap::check_page_allocator_ready()?;
+    let slab_allocators = SlabAllocators {
         size16: new_static_slab_cache!(16),
         // ...
         size2048: new_static_slab_cache!(2048),
-    }).leak();
-    ostd::heap::inject_slab_cache_array(slab_cache_array);
+    };
+    ostd::heap::inject_slab_allocators(slab_allocators)
 }
 
 macro_rules! new_static_slab_cache {
@@ -20,19 +23,20 @@ macro_rules! new_static_slab_cache {
 
             cpu_local! {
                 static LOCAL_SLAB_CACHES: SinglePageSlabCache<SLOT_SIZE, SlabExt> = SinglePageSlabCache::new();
+                static LOCAL_STATS: CpuStats = CpuStats::new();
             }
-            static SINGLETON: ScalableSlabCache<SLOT_SIZE> = ScalableSlabCache::new(&LOCAL_SLAB_CACHES);
+            static SINGLETON: ScalableSlabCache<SLOT_SIZE> = ScalableSlabCache::new(&LOCAL_SLAB_CACHES, &LOCAL_STATS);
 
             fn recycle_slot(
                 slot: FreeSlabSlot<SLOT_SIZE>,
                 extension: &dyn Any,
                 _pin_cpu_guard: &dyn PinCurrentCpu,
             ) {
-                let extension = extension.downcast_ref::<SlabExt>().unwrap();
-                SINGLETON.recycle_slot(slot, extension.owner_cpu);
+                let owner_cpu = owner_cpu_of::<SlabExt>(extension);
+                SINGLETON.recycle_slot(slot, owner_cpu);
             }
 
-            SINGLETON.init();
+            SINGLETON.init(recycle_slot)?;
             &SINGLETON as &'static dyn AnySlabCache<SLOT_SIZE> 
         }
     }
@@ -40,35 +44,191 @@ macro_rules! new_static_slab_cache {
 
 pub struct ScalableSlabCache<const SLOT_SIZE: usize> {
     local_slab_caches: &'static CpuLocal<SinglePageSlabCache<SLOT_SIZE, SlabExt>>,
+    local_stats: &'static CpuLocal<CpuStats>,
 }
 
+/// The statistics of the slab cache of a CPU.
+///
+/// The counters are attributed to the owner CPU of the slots,
+/// i.e., the CPU whose slab cache the slots are allocated from and recycled to.
+pub struct CpuStats {
+    alloc_count: AtomicU64,
+    recycle_count: AtomicU64,
+}
+
+impl CpuStats {
+    pub const fn new() -> Self {
+        Self {
+            alloc_count: AtomicU64::new(0),
+            recycle_count: AtomicU64::new(0),
+        }
+    }
+}
+
+// The maximum number of the slabs moved by one `ScalableSlabCache::rebalance`,
+// which bounds the time spent with the locks of the slab caches.
+const MAX_NR_REBALANCED_SLABS: usize = 4;
+
+#[derive(Clone)]
 struct SlabExt {
     owner_cpu: CpuId,
 }
 
+impl HasOwnerCpu for SlabExt {
+    fn owner_cpu(&self) -> CpuId {
+        self.owner_cpu
+    }
+}
+
 impl<const SLOT_SIZE: usize> ScalableSlabCache<SLOT_SIZE> {
     pub const fn new(
         local_slab_caches: &'static CpuLocal<SinglePageSlabCache<SLOT_SIZE, SlabExt>>,
+        local_stats: &'static CpuLocal<CpuStats>,
     ) -> Self {
         Self {
             local_slab_caches,
+            local_stats,
+        }
+    }
+
+    /// Visits the statistics of every CPU,
+    /// as `(cpu, alloc_count, recycle_count)`.
+    ///
+    /// Comparing the counters across CPUs reveals the CPUs that
+    /// allocate far more than others. The counters are read without
+    /// synchronization, so they are only a best-effort snapshot.
+    pub fn per_cpu_stats(&self, mut f: impl FnMut(CpuId, u64, u64)) {
+        for cpu_i in 0..cpu::num_cpus() {
+            let stats = self.local_stats.get_on_cpu(cpu_i);
+            f(cpu_i, stats.alloc_count.load(Relaxed), stats.recycle_count.load(Relaxed));
         }
     }
 
     #[doc(hidden)]
-    pub fn init(&self, slot_recycle_fn: SlotRecycleFn) {
+    pub fn init(&self, slot_recycle_fn: SlotRecycleFn) -> Result<(), Error> {
         for cpu_i in 0..cpu::num_cpus() {
             let local_slab_cache = self.local_slab_caches.get_on_cpu(cpu_i);
             let slab_extension = SlabExt {
                 owner_cpu: cpu_i,
             };
-            local_slab_cache.init(slab_extension, slot_recycle_fn);
+            local_slab_cache.init(slot_recycle_fn, slab_extension)?;
         }
+        Ok(())
+    }
+
+    /// Allocates a slot from the slab cache of the given CPU,
+    /// which may not be the current CPU.
+    ///
+    /// This is useful for placing an object on the CPU where it will be used,
+    /// e.g., the structures of a task that is migrated to another CPU.
+    /// The owner CPU of the slot is the given CPU,
+    /// so the slot will be recycled to that CPU.
+    pub fn alloc_on(
+        &self,
+        cpu: CpuId,
+        _pin_cpu_guard: &dyn PinCurrentCpu,
+    ) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        // The slab cache of a remote CPU is protected by its lock,
+        // just like when a slot is recycled to a remote CPU.
+        let target_slab_cache = self.local_slab_caches.get_on_cpu(cpu);
+        let free_slot = target_slab_cache.new_slot()?;
+        self.local_stats.get_on_cpu(cpu).alloc_count.fetch_add(1, Relaxed);
+        Some(free_slot)
+    }
+
+    /// Migrates the slabs of the slab cache of CPU `from` to that of CPU `to`,
+    /// e.g., when CPU `from` goes offline.
+    ///
+    /// The owner CPU of the migrated slabs becomes `to`,
+    /// so the slots in use are recycled to the slab cache of `to` afterwards.
+    /// Returns the number of the migrated slabs, which may be less than
+    /// the slabs of `from` if the slab cache of `to` is full.
+    ///
+    /// # Safety
+    ///
+    /// No slot of the slabs of CPU `from` may be recycled concurrently.
+    /// E.g., the other CPUs are stopped during the teardown of CPU hotplug.
+    pub unsafe fn migrate_cpu_cache(&self, from: CpuId, to: CpuId) -> usize {
+        if from == to {
+            return 0;
+        }
+        let src_slab_cache = self.local_slab_caches.get_on_cpu(from);
+        let dst_slab_cache = self.local_slab_caches.get_on_cpu(to);
+        let slab_extension = SlabExt {
+            owner_cpu: to,
+        };
+        // SAFETY: The caller guarantees that no slot is recycled concurrently.
+        // And the slab caches of all CPUs share the same `SlotRecycleFn`.
+        unsafe { src_slab_cache.migrate_slabs_to(dst_slab_cache, slab_extension) }
+    }
+
+    /// Evens out the free slots across the slab caches of the CPUs,
+    /// so that the fast paths of more CPUs hit their resident slabs.
+    ///
+    /// The unused slabs are moved from the CPUs of the most free slots
+    /// (see `capacity_hint`) to those of the fewest, towards the mean.
+    /// The owner CPU of a moved slab becomes the receiving CPU,
+    /// so the slots of the slab are recycled to it afterwards.
+    /// A slab is only moved if that narrows the spread between the two CPUs,
+    /// and at most `MAX_NR_REBALANCED_SLABS` slabs are moved per call.
+    ///
+    /// Returns the number of the moved slabs.
+    pub fn rebalance(&self, _pin_cpu_guard: &dyn PinCurrentCpu) -> usize {
+        let mut nr_moved = 0;
+        while nr_moved < MAX_NR_REBALANCED_SLABS {
+            // The numbers of free slots are a best-effort snapshot,
+            // as they are read from one CPU after another.
+            let mut richest = (0, 0);
+            let mut poorest = (0, usize::MAX);
+            for cpu_i in 0..cpu::num_cpus() {
+                let capacity = self.local_slab_caches.get_on_cpu(cpu_i).capacity_hint();
+                if capacity > richest.1 {
+                    richest = (cpu_i, capacity);
+                }
+                if capacity < poorest.1 {
+                    poorest = (cpu_i, capacity);
+                }
+            }
+            let ((richest, richest_capacity), (poorest, poorest_capacity)) = (richest, poorest);
+            // Moving a slab of up to half the spread never overshoots the mean.
+            let max_nr_slots = richest_capacity.saturating_sub(poorest_capacity) / 2;
+            let slab_extension = SlabExt {
+                owner_cpu: poorest,
+            };
+            let src_slab_cache = self.local_slab_caches.get_on_cpu(richest);
+            let dst_slab_cache = self.local_slab_caches.get_on_cpu(poorest);
+            if richest == poorest
+                || !src_slab_cache.migrate_unused_slab_to(dst_slab_cache, slab_extension, max_nr_slots)
+            {
+                break;
+            }
+            nr_moved += 1;
+        }
+        nr_moved
     }
 
     fn recycle_slot(&self, free_slot: FreeSlabSlot<SLOT_SIZE>, owner_cpu: CpuId) {
         let owner_slab_cache = self.per_cpu.get_on_cpu(owner_cpu);
         owner_slab_cache.recycle_slot(free_slot);
+        self.local_stats.get_on_cpu(owner_cpu).recycle_count.fetch_add(1, Relaxed);
+    }
+}
+
+impl<const SLOT_SIZE: usize> fmt::Debug for ScalableSlabCache<SLOT_SIZE> {
+    // The per-CPU caches are not waited for (see `SinglePageSlabCache::try_capacity_hint`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capacity_hint: Option<usize> = (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).try_capacity_hint())
+            .sum();
+        let mut debug_struct = f.debug_struct("ScalableSlabCache");
+        debug_struct
+            .field("slot_size", &SLOT_SIZE)
+            .field("nr_cpus", &cpu::num_cpus());
+        match capacity_hint {
+            Some(capacity_hint) => debug_struct.field("capacity_hint", &capacity_hint),
+            None => debug_struct.field("capacity_hint", &format_args!("<locked>")),
+        };
+        debug_struct.finish()
     }
 }
 
@@ -76,6 +236,233 @@ impl<const SLOT_SIZE: usize> SlabSlotAlloc<SLOT_SIZE> for ScalableSlabCache<SLOT
     fn alloc(&self, pin_cpu_guard: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
         let current_cpu = pin_cpu_guard.current_cpu();
         let local_slab_cache = self.per_cpu.get_on_cpu(current_cpu);
-        local_slab_cache.new_slot()
+        let free_slot = local_slab_cache.new_slot()?;
+        self.local_stats.get_with(pin_cpu_guard).alloc_count.fetch_add(1, Relaxed);
+        Some(free_slot)
+    }
+
+    fn alloc_aligned(&self, pin_cpu_guard: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        let current_cpu = pin_cpu_guard.current_cpu();
+        let local_slab_cache = self.per_cpu.get_on_cpu(current_cpu);
+        let free_slot = local_slab_cache.new_aligned_slot()?;
+        self.local_stats.get_with(pin_cpu_guard).alloc_count.fetch_add(1, Relaxed);
+        Some(free_slot)
+    }
+
+    fn try_alloc_fast(&self, pin_cpu_guard: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        let current_cpu = pin_cpu_guard.current_cpu();
+        let local_slab_cache = self.per_cpu.get_on_cpu(current_cpu);
+        let free_slot = local_slab_cache.new_slot_fast()?;
+        self.local_stats.get_with(pin_cpu_guard).alloc_count.fetch_add(1, Relaxed);
+        Some(free_slot)
+    }
+
+    fn shrink(&self, min_resident_slabs: usize) -> usize {
+        // Each per-CPU slab cache keeps its own floor of resident slabs.
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).shrink(min_resident_slabs))
+            .sum()
+    }
+
+    fn capacity_hint(&self) -> usize {
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).capacity_hint())
+            .sum()
+    }
+
+    fn health_check(&self, on_corruption: &mut dyn FnMut(*const u8, SlabCorruption)) -> usize {
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).health_check(on_corruption))
+            .sum()
+    }
+
+    fn nr_inuse_slots(&self) -> usize {
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).nr_inuse_slots())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ostd::heap::{self, TestHeap};
+
+    // Creates a `ScalableSlabCache` of 64-byte slots with its own per-CPU caches.
+    //
+    // Each expansion has its own static caches, so that a test never sees
+    // the slabs that a previous test has allocated from its arena.
+    macro_rules! new_fresh_slab_cache {
+        () => {{
+            cpu_local! {
+                static LOCAL_SLAB_CACHES: SinglePageSlabCache<64, SlabExt> = SinglePageSlabCache::new();
+                static LOCAL_STATS: CpuStats = CpuStats::new();
+            }
+            static CACHE: ScalableSlabCache<64> = ScalableSlabCache::new(&LOCAL_SLAB_CACHES, &LOCAL_STATS);
+
+            fn recycle_slot(slot: FreeSlabSlot<64>, extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+                CACHE.recycle_slot(slot, owner_cpu_of::<SlabExt>(extension));
+            }
+
+            CACHE.init(recycle_slot).unwrap();
+            &CACHE
+        }};
+    }
+
+    // Returns a CPU other than the current one.
+    fn another_cpu(pin_cpu_guard: &dyn PinCurrentCpu) -> CpuId {
+        let nr_cpus = cpu::num_cpus();
+        assert!(nr_cpus >= 2, "the test requires at least two CPUs");
+        (pin_cpu_guard.current_cpu() + 1) % nr_cpus
+    }
+
+    #[test]
+    fn reinject_another_version_after_reset() {
+        let test_heap = TestHeap::lock(64);
+        crate::slab_v1::init().unwrap();
+        let boxed = Box::new([0u8; 100]);
+        assert_eq!(heap::usable_size(Box::as_ptr(&boxed) as *mut u8), Some(128));
+        // The memory of the first version must not be freed after the reset.
+        mem::forget(boxed);
+
+        // SAFETY: No memory allocated before the reset is freed afterwards.
+        unsafe { test_heap.reset() };
+        assert!(!heap::is_injected());
+        heap::verify_no_leaks().unwrap();
+
+        init().unwrap();
+        assert!(heap::is_injected());
+        let boxed = Box::new([0u8; 100]);
+        assert_eq!(heap::usable_size(Box::as_ptr(&boxed) as *mut u8), Some(128));
+        assert_eq!(heap::verify_no_leaks().unwrap_err().nr_leaked_slots[SizeClass::B128.index()], 1);
+        drop(boxed);
+        heap::verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn owner_cpu_is_recovered_from_the_slab_extension() {
+        let _test_heap = TestHeap::lock(64);
+        static CACHE: SinglePageSlabCache<64, SlabExt> = SinglePageSlabCache::new();
+        fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            CACHE.recycle_slot(slot);
+        }
+        CACHE.init(recycle_slot, SlabExt { owner_cpu: 1 }).unwrap();
+
+        let slot = CACHE.new_slot().unwrap();
+        assert_eq!(owner_cpu_of::<SlabExt>(slot.slab_view().extension()), 1);
+        CACHE.recycle_slot(slot);
+    }
+
+    #[test]
+    #[should_panic(expected = "the slab extension is expected to be")]
+    fn owner_cpu_of_another_extension_type_panics() {
+        owner_cpu_of::<SlabExt>(&());
+    }
+
+    #[test]
+    fn alloc_on_another_cpu_is_owned_by_that_cpu() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let irq_disabled_guard = irq::disable_local();
+        let target_cpu = another_cpu(&irq_disabled_guard);
+
+        let slot = cache.alloc_on(target_cpu, &irq_disabled_guard).unwrap();
+        assert_eq!(owner_cpu_of::<SlabExt>(slot.slab_view().extension()), target_cpu);
+        assert_eq!(cache.local_slab_caches.get_on_cpu(target_cpu).nr_inuse_slots(), 1);
+
+        // The slot is recycled to the target CPU, not the current one.
+        slot.recycle(&irq_disabled_guard);
+        assert_eq!(cache.local_slab_caches.get_on_cpu(target_cpu).nr_inuse_slots(), 0);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    fn per_cpu_stats_count_uneven_allocations() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let irq_disabled_guard = irq::disable_local();
+        let current_cpu = irq_disabled_guard.current_cpu();
+        let other_cpu = another_cpu(&irq_disabled_guard);
+
+        let mut slots = Vec::new();
+        for _ in 0..3 {
+            slots.push(cache.alloc(&irq_disabled_guard).unwrap());
+        }
+        slots.push(cache.alloc_on(other_cpu, &irq_disabled_guard).unwrap());
+        for slot in slots.drain(..2) {
+            slot.recycle(&irq_disabled_guard);
+        }
+
+        let mut stats = Vec::new();
+        cache.per_cpu_stats(|cpu, alloc_count, recycle_count| stats.push((cpu, alloc_count, recycle_count)));
+        for (cpu, alloc_count, recycle_count) in stats {
+            let expected = match cpu {
+                cpu if cpu == current_cpu => (3, 2),
+                cpu if cpu == other_cpu => (1, 0),
+                _ => (0, 0),
+            };
+            assert_eq!((alloc_count, recycle_count), expected, "CPU {cpu}");
+        }
+
+        for slot in slots {
+            slot.recycle(&irq_disabled_guard);
+        }
+    }
+
+    #[test]
+    fn slabs_of_an_offline_cpu_stay_reachable_after_migration() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let irq_disabled_guard = irq::disable_local();
+        let online_cpu = irq_disabled_guard.current_cpu();
+        let offline_cpu = another_cpu(&irq_disabled_guard);
+        let live_slot = cache.alloc_on(offline_cpu, &irq_disabled_guard).unwrap();
+        let offline_capacity = cache.local_slab_caches.get_on_cpu(offline_cpu).capacity_hint();
+        let online_capacity = cache.local_slab_caches.get_on_cpu(online_cpu).capacity_hint();
+
+        // SAFETY: The slot of the offline CPU is only recycled after the migration.
+        assert_eq!(unsafe { cache.migrate_cpu_cache(offline_cpu, online_cpu) }, 1);
+        let offline_slab_cache = cache.local_slab_caches.get_on_cpu(offline_cpu);
+        let online_slab_cache = cache.local_slab_caches.get_on_cpu(online_cpu);
+        assert_eq!(offline_slab_cache.capacity_hint(), 0);
+        assert_eq!(online_slab_cache.capacity_hint(), online_capacity + offline_capacity);
+
+        // The live slot is now owned by the online CPU, and recycled to it.
+        assert_eq!(owner_cpu_of::<SlabExt>(live_slot.slab_view().extension()), online_cpu);
+        assert_eq!(online_slab_cache.nr_inuse_slots(), 1);
+        live_slot.recycle(&irq_disabled_guard);
+        assert_eq!(online_slab_cache.nr_inuse_slots(), 0);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+    }
+
+    #[test]
+    fn rebalance_reduces_the_spread_of_free_slots() {
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let irq_disabled_guard = irq::disable_local();
+        let rich_cpu = another_cpu(&irq_disabled_guard);
+        let spread = || {
+            let capacities = (0..cpu::num_cpus()).map(|cpu_i| cache.local_slab_caches.get_on_cpu(cpu_i).capacity_hint());
+            capacities.clone().max().unwrap() - capacities.min().unwrap()
+        };
+
+        // Grow the slab cache of one CPU to three slabs, which are all unused afterwards.
+        let nr_slots_per_slab = cache.local_slab_caches.get_on_cpu(rich_cpu).capacity_hint();
+        let slots: Vec<_> = (0..3 * nr_slots_per_slab)
+            .map(|_| cache.alloc_on(rich_cpu, &irq_disabled_guard).unwrap())
+            .collect();
+        for slot in slots {
+            slot.recycle(&irq_disabled_guard);
+        }
+        let spread_before = spread();
+        assert_eq!(spread_before, 2 * nr_slots_per_slab);
+
+        assert!(cache.rebalance(&irq_disabled_guard) >= 1);
+        assert!(spread() < spread_before);
+        assert_eq!(cache.nr_inuse_slots(), 0);
+        // Rebalancing again never widens the spread.
+        let spread_after = spread();
+        cache.rebalance(&irq_disabled_guard);
+        assert!(spread() <= spread_after);
     }
 }
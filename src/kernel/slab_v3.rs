@@ -5,13 +5,16 @@
 //! for the common scanario when objects are allocated and deallocated
 //! on the same CPU.
 
-pub fn init() {
-    let slab_cache_array = Box::new(SlabCacheArray {
+pub fn init() -> Result<(), Error> {
+    // The caches allocate their first slabs right away.
+    // So the page allocator must be ready before any cache is initialized.
+    ostd::heap::check_page_allocator_ready()?;
+    let slab_allocators = SlabAllocators {
         size16: new_static_slab_cache!(16),
         // ...
         size2048: new_static_slab_cache!(2048),
-    }).leak();
-    ostd::heap::inject_slab_cache_array(slab_cache_array);
+    };
+    ostd::heap::inject_slab_allocators(slab_allocators)
 }
 
 macro_rules! new_static_slab_cache {
@@ -21,23 +24,27 @@ macro_rules! new_static_slab_cache {
 
             cpu_local! {
                 static LOCAL_SLAB_CACHES: SinglePageSlabCache<SLOT_SIZE, SlabExt>= SinglePageSlabCache::new();
-                static LOCAL_FREE_LIST: RefCell<FreeSlabSlotList<SLOT_SIZE>> = RefCell::new(None);
+                static LOCAL_FREE_LIST: RefCell<FreeSlabSlotList<SLOT_SIZE>> = RefCell::new(FreeSlabSlotList::new());
+                static REMOTE_FREE_QUEUE: RemoteFreeQueue<SLOT_SIZE> = RemoteFreeQueue::new();
+                static ALLOCS_SINCE_TRIM: Cell<usize> = Cell::new(0);
             }
-            static SINGLETON: LocklessSlabCache = LocklessSlabCache::new(&LOCAL_SLAB_CACHES, &LOCAL_FREE_LIST);
+            static SINGLETON: LocklessSlabCache = LocklessSlabCache::new(
+                &LOCAL_SLAB_CACHES,
+                &LOCAL_FREE_LIST,
+                &REMOTE_FREE_QUEUE,
+                &ALLOCS_SINCE_TRIM,
+            );
 
             fn recycle_slot(
                 slot: FreeSlabSlot<SLOT_SIZE>,
                 extension: &dyn Any,
                 pin_cpu_guard: &dyn PinCurrentCpu,
             ) {
-                let owner_cpu = {
-                    let extension = extension.downcast_ref::<SlabExt>().unwrap();
-                    extension.owner_cpu
-                };
+                let owner_cpu = owner_cpu_of::<SlabExt>(extension);
                 SINGLETON.recycle_slot(slot, owner_cpu, pin_cpu_guard);
             }
 
-            SINGLETON.init();
+            SINGLETON.init()?;
             &SINGLETON as &'static dyn AnySlabCache<$slot_size> 
         }
     }
@@ -46,31 +53,98 @@ macro_rules! new_static_slab_cache {
 pub struct LocklessSlabCache<const SLOT_SIZE: usize> {
     local_slab_caches: &'static CpuLocal<SinglePageSlabCache<SLOT_SIZE>>,
     local_free_list: &'static CpuLocal<RefCell<FreeSlabSlotList>>,
+    // The slots freed on remote CPUs, which are drained by the owner CPU.
+    remote_free_queues: &'static CpuLocal<RemoteFreeQueue<SLOT_SIZE>>,
+    // The number of allocations from the local free list since it was last trimmed.
+    allocs_since_trim: &'static CpuLocal<Cell<usize>>,
 }
 
-struct SlabMeta {
+#[derive(Clone)]
+struct SlabExt {
     owner_cpu: CpuId,
 }
 
+impl HasOwnerCpu for SlabExt {
+    fn owner_cpu(&self) -> CpuId {
+        self.owner_cpu
+    }
+}
+
 impl<const SLOT_SIZE: usize> LocklessSlabCache<SLOT_SIZE> {
     pub const fn new(
         local_slab_caches: &'static CpuLocal<SinglePageSlabCache<SLOT_SIZE>>,
         local_free_list: &'static CpuLocal<RefCell<FreeSlabSlotList>>,
+        remote_free_queues: &'static CpuLocal<RemoteFreeQueue<SLOT_SIZE>>,
+        allocs_since_trim: &'static CpuLocal<Cell<usize>>,
     ) -> Self {
         Self {
             local_slab_caches,
             local_free_list,
+            remote_free_queues,
+            allocs_since_trim,
         }
     }
 
-    fn init(&self) {
+    /// The number of allocations from a local free list between two trims.
+    pub const TRIM_INTERVAL: usize = 1024;
+    /// The number of free slots that a local free list keeps after being trimmed.
+    pub const MAX_NR_LOCAL_FREE_SLOTS: usize = 256;
+
+    // Counts an allocation from the local free list, and trims the list
+    // every `TRIM_INTERVAL` allocations.
+    //
+    // Trimming spills the excess free slots back to their slabs, so that
+    // the slabs can be reclaimed. This bounds the memory parked on
+    // the local free lists without relying on a timer or an idle pass,
+    // and costs only a counter check on the fast path.
+    fn count_alloc_for_trim(
+        &self,
+        local_free_list: &mut FreeSlabSlotList<SLOT_SIZE>,
+        pin_cpu_guard: &dyn PinCurrentCpu,
+    ) {
+        let allocs_since_trim = self.allocs_since_trim.get_with(pin_cpu_guard);
+        let nr_allocs = allocs_since_trim.get() + 1;
+        if nr_allocs < Self::TRIM_INTERVAL {
+            allocs_since_trim.set(nr_allocs);
+            return;
+        }
+        allocs_since_trim.set(0);
+
+        let mut excess_slots = FreeSlabSlotList::new();
+        while local_free_list.len() > Self::MAX_NR_LOCAL_FREE_SLOTS {
+            excess_slots.push(local_free_list.pop().unwrap());
+        }
+        if excess_slots.is_empty() {
+            return;
+        }
+        // The slots on the local free list are all owned by the current CPU.
+        let current_cpu = pin_cpu_guard.current_cpu();
+        let local_slab_cache = self.local_slab_caches.get_on_cpu(current_cpu);
+        local_slab_cache.recycle_batch(excess_slots);
+    }
+
+    fn init(&self) -> Result<(), Error> {
         for cpu_i in 0..cpu::num_cpus() {
             let local_slab_cache = self.local_slab_caches.get_on_cpu(cpu_i);
             let slab_extension = SlabExt {
                 owner_cpu: cpu_i,
             };
-            local_slab_cache.init(slab_extension, slot_recycle_fn);
+            local_slab_cache.init(slot_recycle_fn, slab_extension)?;
         }
+        Ok(())
+    }
+
+    /// Drains the local free list of the current CPU
+    /// back to the local slab cache, in one lock acquisition.
+    ///
+    /// Draining the local free lists allows their slabs to be reclaimed.
+    pub fn drain_local(&self, pin_cpu_guard: &dyn PinCurrentCpu) {
+        let local_free_list_cell = self.free_list.get_with(pin_cpu_guard);
+        let local_free_list = mem::take(&mut *local_free_list_cell.borrow_mut());
+
+        let current_cpu = pin_cpu_guard.current_cpu();
+        let local_slab_cache = self.local_slab_caches.get_on_cpu(current_cpu);
+        local_slab_cache.recycle_batch(local_free_list);
     }
 
     fn recycle_slot(
@@ -87,10 +161,29 @@ impl<const SLOT_SIZE: usize> LocklessSlabCache<SLOT_SIZE> {
             return;
         }
         
-        // Slow path: returning the slot to the per-CPU slab cache
-        // on the remote, owner CPU.
-        let owner_slab_cache = self.local_slab_caches.get_on_cpu(owner_cpu);
-        owner_slab_cache.recycle_slot(free_slot);
+        // Slow path: pushing the slot into the remote free queue of the owner CPU,
+        // without taking the lock of the owner's slab cache.
+        // The owner CPU will drain the queue on its next allocation.
+        let owner_remote_free_queue = self.remote_free_queues.get_on_cpu(owner_cpu);
+        owner_remote_free_queue.push(free_slot);
+    }
+}
+
+impl<const SLOT_SIZE: usize> fmt::Debug for LocklessSlabCache<SLOT_SIZE> {
+    // The per-CPU caches are not waited for (see `SinglePageSlabCache::try_capacity_hint`).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let capacity_hint: Option<usize> = (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).try_capacity_hint())
+            .sum();
+        let mut debug_struct = f.debug_struct("LocklessSlabCache");
+        debug_struct
+            .field("slot_size", &SLOT_SIZE)
+            .field("nr_cpus", &cpu::num_cpus());
+        match capacity_hint {
+            Some(capacity_hint) => debug_struct.field("capacity_hint", &capacity_hint),
+            None => debug_struct.field("capacity_hint", &format_args!("<locked>")),
+        };
+        debug_struct.finish()
     }
 }
 
@@ -101,32 +194,184 @@ impl<const SLOT_SIZE: usize> SlabSlotAlloc<SLOT_SIZE> for LocklessSlabCache<SLOT
         let local_free_list = free_list_cell.borrow_mut();
         let free_slot = free_list.pop();
         if free_slot.is_some() {
+            self.count_alloc_for_trim(&mut local_free_list, pin_cpu_guard);
             return free_slot;
         }
 
-        // Slow path: try to get a free slot from the local, per-CPU slab ache
+        // Fast path: take back the slots freed on remote CPUs
         let current_cpu = pin_cpu_guard.current_cpu();
+        let remote_free_queue = self.remote_free_queues.get_on_cpu(current_cpu);
+        *local_free_list = remote_free_queue.take_all();
+        let free_slot = local_free_list.pop();
+        if free_slot.is_some() {
+            return free_slot;
+        }
+
+        // Slow path: try to get a free slot from the local, per-CPU slab ache
         let local_slab_cache = self.local_slab_cache.get_on_cpu(current_cpu);
         local_slab_cache.new_slot()
     }
-}
 
-pub struct FreeSlabSlotList<const SLOT_SIZE: usize> {
-    head: Option<FreeSlabSlot<SLOT_SIZE>>
+    fn try_alloc_fast(&self, pin_cpu_guard: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        // Only the local free list is the fast path.
+        let local_free_list_cell = self.free_list.get_with(pin_cpu_guard);
+        let mut local_free_list = local_free_list_cell.borrow_mut();
+        local_free_list.pop()
+    }
+
+    fn shrink(&self, min_resident_slabs: usize) -> usize {
+        // The slots parked on the local free lists and the remote free queues
+        // are borrowed from the slabs. A slab is only reclaimed if none of its slots
+        // are borrowed, so the slots are returned to their slabs first.
+        //
+        // The local free list of the current CPU is drained here. Those of the other CPUs
+        // can only be accessed by their own CPUs, which trim them periodically
+        // (see `count_alloc_for_trim`).
+        let irq_disabled_guard = irq::disable_local();
+        self.drain_local(&irq_disabled_guard);
+        // A remote free queue is taken atomically as a whole,
+        // so any CPU can drain it to the slab cache of its owner.
+        for cpu_i in 0..cpu::num_cpus() {
+            let remote_free_slots = self.remote_free_queues.get_on_cpu(cpu_i).take_all();
+            if !remote_free_slots.is_empty() {
+                self.local_slab_caches.get_on_cpu(cpu_i).recycle_batch(remote_free_slots);
+            }
+        }
+
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).shrink(min_resident_slabs))
+            .sum()
+    }
+
+    fn capacity_hint(&self) -> usize {
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).capacity_hint())
+            .sum()
+    }
+
+    fn health_check(&self, on_corruption: &mut dyn FnMut(*const u8, SlabCorruption)) -> usize {
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).health_check(on_corruption))
+            .sum()
+    }
+
+    fn nr_inuse_slots(&self) -> usize {
+        (0..cpu::num_cpus())
+            .map(|cpu_i| self.local_slab_caches.get_on_cpu(cpu_i).nr_inuse_slots())
+            .sum()
+    }
 }
 
-impl<const SLOT_SIZE: usize> FreeSlabSlotList<SLOT_SIZE> {
-    pub const fn new() -> Slef {
-        Selef {
-            head: None,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ostd::heap::TestHeap;
+
+    // Creates a `LocklessSlabCache` of 64-byte slots with its own per-CPU states.
+    //
+    // Each expansion has its own static states, so that a test never sees
+    // the slabs that a previous test has allocated from its arena.
+    macro_rules! new_fresh_slab_cache {
+        () => {{
+            cpu_local! {
+                static LOCAL_SLAB_CACHES: SinglePageSlabCache<64, SlabExt> = SinglePageSlabCache::new();
+                static LOCAL_FREE_LIST: RefCell<FreeSlabSlotList<64>> = RefCell::new(FreeSlabSlotList::new());
+                static REMOTE_FREE_QUEUE: RemoteFreeQueue<64> = RemoteFreeQueue::new();
+                static ALLOCS_SINCE_TRIM: Cell<usize> = Cell::new(0);
+            }
+            static CACHE: LocklessSlabCache<64> = LocklessSlabCache::new(
+                &LOCAL_SLAB_CACHES,
+                &LOCAL_FREE_LIST,
+                &REMOTE_FREE_QUEUE,
+                &ALLOCS_SINCE_TRIM,
+            );
+
+            fn recycle_slot(slot: FreeSlabSlot<64>, extension: &dyn Any, pin_cpu_guard: &dyn PinCurrentCpu) {
+                CACHE.recycle_slot(slot, owner_cpu_of::<SlabExt>(extension), pin_cpu_guard);
+            }
+
+            CACHE.init().unwrap();
+            &CACHE
+        }};
+    }
+
+    #[test]
+    fn owner_cpu_is_recovered_from_the_slab_extension() {
+        let _test_heap = TestHeap::lock(64);
+        static CACHE: SinglePageSlabCache<64, SlabExt> = SinglePageSlabCache::new();
+        fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            CACHE.recycle_slot(slot);
         }
+        CACHE.init(recycle_slot, SlabExt { owner_cpu: 1 }).unwrap();
+
+        let slot = CACHE.new_slot().unwrap();
+        assert_eq!(owner_cpu_of::<SlabExt>(slot.slab_view().extension()), 1);
+        CACHE.recycle_slot(slot);
     }
 
-    pub fn push(&mut self, slot: FreeSlabSlot<SLOT_SIZE>) {
-        todo!()
+    #[test]
+    #[should_panic(expected = "the slab extension is expected to be")]
+    fn owner_cpu_of_another_extension_type_panics() {
+        owner_cpu_of::<SlabExt>(&());
     }
 
-    pub fn pop(&mut self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
-        todo!()
+    #[test]
+    fn local_free_list_is_trimmed_at_the_threshold() {
+        type Cache = LocklessSlabCache<64>;
+        let _test_heap = TestHeap::lock(64);
+        let cache = new_fresh_slab_cache!();
+        let irq_disabled_guard = irq::disable_local();
+        let local_free_list_len = || cache.local_free_list.get_with(&irq_disabled_guard).borrow().len();
+
+        // Park more free slots than the trimmed list keeps on the local free list.
+        let nr_parked = Cache::MAX_NR_LOCAL_FREE_SLOTS + 64;
+        let slots: Vec<_> = (0..nr_parked).map(|_| cache.alloc(&irq_disabled_guard).unwrap()).collect();
+        for slot in slots {
+            slot.recycle(&irq_disabled_guard);
+        }
+        assert_eq!(local_free_list_len(), nr_parked);
+
+        // Each round trip pops a slot from the local free list and pushes it back.
+        for _ in 0..Cache::TRIM_INTERVAL - 1 {
+            cache.alloc(&irq_disabled_guard).unwrap().recycle(&irq_disabled_guard);
+        }
+        assert_eq!(local_free_list_len(), nr_parked);
+
+        // The allocation that reaches the threshold spills the excess slots to the slabs.
+        let slot = cache.alloc(&irq_disabled_guard).unwrap();
+        assert_eq!(local_free_list_len(), Cache::MAX_NR_LOCAL_FREE_SLOTS);
+
+        slot.recycle(&irq_disabled_guard);
+        cache.drain_local(&irq_disabled_guard);
+        assert_eq!(cache.nr_inuse_slots(), 0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn recycling_observes_the_owner_cpu_set_after_creation() {
+        // The owner CPU and the address of the last recycled slot.
+        static RECYCLED_OWNER_CPU: AtomicUsize = AtomicUsize::new(0);
+        static RECYCLED_ADDR: AtomicUsize = AtomicUsize::new(0);
+        fn recycle_slot(slot: FreeSlabSlot<64>, extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            RECYCLED_OWNER_CPU.store(owner_cpu_of::<SlabExt>(extension) as usize, Relaxed);
+            // The slot stays in use until the test recycles it to the slab.
+            RECYCLED_ADDR.store(slot.into_raw() as usize, Relaxed);
+        }
+
+        let _test_heap = TestHeap::lock(64);
+        let mut slab = Slab::<64, SlabExt>::alloc(recycle_slot, SlabExt { owner_cpu: 1 }).unwrap();
+        let slot = slab.new_slot().unwrap();
+        let slot_addr = slot.as_ptr() as usize;
+
+        // SAFETY: No slot of the slab is recycled concurrently.
+        unsafe { slab.set_extension(SlabExt { owner_cpu: 2 }) };
+        assert_eq!(owner_cpu_of::<SlabExt>(slot.slab_view().extension()), 2);
+
+        // The type-erased path of the free observes the new owner.
+        slot.recycle(&irq::disable_local());
+        assert_eq!(RECYCLED_OWNER_CPU.load(Relaxed), 2);
+        assert_eq!(RECYCLED_ADDR.load(Relaxed), slot_addr);
+
+        // SAFETY: The slot is converted back from its raw pointer exactly once.
+        slab.recycle_slot(unsafe { FreeSlabSlot::from_raw(slot_addr as *mut u8) });
+    }
+}
@@ -1,3 +1,5 @@
+use super::{PageAligned, PAGE_SIZE};
+
 /// A heap allocator for the early heap.
 pub struct EarlyHeapAlloc {
     free_list_16: *mut LinkedFreeSlot,
@@ -53,6 +55,7 @@ struct LinkedFreeSlot {
 }
 
 /// Returns whether a pointer belongs to the early heap.
+#[cfg(not(feature = "no_early_heap"))]
 pub fn contains_ptr(ptr: *mut u8) -> bool {
     let heap_page_start = &EARLY_HEAP_PAGES.0 as usize;
     let heap_page_end = heap_page_start + NR_EARLY_HEAP_PAEGS * PAGE_SIZE;
@@ -60,12 +63,55 @@ pub fn contains_ptr(ptr: *mut u8) -> bool {
     heap_page_start <= ptr_addr && ptr_addr < heap_page_end
 }
 
-// The static memory region for the early heap.
+/// Returns whether a pointer belongs to the early heap,
+/// which never holds as the early heap is disabled.
+#[cfg(feature = "no_early_heap")]
+pub fn contains_ptr(_ptr: *mut u8) -> bool {
+    false
+}
+
+/// Forces all the pages of the early heap to be resident,
+/// so that the allocations from the early heap never fault on them.
+///
+/// Touching the pages lazily may cause page-fault jitter during critical early boot,
+/// so this function can be called before the first allocation.
+/// It does not allocate memory and is idempotent:
+/// only the first call touches the pages, and the concurrent calls
+/// wait for it, so that every call returns with all the pages resident.
+///
+/// With the `no_early_heap` feature, there are no pages to touch.
+#[cfg(not(feature = "no_early_heap"))]
+pub fn prefault() {
+    static PREFAULTED: Once<()> = Once::new();
+    PREFAULTED.call_once(|| {
+        let heap_page_start = &raw mut EARLY_HEAP_PAGES as *mut u8;
+        for page_idx in 0..NR_EARLY_HEAP_PAEGS {
+            // SAFETY: The byte lies within the early heap pages. Adding zero atomically
+            // writes the page without changing the contents, which may already be
+            // in use by the allocations from the early heap on other CPUs.
+            let byte = unsafe { AtomicU8::from_ptr(heap_page_start.add(page_idx * PAGE_SIZE)) };
+            byte.fetch_add(0, Relaxed);
+        }
+    });
+}
+
+/// Forces all the pages of the early heap to be resident,
+/// which is a no-op as the early heap is disabled.
+#[cfg(feature = "no_early_heap")]
+pub fn prefault() {}
+
+// The static memory region for the early heap,
+// which is left out of the binary with the `no_early_heap` feature.
 
-const NR_EARLY_HEAP_PAEGS: usize = 256;    
+// The size of the early heap is fixed in bytes (1MB),
+// regardless of the page size of the target.
+const NR_EARLY_HEAP_PAEGS: usize = (1024 * 1024) / PAGE_SIZE;
 
-#[repr(align(4096))]
-struct EarlyHeapPages([[u8; PAGE_SIZE]; NR_EARLY_HEAP_PAEGS]);
+// The pages are aligned by the zero-sized `PageAligned`,
+// which follows the same page size configuration as `PAGE_SIZE`.
+#[repr(C)]
+struct EarlyHeapPages([[u8; PAGE_SIZE]; NR_EARLY_HEAP_PAEGS], [PageAligned; 0]);
+static_assert!(mem::align_of::<EarlyHeapPages>() == PAGE_SIZE);
 
 impl EarlyHeapPages {
     pub fn new() -> Self {
@@ -73,6 +119,34 @@ impl EarlyHeapPages {
     }
 }
 
+#[cfg(not(feature = "no_early_heap"))]
 static mut EARLY_HEAP_PAGES: EarlyHeapPages = EarlyHeapPages::new();
 
-static NR_USED_PAGES: AtomicU16 = AtomicU16::new();
\ No newline at end of file
+#[cfg(not(feature = "no_early_heap"))]
+static NR_USED_PAGES: AtomicU16 = AtomicU16::new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::TestHeap;
+
+    #[test]
+    #[cfg(not(feature = "no_early_heap"))]
+    fn prefaulted_pages_are_readable_and_writable() {
+        // The heap is locked, so no other test allocates from the early heap meanwhile.
+        let _test_heap = TestHeap::lock(16);
+        prefault();
+        // The call is idempotent.
+        prefault();
+
+        let heap_page_start = &raw mut EARLY_HEAP_PAGES as *mut u8;
+        for page_idx in 0..NR_EARLY_HEAP_PAEGS {
+            // SAFETY: The last byte of the page lies within the early heap pages.
+            // The byte is changed and restored atomically, as it may be in use.
+            let byte = unsafe { AtomicU8::from_ptr(heap_page_start.add((page_idx + 1) * PAGE_SIZE - 1)) };
+            let value = byte.fetch_add(1, Relaxed);
+            assert_eq!(byte.fetch_sub(1, Relaxed), value.wrapping_add(1));
+            assert!(contains_ptr(byte.as_ptr()));
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! The emergency reserve of the heap.
+//!
+//! The emergency reserve is a small pool of slab slots that are set aside
+//! for the critical code paths that must make forward progress
+//! even when the heap is exhausted, e.g., the OOM killer.
+//! The slots are only allocated by [`alloc_emergency`],
+//! never by the main allocation path of the heap.
+//!
+//! The reserve of each size class is a free list seeded from dedicated slabs.
+//! The dedicated slabs are never reclaimed, and their slots are always
+//! recycled to the reserve.
+
+struct EmergencyReserve<const SLOT_SIZE: usize> {
+    free_slots: SpinLock<FreeSlabSlotList<SLOT_SIZE>>,
+}
+
+impl<const SLOT_SIZE: usize> EmergencyReserve<SLOT_SIZE> {
+    const fn new() -> Self {
+        Self {
+            free_slots: SpinLock::new(FreeSlabSlotList::new()),
+        }
+    }
+
+    // Sets aside at least `nr_slots` slots from dedicated slabs.
+    fn reserve(&'static self, nr_slots: usize) -> Result<(), Error> {
+        let mut nr_reserved = 0;
+        while nr_reserved < nr_slots {
+            // The extension of a dedicated slab is the reserve itself,
+            // to which the slots are recycled.
+            let mut slab = Slab::<SLOT_SIZE, &'static Self>::alloc(recycle_slot::<SLOT_SIZE>, self)
+                .ok_or(Error::NoMemory)?;
+            let mut free_slots = self.free_slots.lock();
+            while let Some(free_slot) = slab.new_slot() {
+                free_slots.push(free_slot);
+                nr_reserved += 1;
+            }
+            // The dedicated slabs are owned by the reserve forever.
+            mem::forget(slab);
+        }
+        Ok(())
+    }
+
+    fn alloc(&self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.free_slots.lock().pop()
+    }
+
+    fn recycle(&self, free_slot: FreeSlabSlot<SLOT_SIZE>) {
+        self.free_slots.lock().push(free_slot);
+    }
+
+    // Empties the reserve, for `reset_for_test`.
+    //
+    // The slots belong to the dedicated slabs of the previous page source,
+    // so they are forgotten instead of being recycled.
+    #[cfg(test)]
+    fn reset_for_test(&self) {
+        mem::forget(mem::replace(&mut *self.free_slots.lock(), FreeSlabSlotList::new()));
+    }
+}
+
+// Recycles a slot of a dedicated slab to the reserve that the slab belongs to.
+fn recycle_slot<const SLOT_SIZE: usize>(
+    slot: FreeSlabSlot<SLOT_SIZE>,
+    extension: &dyn Any,
+    _pin_cpu_guard: &dyn PinCurrentCpu,
+) {
+    let reserve = extension.downcast_ref::<&'static EmergencyReserve<SLOT_SIZE>>().unwrap();
+    reserve.recycle(slot);
+}
+
+struct EmergencyReserves {
+    size16: EmergencyReserve<{ SizeClass::B16.bytes() }>,
+    size32: EmergencyReserve<{ SizeClass::B32.bytes() }>,
+    // ...
+    size2048: EmergencyReserve<{ SizeClass::B2048.bytes() }>,
+}
+
+static EMERGENCY_RESERVES: EmergencyReserves = EmergencyReserves {
+    size16: EmergencyReserve::new(),
+    size32: EmergencyReserve::new(),
+    // ...
+    size2048: EmergencyReserve::new(),
+};
+
+// Empties the emergency reserves of all size classes,
+// for `HeapAlloc::reset_for_test`.
+//
+// # Safety
+//
+// No one may use the emergency reserves concurrently.
+#[cfg(test)]
+pub(super) unsafe fn reset_for_test() {
+    EMERGENCY_RESERVES.size16.reset_for_test();
+    EMERGENCY_RESERVES.size32.reset_for_test();
+    // ...
+    EMERGENCY_RESERVES.size2048.reset_for_test();
+}
+
+/// Sets aside about `bytes` bytes of memory as the emergency reserve,
+/// which is split evenly among the size classes.
+///
+/// Each size class reserves at least one slot.
+/// The reserve can be enlarged by calling this function again.
+pub fn reserve_emergency(bytes: usize) -> Result<(), Error> {
+    let bytes_per_class = bytes / NR_SLAB_CLASSES;
+    let nr_slots_of = |size_class: SizeClass| (bytes_per_class / size_class.bytes()).max(1);
+
+    let _irq_disabled_guard = irq::disable_local();
+    EMERGENCY_RESERVES.size16.reserve(nr_slots_of(SizeClass::B16))?;
+    EMERGENCY_RESERVES.size32.reserve(nr_slots_of(SizeClass::B32))?;
+    // ...
+    EMERGENCY_RESERVES.size2048.reserve(nr_slots_of(SizeClass::B2048))?;
+    Ok(())
+}
+
+/// Allocates memory from the emergency reserve.
+///
+/// Returns a null pointer if the reserve of the size class is exhausted
+/// or the layout cannot be served by slab slots.
+///
+/// The memory is not charged to memory control groups,
+/// and must be freed with [`dealloc_emergency`].
+pub fn alloc_emergency(layout: Layout) -> *mut u8 {
+    if layout.size() == 0 || layout.size() > slab::MAX_SLAB_SLOT_SIZE {
+        return ptr::null_mut();
+    }
+    let size_class = determine_slot_size(layout.size());
+    if layout.align() > size_class.bytes() {
+        return ptr::null_mut();
+    }
+
+    let _irq_disabled_guard = irq::disable_local();
+    let slot_ptr = match size_class {
+        SizeClass::B16 => EMERGENCY_RESERVES.size16.alloc().map(FreeSlabSlot::into_raw),
+        // ...
+        SizeClass::B2048 => EMERGENCY_RESERVES.size2048.alloc().map(FreeSlabSlot::into_raw),
+    };
+    slot_ptr.unwrap_or(ptr::null_mut())
+}
+
+/// Frees memory that has been allocated by [`alloc_emergency`].
+///
+/// # Safety
+///
+/// The pointer must have been returned by `alloc_emergency` with the same layout,
+/// and must not be used afterwards.
+pub unsafe fn dealloc_emergency(ptr: *mut u8, layout: Layout) {
+    let size_class = determine_slot_size(layout.size());
+
+    // The slots of the dedicated slabs are recycled to the emergency reserve.
+    let irq_disabled_guard = irq::disable_local();
+    match size_class {
+        SizeClass::B16 => {
+            // SAFETY: The pointer refers to a slot of 16 bytes in use.
+            let free_slab_slot = unsafe { FreeSlabSlot::<16>::new(ptr) };
+            free_slab_slot.recycle(&irq_disabled_guard);
+        }
+        // ...
+        SizeClass::B2048 => {
+            // SAFETY: The pointer refers to a slot of 2048 bytes in use.
+            let free_slab_slot = unsafe { FreeSlabSlot::<2048>::new(ptr) };
+            free_slab_slot.recycle(&irq_disabled_guard);
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! The large objects of the heap.
+//!
+//! A large object is too large or too strongly aligned to fit in a slab slot,
+//! so it is allocated directly from the page allocator.
+//! Every page of a large object is tagged with `PageUsage::LargeObject`
+//! in the page metadata maintained by the page allocator,
+//! which allows classifying a pointer as a large object
+//! and recovering the number of pages of the object.
+//!
+//! The page metadata is indexed by the page frame, so that
+//! recording a large object never allocates memory from the heap,
+//! and looking up a pointer takes constant time without any lock.
+//! Thus the number of large objects is only bounded by the pages.
+//!
+//! A large object may be guarded by an unmapped page on each side
+//! (see [`alloc_guarded`](super::alloc_guarded)), so that an overflow
+//! or underflow of the object faults instead of corrupting other memory.
+
+#[derive(Clone, Copy, Debug)]
+struct LargeObject {
+    addr: usize,
+    nr_pages: usize,
+    // Whether the object is surrounded by two guard pages,
+    // which are allocated with the object but not a part of it.
+    is_guarded: bool,
+}
+
+impl LargeObject {
+    // Returns the start address and the number of pages of the whole span,
+    // including the guard pages, if any.
+    fn span(&self) -> (usize, usize) {
+        if self.is_guarded {
+            (self.addr - PAGE_SIZE, self.nr_pages + 2)
+        } else {
+            (self.addr, self.nr_pages)
+        }
+    }
+}
+
+// The number of the live large objects.
+static NR_LARGE_OBJECTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocates a large object from the page allocator.
+///
+/// Returns a null pointer if the page allocator is out of memory
+/// or the alignment is larger than `PAGE_SIZE`.
+pub(super) fn alloc(layout: Layout) -> *mut u8 {
+    alloc_object(layout, false)
+}
+
+/// Allocates a large object with an unmapped guard page on each side.
+///
+/// Returns a null pointer in the same cases as `alloc`.
+pub(super) fn alloc_guarded(layout: Layout) -> *mut u8 {
+    alloc_object(layout, true)
+}
+
+fn alloc_object(layout: Layout, is_guarded: bool) -> *mut u8 {
+    // The page allocator only guarantees page alignment.
+    if layout.align() > PAGE_SIZE {
+        return ptr::null_mut();
+    }
+
+    let nr_pages = layout.size().div_ceil(PAGE_SIZE);
+    let nr_span_pages = if is_guarded { nr_pages + 2 } else { nr_pages };
+    let Some(pages) = page_alloc::alloc_pages(nr_span_pages) else {
+        return ptr::null_mut();
+    };
+
+    let mut addr = pages.as_ptr() as usize;
+    if is_guarded {
+        let last_guard_page = (addr + (nr_pages + 1) * PAGE_SIZE) as *mut u8;
+        // SAFETY: The guard pages are a part of the span just allocated,
+        // which are not used by anyone else.
+        unsafe {
+            page_alloc::unmap_pages(pages.as_ptr(), 1);
+            page_alloc::unmap_pages(last_guard_page, 1);
+        }
+        addr += PAGE_SIZE;
+    }
+    // Only the pages of the object are tagged, not the guard pages.
+    page_alloc::set_page_usage(
+        addr as *const u8,
+        nr_pages,
+        PageUsage::LargeObject { addr, nr_pages, is_guarded },
+    );
+    NR_LARGE_OBJECTS.fetch_add(1, Relaxed);
+    addr as *mut u8
+}
+
+/// Deallocates a large object.
+///
+/// # Safety
+///
+/// The pointer must have been returned by `alloc` and not deallocated yet.
+pub(super) unsafe fn dealloc(ptr: *mut u8) {
+    let object = object_of(ptr)
+        .filter(|object| object.addr == ptr as usize)
+        .expect("the pointer must refer to a large object");
+    NR_LARGE_OBJECTS.fetch_sub(1, Relaxed);
+    let (span_addr, nr_span_pages) = object.span();
+    let span_ptr = span_addr as *mut u8;
+    // SAFETY: The pages have been allocated by `alloc` or `alloc_guarded`
+    // and are no longer used. The guard pages must be mapped again
+    // before they are returned to the page allocator,
+    // which clears the tags of the pages.
+    unsafe {
+        if object.is_guarded {
+            let last_guard_page = span_ptr.add((nr_span_pages - 1) * PAGE_SIZE);
+            page_alloc::remap_pages(span_ptr, 1);
+            page_alloc::remap_pages(last_guard_page, 1);
+        }
+        page_alloc::dealloc_pages(span_ptr, nr_span_pages);
+    }
+}
+
+/// Returns the number of the live large objects.
+pub(super) fn nr_objects() -> usize {
+    NR_LARGE_OBJECTS.load(Relaxed)
+}
+
+// Resets the number of the live large objects, for `HeapAlloc::reset_for_test`.
+//
+// The large objects of the previous test are leaked along with their page source,
+// whose page metadata is discarded by the page allocator.
+#[cfg(test)]
+pub(super) fn reset_for_test() {
+    NR_LARGE_OBJECTS.store(0, Relaxed);
+}
+
+/// Returns whether the pointer refers to a byte of a large object.
+pub(super) fn contains_ptr(ptr: *const u8) -> bool {
+    object_of(ptr).is_some()
+}
+
+/// Returns the number of pages of the large object that contains the pointer.
+pub(super) fn nr_pages_of(ptr: *const u8) -> Option<usize> {
+    object_of(ptr).map(|object| object.nr_pages)
+}
+
+/// Returns whether the pointer refers to the beginning of a large object.
+pub(super) fn is_object_start(ptr: *const u8) -> bool {
+    object_of(ptr).is_some_and(|object| object.addr == ptr as usize)
+}
+
+// Returns the large object that contains the pointer, by the tag of its page.
+//
+// Like `slab::slot_size_of`, this function only queries the page metadata,
+// so the pointer is never dereferenced.
+fn object_of(ptr: *const u8) -> Option<LargeObject> {
+    match page_alloc::page_usage_of(ptr)? {
+        PageUsage::LargeObject { addr, nr_pages, is_guarded } => {
+            Some(LargeObject { addr, nr_pages, is_guarded })
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,66 @@
+//! The memory accounting of heap allocations.
+//!
+//! Each heap allocation is charged to a "charge context",
+//! e.g., the memory control group of the current task.
+//! The charge context and the charge policy are provided by
+//! the OSTD user via [`set_memcg_hooks`].
+
+/// The hooks for charging heap allocations.
+pub struct MemcgHooks {
+    /// Returns the charge context of the current CPU or task.
+    pub current_context: fn() -> u32,
+    /// Charges `size` bytes to the context.
+    ///
+    /// Returns `false` if the context is over its limit,
+    /// in which case the allocation fails.
+    pub charge: fn(size: usize, context: u32) -> bool,
+    /// Uncharges `size` bytes from the context.
+    pub uncharge: fn(size: usize, context: u32),
+}
+
+static MEMCG_HOOKS: Once<MemcgHooks> = Once::new();
+
+/// Installs the memory accounting hooks.
+///
+/// The hooks must not allocate memory from the heap.
+///
+/// # Panics
+///
+/// This method will panic if it is called more than once.
+pub fn set_memcg_hooks(hooks: MemcgHooks) {
+    super::set_hook_once(&MEMCG_HOOKS, hooks, "memcg hooks");
+}
+
+/// Removes the memory accounting hooks, for `HeapAlloc::reset_for_test`.
+///
+/// # Safety
+///
+/// No one may use the hooks concurrently.
+#[cfg(test)]
+pub(super) unsafe fn reset_for_test() {
+    // SAFETY: The caller guarantees that no one else is using the hooks,
+    // which are function pointers that need not be dropped.
+    unsafe { super::reset_once(&MEMCG_HOOKS) };
+}
+
+/// Charges an allocation of `size` bytes to the current context.
+///
+/// Returns `true` if no hooks are installed.
+pub(super) fn charge(size: usize) -> bool {
+    let Some(hooks) = MEMCG_HOOKS.get() else {
+        return true;
+    };
+    (hooks.charge)(size, (hooks.current_context)())
+}
+
+/// Uncharges a deallocation of `size` bytes from the current context.
+//
+// Note that the context that frees an object may differ from
+// the one that allocated it. We do not record the charge context
+// per allocation to keep the allocation path cheap.
+pub(super) fn uncharge(size: usize) {
+    let Some(hooks) = MEMCG_HOOKS.get() else {
+        return;
+    };
+    (hooks.uncharge)(size, (hooks.current_context)())
+}
@@ -1,32 +1,895 @@
+//! The global heap.
+//!
+//! The heap must never depend on itself to function. So the allocation and
+//! deallocation paths use only fixed-size data structures (e.g., arrays and
+//! intrusive lists of slab slots), never the collections of the `alloc` crate.
+//! The build enforces so: the collections are disallowed by `clippy.toml`,
+//! and the lints are denied for the heap except its tests.
+//!
+//! The hooks of the heap (e.g., [`set_clock`] and [`set_bad_free_handler`])
+//! can only be set once, and setting a hook again panics,
+//! like the injection of the slab allocators.
+//! The tunables (e.g., [`set_max_allocation`]) can be changed at any time.
+
+#![cfg_attr(not(test), deny(clippy::disallowed_types, clippy::disallowed_macros))]
+
+/// Asserts an invariant of the heap at the given level of checking.
+///
+/// - `cheap`: The checks that cost little and guard against memory corruption.
+///   They are on unless the `heap_assert_none` feature is enabled.
+/// - `full`: The checks of internal consistency, which may be costly on hot paths.
+///   They are only on with the `heap_assert_full` feature.
+///
+/// The disabled checks are still type-checked, but compiled out.
+/// The panics documented by the public APIs are not subject to the levels.
+macro_rules! heap_assert {
+    (cheap, $($arg:tt)+) => {
+        if cfg!(not(feature = "heap_assert_none")) {
+            assert!($($arg)+);
+        }
+    };
+    (full, $($arg:tt)+) => {
+        if cfg!(feature = "heap_assert_full") {
+            assert!($($arg)+);
+        }
+    };
+}
+
+#[cfg(all(feature = "heap_assert_none", feature = "heap_assert_full"))]
+compile_error!("`heap_assert_none` and `heap_assert_full` are mutually exclusive");
+
 mod early_heap;
+mod emergency;
+mod large_object;
+#[cfg(feature = "memcg")]
+mod memcg;
+mod page_stash;
+mod pool;
+#[cfg(feature = "heap_profile")]
+mod profile;
+mod size_class;
 mod slab;
+#[cfg(feature = "heap_trace")]
+mod trace;
 
-pub use self::slab::{Slab, FreeSlabSlot};
+pub use self::size_class::{size_class_index, SizeClass, NR_SLAB_CLASSES, SLAB_SIZE_CLASSES};
+pub use self::slab::{
+    Slab, SlabCorruption, FreeSlabSlot, FreeSlabSlotList, RemoteFreeQueue, HasOwnerCpu, owner_cpu_of,
+    AgeExt, oldest_slab, SlabLifecycle, SlotAllocHook, SlabView, SlabAllocGuard, heap_epoch,
+    set_pages_per_slab, pages_per_slab, set_low_memory_watermark, admits_new_slab, MAX_PAGES_PER_SLAB, ALLOC_POISON_BYTE,
+    SLAB_COLOR_ALIGN, slot_align_of,
+};
+pub use self::emergency::{alloc_emergency, dealloc_emergency, reserve_emergency};
+pub use self::page_stash::{set_page_stash_size, warm_page_cache};
+pub use self::pool::{PoolBox, SlabPool};
+pub use self::early_heap::prefault as prefault_early_heap;
 use self::early_heap::{EarlyHeapAlloc};
+#[cfg(feature = "memcg")]
+pub use self::memcg::{set_memcg_hooks, MemcgHooks};
+#[cfg(feature = "heap_profile")]
+pub use self::profile::{heap_profile_count, heap_profile_top};
+#[cfg(feature = "heap_trace")]
+pub use self::trace::{for_each_live_allocation, CallSite};
+
+// Defines the base page size and a type aligned to a page from one literal,
+// as `repr(align)` only accepts literals.
+macro_rules! define_page_size {
+    ($page_size:literal) => {
+        // The base page size, which is selected by the `page_size_*` features.
+        //
+        // All the page-related logic of the heap (e.g., the number of slots per slab
+        // and the masking from a slot to its slab) must follow this constant,
+        // rather than assuming 4KB pages.
+        pub(crate) const PAGE_SIZE: usize = $page_size;
+
+        // A zero-sized type that aligns the types that embed it to `PAGE_SIZE`,
+        // e.g., the static pages of the early heap.
+        #[repr(align($page_size))]
+        #[derive(Clone, Copy)]
+        pub(crate) struct PageAligned;
+    };
+}
+
+#[cfg(not(any(feature = "page_size_16k", feature = "page_size_64k")))]
+define_page_size!(4096);
+#[cfg(feature = "page_size_16k")]
+define_page_size!(16384);
+#[cfg(feature = "page_size_64k")]
+define_page_size!(65536);
+
+static_assert!(PAGE_SIZE.is_power_of_two());
+static_assert!(mem::align_of::<PageAligned>() == PAGE_SIZE);
+// The features must agree with the page size of the arch layer of OSTD.
+static_assert!(PAGE_SIZE == crate::arch::mm::PAGE_SIZE);
 
 /// Injects an array of slab allocators of different slot sizes.
+///
+/// The slab allocators allocate slabs from the page allocator.
+/// So the page allocator must have been initialized before the injection,
+/// otherwise `Error::PageAllocatorNotReady` is returned
+/// and the heap keeps using the early heap.
 /// 
 /// # Panics
 /// 
 /// This method will panic if it is called more than once.
-pub fn inject_slab_allocators(slab_alloc_array: SlabAllocators) {
-    self.HEAP_ALLOC.inject_slab_allocators(slab_allocators)
+pub fn inject_slab_allocators(slab_allocators: SlabAllocators) -> Result<(), Error> {
+    HEAP_ALLOC.inject_slab_allocators(slab_allocators)
 }
 
+/// Checks whether the page allocator is ready for the slab allocators.
+///
+/// The slab allocators that allocate slabs upon their initialization
+/// (e.g., to warm up their caches) must call this function first,
+/// so that they fail cleanly with `Error::PageAllocatorNotReady`
+/// instead of running out of pages during the boot.
+pub fn check_page_allocator_ready() -> Result<(), Error> {
+    if !page_alloc::is_initialized() {
+        return Err(Error::PageAllocatorNotReady);
+    }
+    Ok(())
+}
+
+// The `SLOT_SIZE` of each field is bound to its size class,
+// so a field cannot hold a slab allocator of a mismatched slot size.
 pub struct SlabAllocators {
-    pub size16: &'static dyn SlabSlotAlloc<16>,
-    pub size32: &'static dyn SlabSlotAlloc<32>,
+    pub size16: &'static dyn SlabSlotAlloc<{ SizeClass::B16.bytes() }>,
+    pub size32: &'static dyn SlabSlotAlloc<{ SizeClass::B32.bytes() }>,
     // ...
-    pub size2048: &'static dyn SlabSlotAlloc<2048>,
+    pub size2048: &'static dyn SlabSlotAlloc<{ SizeClass::B2048.bytes() }>,
+}
+/// The slab allocator of the size class whose slot size is `SLOT_SIZE`.
+///
+/// The trait maps a slot size to a field of `SlabAllocators` at compile time,
+/// which allows [`alloc_class`] to skip the dispatch on the size class.
+pub trait SlabAllocatorOf<const SLOT_SIZE: usize> {
+    fn slab_allocator(&self) -> &'static dyn SlabSlotAlloc<SLOT_SIZE>;
+}
+
+impl SlabAllocatorOf<{ SizeClass::B16.bytes() }> for SlabAllocators {
+    fn slab_allocator(&self) -> &'static dyn SlabSlotAlloc<{ SizeClass::B16.bytes() }> {
+        self.size16
+    }
+}
+
+impl SlabAllocatorOf<{ SizeClass::B32.bytes() }> for SlabAllocators {
+    fn slab_allocator(&self) -> &'static dyn SlabSlotAlloc<{ SizeClass::B32.bytes() }> {
+        self.size32
+    }
+}
+
+// ...
+
+impl SlabAllocatorOf<{ SizeClass::B2048.bytes() }> for SlabAllocators {
+    fn slab_allocator(&self) -> &'static dyn SlabSlotAlloc<{ SizeClass::B2048.bytes() }> {
+        self.size2048
+    }
+}
+
+static_assert!(SizeClass::B16.bytes() == slab::MIN_SLAB_SLOT_SIZE);
+static_assert!(SizeClass::B2048.bytes() == slab::MAX_SLAB_SLOT_SIZE);
+static_assert!(is_slot_size_mapping_sound());
+
+// Checks that, for every object size from 1 to `MAX_SLAB_SLOT_SIZE`,
+// `determine_slot_size` returns the smallest size class
+// in `SLAB_SIZE_CLASSES` that can hold the object.
+//
+// The check is evaluated at compile time, which catches
+// off-by-one bugs in the mapping from object sizes to size classes.
+const fn is_slot_size_mapping_sound() -> bool {
+    let mut obj_size = 1;
+    while obj_size <= slab::MAX_SLAB_SLOT_SIZE {
+        let slot_size = determine_slot_size(obj_size).bytes();
+
+        let mut is_valid_class = false;
+        let mut is_smallest_class = true;
+        let mut i = 0;
+        while i < SLAB_SIZE_CLASSES.len() {
+            let class_size = SLAB_SIZE_CLASSES[i];
+            if class_size == slot_size {
+                is_valid_class = true;
+            }
+            if obj_size <= class_size && class_size < slot_size {
+                is_smallest_class = false;
+            }
+            i += 1;
+        }
+        if slot_size < obj_size || !is_valid_class || !is_smallest_class {
+            return false;
+        }
+
+        obj_size += 1;
+    }
+    true
 }
-static_assert!(16 == slab::MIN_SLAB_SLOT_SIZE);
-static_assert!(2028 == slab::MAX_SLAB_SLOT_SIZE);
 
 pub trait SlabSlotAlloc<const OBJ_SIZE: usize> {
     fn alloc(&self, current_cpu: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<OBJ_SIZE>>;
+
+    /// Allocates a slot that is aligned to `OBJ_SIZE`.
+    ///
+    /// The heap calls this method for the allocations whose alignment
+    /// equals the slot size, or exceeds the alignment of the slots of `alloc`
+    /// (see `slot_align_of`). With the `slab_coloring` feature,
+    /// a slab allocator must serve such allocations from a sub-pool of
+    /// uncolored slabs (see `Slab::alloc_aligned`).
+    ///
+    /// By default, the slots of `alloc` are assumed to be aligned to `OBJ_SIZE`,
+    /// which holds for the slabs that are never colored.
+    fn alloc_aligned(&self, current_cpu: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<OBJ_SIZE>> {
+        self.alloc(current_cpu)
+    }
+
+    /// Allocates a slot only if it can be done on the fast path.
+    ///
+    /// Unlike `alloc`, this method never allocates a new slab.
+    /// So real-time code that has pre-filled the slab allocator
+    /// can treat any `None` as a bug.
+    fn try_alloc_fast(&self, _current_cpu: &dyn PinCurrentCpu) -> Option<FreeSlabSlot<OBJ_SIZE>> {
+        None
+    }
+
+    /// Returns the unused slabs to the page allocator,
+    /// but keeps at least `min_resident_slabs` slabs resident.
+    ///
+    /// Returns the number of the reclaimed slabs.
+    fn shrink(&self, _min_resident_slabs: usize) -> usize {
+        0
+    }
+
+    /// Returns the number of slots that can be allocated
+    /// before the slab allocator has to allocate new slabs.
+    ///
+    /// The number is a best-effort snapshot, which is for tuning purposes only.
+    fn capacity_hint(&self) -> usize {
+        0
+    }
+
+    /// Validates all the slabs of the slab allocator (see `Slab::validate`).
+    ///
+    /// Each corruption is reported to `on_corruption`, along with
+    /// the base address of the corrupted slab.
+    /// Returns the number of the corrupted slabs.
+    ///
+    /// There is no default, as a slab allocator that reports no corruption
+    /// without checking would hide the corruptions.
+    fn health_check(&self, on_corruption: &mut dyn FnMut(*const u8, SlabCorruption)) -> usize;
+
+    /// Returns the number of slots that are used by objects,
+    /// excluding the borrowed slots parked on cache-level free lists.
+    ///
+    /// Like `capacity_hint`, the number is a best-effort snapshot.
+    /// There is no default, as the leak checks rely on the number.
+    fn nr_inuse_slots(&self) -> usize;
+}
+
+/// Sets the minimum number of slabs that the slab allocator of a size class
+/// keeps resident, even if the slabs are unused.
+///
+/// Keeping a small working set warm avoids thrashing the page allocator
+/// on bursty workloads.
+pub fn set_min_resident(size_class: SizeClass, nr_slabs: usize) {
+    HEAP_ALLOC.set_min_resident(size_class, nr_slabs)
+}
+
+/// Prepares the heap for a host test that drives the slab machinery end to end.
+///
+/// The page allocator is backed by the given arena, so that slabs and
+/// large objects are allocated from the arena deterministically.
+/// And the heap is reset to the early-heap backend (see `HeapAlloc::reset_for_test`),
+/// so that the test can inject its own slab allocators.
+///
+/// # Safety
+///
+/// The safety requirements are the same as `HeapAlloc::reset_for_test`.
+/// In addition, no memory allocated from the previous page source may be freed
+/// after the call.
+#[cfg(test)]
+pub unsafe fn enter_test_mode(arena: &'static mut [u8]) {
+    assert!(
+        arena.as_ptr() as usize % PAGE_SIZE == 0 && arena.len() % PAGE_SIZE == 0,
+        "the arena must consist of whole pages"
+    );
+    // SAFETY: The caller guarantees that no one else is using the page allocator.
+    unsafe { page_alloc::init_with_arena(arena) };
+    // SAFETY: The caller upholds the safety requirements.
+    unsafe { HEAP_ALLOC.reset_for_test() };
+}
+
+/// The global heap, locked by a test that drives it end to end.
+///
+/// The heap is shared by all the tests of the process,
+/// so such tests must run one at a time, each holding a `TestHeap`.
+/// The heap is backed by a static arena of pages (see `enter_test_mode`).
+///
+/// The global allocator serves the heap only to the thread that holds the `TestHeap`
+/// and the threads attached to it (see `attach_current_thread`).
+/// The other threads, e.g., those of the test harness, are served by the system allocator,
+/// so their memory never lives in the arena that the next test resets.
+#[cfg(test)]
+pub struct TestHeap {
+    nr_pages: usize,
+    _guard: SpinLockGuard<'static, ()>,
 }
 
+// The static arena of pages that backs the heap in tests.
+//
+// The alignment covers all the supported page sizes.
+#[cfg(test)]
+#[repr(C, align(65536))]
+struct TestArena([u8; TestHeap::MAX_NR_PAGES * PAGE_SIZE]);
+#[cfg(test)]
+static mut TEST_ARENA: TestArena = TestArena([0; TestHeap::MAX_NR_PAGES * PAGE_SIZE]);
+
+#[cfg(test)]
+std::thread_local! {
+    // Whether the current thread is served by the heap under test.
+    static DRIVES_TEST_HEAP: Cell<bool> = const { Cell::new(false) };
+}
+
+// Returns whether the current thread is served by the heap under test.
+#[cfg(test)]
+fn drives_test_heap() -> bool {
+    // The flag is gone while the thread is being torn down.
+    DRIVES_TEST_HEAP.try_with(Cell::get).unwrap_or(false)
+}
+
+// Returns whether the memory that `ptr` refers to is served by the heap under test,
+// which allocates from the arena and the early heap only.
+#[cfg(test)]
+fn is_test_heap_ptr(ptr: *mut u8) -> bool {
+    let arena_start = &raw const TEST_ARENA as usize;
+    let arena_end = arena_start + mem::size_of::<TestArena>();
+    (arena_start..arena_end).contains(&(ptr as usize)) || early_heap::contains_ptr(ptr)
+}
+
+// The global allocator of tests, which dispatches between the heap under test
+// and the system allocator (see `TestHeap`).
+//
+// The memory is freed to whichever allocator it comes from. The memory of the heap
+// that is freed by a thread no longer served by the heap, e.g., a panic message
+// that the harness drops after the test has ended, is leaked instead,
+// as the heap may have been reset for another test.
+#[cfg(test)]
+struct TestGlobalAlloc;
+
+#[cfg(test)]
 #[global_allocator]
+static TEST_GLOBAL_ALLOC: TestGlobalAlloc = TestGlobalAlloc;
+
+#[cfg(test)]
+unsafe impl GlobalAlloc for TestGlobalAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // SAFETY: The caller upholds the safety requirements of `GlobalAlloc::alloc`.
+        unsafe {
+            if drives_test_heap() {
+                HEAP_ALLOC.alloc(layout)
+            } else {
+                std::alloc::System.alloc(layout)
+            }
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // SAFETY: The caller upholds the safety requirements of `GlobalAlloc::realloc`,
+        // and the memory is reallocated by the allocator that it comes from.
+        unsafe {
+            if !is_test_heap_ptr(ptr) {
+                return std::alloc::System.realloc(ptr, layout, new_size);
+            }
+            if drives_test_heap() {
+                return HEAP_ALLOC.realloc(ptr, layout, new_size);
+            }
+            let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+            let new_ptr = std::alloc::System.alloc(new_layout);
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            }
+            new_ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // SAFETY: The caller upholds the safety requirements of `GlobalAlloc::dealloc`,
+        // and the memory is freed to the allocator that it comes from.
+        unsafe {
+            if !is_test_heap_ptr(ptr) {
+                std::alloc::System.dealloc(ptr, layout);
+            } else if drives_test_heap() {
+                HEAP_ALLOC.dealloc(ptr, layout);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl TestHeap {
+    /// The maximum number of pages of the arena.
+    pub const MAX_NR_PAGES: usize = 256;
+
+    /// Locks the heap for the current test, and resets it
+    /// with a fresh arena of `nr_pages` pages.
+    ///
+    /// A small arena allows a test to exhaust the page allocator quickly.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `nr_pages` exceeds `MAX_NR_PAGES`.
+    pub fn lock(nr_pages: usize) -> Self {
+        assert!(nr_pages <= Self::MAX_NR_PAGES);
+        static TEST_HEAP_LOCK: SpinLock<()> = SpinLock::new(());
+        let test_heap = Self {
+            nr_pages,
+            _guard: TEST_HEAP_LOCK.lock(),
+        };
+        // SAFETY: The memory allocated by the previous test is never freed to the heap,
+        // as the threads that the test has attached to the heap have been detached.
+        unsafe { test_heap.reset() };
+        DRIVES_TEST_HEAP.set(true);
+        test_heap
+    }
+
+    /// Attaches the current thread to the heap under test, e.g., a thread
+    /// spawned by the test, until the returned guard is dropped.
+    ///
+    /// The thread must be detached before the test ends,
+    /// so that it never frees the memory of the heap after a reset.
+    pub fn attach_current_thread(&self) -> TestHeapThread<'_> {
+        DRIVES_TEST_HEAP.set(true);
+        TestHeapThread { _test_heap: self, _not_send: PhantomData }
+    }
+
+    /// Returns the number of free pages of the page allocator,
+    /// e.g., to check that a reclamation has returned its pages.
+    pub fn nr_free_pages(&self) -> usize {
+        page_alloc::nr_free_pages()
+    }
+
+    /// Locks the heap for the current test, and resets it without any page source,
+    /// as if the page allocator had not been initialized yet.
+    ///
+    /// This allows a test to drive the heap during the early boot.
+    pub fn lock_before_page_alloc() -> Self {
+        let test_heap = Self::lock(0);
+        // SAFETY: The heap is locked by the current test,
+        // and no memory has been allocated from the arena yet.
+        unsafe {
+            page_alloc::reset_for_test();
+            HEAP_ALLOC.reset_for_test();
+        }
+        test_heap
+    }
+
+    /// Resets the heap with a fresh arena, e.g., to inject another set of slab allocators.
+    ///
+    /// # Safety
+    ///
+    /// No memory allocated from the heap before the reset may be freed afterwards.
+    pub unsafe fn reset(&self) {
+        // SAFETY: The arena is only used by the heap, which is locked by this test.
+        // The caller upholds the rest of the safety requirements.
+        unsafe {
+            let arena = &mut (*(&raw mut TEST_ARENA)).0[..self.nr_pages * PAGE_SIZE];
+            enter_test_mode(arena);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestHeap {
+    fn drop(&mut self) {
+        DRIVES_TEST_HEAP.set(false);
+    }
+}
+
+/// A thread attached to the heap under test (see `TestHeap::attach_current_thread`).
+#[cfg(test)]
+pub struct TestHeapThread<'a> {
+    _test_heap: &'a TestHeap,
+    // The attachment is a state of the current thread.
+    _not_send: PhantomData<*const ()>,
+}
+
+#[cfg(test)]
+impl Drop for TestHeapThread<'_> {
+    fn drop(&mut self) {
+        DRIVES_TEST_HEAP.set(false);
+    }
+}
+
+/// Allocates a slot of `SLOT_SIZE` directly from the slab allocator of its size class.
+///
+/// This is for the code that repeatedly allocates objects of a fixed size.
+/// The size class is resolved at compile time, so the allocation skips
+/// `determine_slot_size` and the dispatch on the size class.
+/// Like the slab allocators, the allocation is not charged to
+/// memory control groups and not profiled.
+///
+/// Returns `None` if the slab allocators have not been injected
+/// or the slab allocator is out of memory.
+pub fn alloc_class<const SLOT_SIZE: usize>(
+    pin_cpu_guard: &dyn PinCurrentCpu,
+) -> Option<FreeSlabSlot<SLOT_SIZE>>
+where
+    SlabAllocators: SlabAllocatorOf<SLOT_SIZE>,
+{
+    let CurrentBackend::SlabCaches(slab_allocators) = HEAP_ALLOC.current_backend() else {
+        return None;
+    };
+    slab_allocators.slab_allocator().alloc(pin_cpu_guard)
+}
+
+/// Frees a slot that has been allocated by [`alloc_class`].
+///
+/// The slot is recycled to its slab directly (see `FreeSlabSlot::recycle`).
+pub fn dealloc_class<const SLOT_SIZE: usize>(
+    free_slot: FreeSlabSlot<SLOT_SIZE>,
+    pin_cpu_guard: &dyn PinCurrentCpu,
+) where
+    SlabAllocators: SlabAllocatorOf<SLOT_SIZE>,
+{
+    free_slot.recycle(pin_cpu_guard);
+}
+
+/// Checks that the heap has no outstanding allocations, for the end of a test.
+///
+/// Every size class must have no slots in use by objects,
+/// and there must be no large objects.
+/// Otherwise, a report of the outstanding allocations is returned.
+#[cfg(test)]
+pub fn verify_no_leaks() -> Result<(), LeakReport> {
+    let mut report = LeakReport {
+        nr_leaked_slots: [0; NR_SLAB_CLASSES],
+        nr_large_objects: large_object::nr_objects(),
+    };
+    if let CurrentBackend::SlabCaches(slab_allocators) = HEAP_ALLOC.current_backend() {
+        report.nr_leaked_slots[SizeClass::B16.index()] = slab_allocators.size16.nr_inuse_slots();
+        report.nr_leaked_slots[SizeClass::B32.index()] = slab_allocators.size32.nr_inuse_slots();
+        // ...
+        report.nr_leaked_slots[SizeClass::B2048.index()] = slab_allocators.size2048.nr_inuse_slots();
+    }
+
+    if report.nr_large_objects == 0 && report.nr_leaked_slots.iter().all(|&nr| nr == 0) {
+        Ok(())
+    } else {
+        Err(report)
+    }
+}
+
+/// The outstanding allocations found by [`verify_no_leaks`].
+#[cfg(test)]
+#[derive(Clone, Copy, Debug)]
+pub struct LeakReport {
+    /// The number of the slots in use, indexed by the size class.
+    pub nr_leaked_slots: [usize; NR_SLAB_CLASSES],
+    /// The number of the large objects.
+    pub nr_large_objects: usize,
+}
+
+#[cfg(test)]
+impl fmt::Display for LeakReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "leaked allocations:")?;
+        for &size_class in SizeClass::ALL.iter() {
+            let nr_leaked = self.nr_leaked_slots[size_class.index()];
+            if nr_leaked > 0 {
+                write!(f, " {} x {} bytes,", nr_leaked, size_class.bytes())?;
+            }
+        }
+        write!(f, " {} large objects", self.nr_large_objects)
+    }
+}
+
+/// Allocates memory like `GlobalAlloc::alloc`, with headroom for the object to grow.
+///
+/// The memory is allocated from the next larger size class, if any,
+/// so that the subsequent `realloc`s that grow the object within the slot
+/// need no copy. The layout of the memory may imply a smaller slot,
+/// so the memory must be freed with [`dealloc_in_larger_slot`],
+/// unless it has been reallocated to a size of the class of its slot.
+///
+/// Before the slab allocators are injected, no headroom is reserved.
+///
+/// # Safety
+///
+/// The safety requirements are the same as `GlobalAlloc::alloc`.
+pub unsafe fn alloc_growable(layout: Layout) -> *mut u8 {
+    let promoted_layout = match (HEAP_ALLOC.current_backend(), SizeClass::for_size(layout.size())) {
+        (CurrentBackend::SlabCaches(_), Some(size_class)) if layout.size() > 0 => size_class
+            .next()
+            .and_then(|promoted| Layout::from_size_align(promoted.bytes(), layout.align()).ok())
+            .unwrap_or(layout),
+        _ => layout,
+    };
+    unsafe { HEAP_ALLOC.alloc(promoted_layout) }
+}
+
+/// Allocates at least `min_size` bytes of memory aligned to `align`,
+/// and returns the memory along with its usable size.
+///
+/// The usable size is the slot size for the memory served by slabs,
+/// or `min_size` rounded up to pages for the large objects.
+/// So a growable buffer can use the whole memory without `realloc`s.
+/// The memory can be freed with any layout of `align` whose size is
+/// between `min_size` and the usable size.
+///
+/// Returns `None` if `align` is not a power of two,
+/// the size overflows when rounded up, or the heap is out of memory.
+pub fn alloc_at_least(min_size: usize, align: usize) -> Option<(NonNull<u8>, usize)> {
+    let layout = Layout::from_size_align(min_size, align).ok()?;
+    let usable_size = usable_size_of_layout(layout)?;
+    // The hooks, e.g., memory control groups, are charged by the backing size
+    // of the memory, which is the usable size, regardless of the layout.
+    // SAFETY: Zero-sized layouts are supported by `HeapAlloc`.
+    let ptr = NonNull::new(unsafe { HEAP_ALLOC.alloc(layout) })?;
+    Some((ptr, usable_size))
+}
+
+/// Allocates a slot of exactly `slot_size` bytes aligned to `align`,
+/// from the named size class instead of the class implied by the object size.
+///
+/// It suits a caller that wants a full slot for an object that is tiny at first,
+/// e.g., a buffer that will grow, to avoid future reallocations.
+/// The slot can be freed with the layout of the object, e.g., 4 bytes,
+/// with [`dealloc_in_larger_slot`], as the heap recovers the size class
+/// from the slab of the slot.
+///
+/// Returns `None` if `slot_size` is not the slot size of any size class,
+/// `align` is not a power of two or larger than `slot_size`,
+/// or the heap is out of memory. Before the slab allocators are injected,
+/// `None` is returned as well, since the early heap does not record the slot sizes,
+/// so the size class could not be recovered upon deallocation.
+pub fn alloc_in_class(slot_size: usize, align: usize) -> Option<NonNull<u8>> {
+    SizeClass::from_bytes(slot_size)?;
+    if align > slot_size {
+        return None;
+    }
+    // The heap is never switched back to the early heap,
+    // so the slot is allocated from the slabs once the check passes.
+    let CurrentBackend::SlabCaches(_) = HEAP_ALLOC.current_backend() else {
+        return None;
+    };
+    let layout = Layout::from_size_align(slot_size, align).ok()?;
+    // SAFETY: The layout is not zero-sized.
+    NonNull::new(unsafe { HEAP_ALLOC.alloc(layout) })
+}
+
+/// Frees memory like `GlobalAlloc::dealloc`, with a layout that may imply
+/// a smaller slot than the one of the memory.
+///
+/// With debug assertions, `GlobalAlloc::dealloc` checks that the layout
+/// implies the size class of the slot, which catches the memory freed with
+/// a wrong layout. The memory of [`alloc_growable`] and [`alloc_in_class`]
+/// opts out of the check by being freed with this function, which only
+/// checks that the layout fits in the slot.
+///
+/// # Safety
+///
+/// The safety requirements are the same as `GlobalAlloc::dealloc`,
+/// except that the layout of a slab slot only has to fit in the slot.
+pub unsafe fn dealloc_in_larger_slot(ptr: *mut u8, layout: Layout) {
+    // SAFETY: The caller upholds the safety requirements.
+    unsafe { HEAP_ALLOC.dealloc_with_check(ptr, layout, LayoutCheck::FitsInSlot) }
+}
+
+// Returns the size of the memory that backs a live allocation,
+// which is the size that the allocation has been charged to memcg.
+//
+// The size is recovered from the slab or the large object of the pointer,
+// instead of the layout given by the caller, which may be smaller
+// (see `alloc_growable`). The early heap does not record the slot sizes,
+// but its allocations are never promoted.
+#[cfg(feature = "memcg")]
+fn backing_size_of(ptr: *mut u8, layout: Layout) -> usize {
+    if layout.size() == 0 {
+        return 0;
+    }
+    if let Some(slot_size) = slab::slot_size_of(ptr) {
+        return slot_size;
+    }
+    if let Some(nr_pages) = large_object::nr_pages_of(ptr) {
+        return nr_pages * PAGE_SIZE;
+    }
+    usable_size_of_layout(layout).unwrap_or(layout.size())
+}
+
+// Returns the size of the memory that an allocation of the layout gets,
+// which mirrors the dispatch of `HeapAlloc::do_alloc`.
+//
+// Returns `None` if the size overflows when rounded up to pages.
+fn usable_size_of_layout(layout: Layout) -> Option<usize> {
+    if layout.size() == 0 {
+        return Some(0);
+    }
+    if layout.size() <= slab::MAX_SLAB_SLOT_SIZE {
+        let slot_size = determine_slot_size(layout.size()).bytes();
+        if layout.align() <= slot_size {
+            return Some(slot_size);
+        }
+    }
+    layout.size().checked_next_multiple_of(PAGE_SIZE)
+}
+
+/// Allocates memory directly from the page allocator,
+/// with an unmapped guard page before and after the memory.
+///
+/// An overflow or underflow of the memory faults on the guard pages,
+/// which suits security-sensitive allocations, e.g., stacks and crypto buffers.
+/// The memory is always page-backed, regardless of the size of the layout.
+///
+/// Returns a null pointer if the page allocator is out of memory
+/// or the alignment is larger than `PAGE_SIZE`.
+/// The memory is not charged to memory control groups,
+/// and must be freed with [`dealloc_guarded`].
+pub fn alloc_guarded(layout: Layout) -> *mut u8 {
+    if layout.size() == 0 || layout.size() > MAX_ALLOCATION.load(Relaxed) {
+        return ptr::null_mut();
+    }
+    large_object::alloc_guarded(layout)
+}
+
+/// Frees memory that has been allocated by [`alloc_guarded`],
+/// along with its guard pages.
+///
+/// # Safety
+///
+/// The pointer must have been returned by `alloc_guarded` with the same layout,
+/// and must not be used afterwards.
+pub unsafe fn dealloc_guarded(ptr: *mut u8, _layout: Layout) {
+    // SAFETY: The caller guarantees that the memory is a live guarded large object.
+    unsafe { large_object::dealloc(ptr) }
+}
+
+/// Allocates `nr_pages` contiguous pages, without going through a `Layout`.
+///
+/// The pages are a large object of the heap, tagged in the page metadata
+/// like the allocations larger than the slab slots.
+/// So `owner_of` classifies them as `HeapRegion::LargeObject`,
+/// and `usable_size` reports their size.
+///
+/// Returns `None` if `nr_pages` is zero, the size exceeds the maximum allocation
+/// (see `set_max_allocation`), or the page allocator is out of memory.
+/// The pages must be freed with [`free_pages`].
+pub fn alloc_pages(nr_pages: usize) -> Option<NonNull<u8>> {
+    let size = nr_pages.checked_mul(PAGE_SIZE)?;
+    if size == 0 || size > MAX_ALLOCATION.load(Relaxed) {
+        return None;
+    }
+    let layout = Layout::from_size_align(size, PAGE_SIZE).ok()?;
+    NonNull::new(large_object::alloc(layout))
+}
+
+/// Frees the pages that have been allocated by [`alloc_pages`].
+///
+/// # Panics
+///
+/// This function will panic if the pointer does not refer to
+/// the beginning of pages allocated by `alloc_pages`, or `nr_pages` does not match.
+///
+/// # Safety
+///
+/// The pages must not be used afterwards.
+pub unsafe fn free_pages(ptr: NonNull<u8>, nr_pages: usize) {
+    assert!(
+        large_object::is_object_start(ptr.as_ptr())
+            && large_object::nr_pages_of(ptr.as_ptr()) == Some(nr_pages),
+        "the pages to free do not match an allocation of `alloc_pages`",
+    );
+    // SAFETY: The pointer refers to a large object of `nr_pages` pages,
+    // and the caller guarantees that the pages are no longer used.
+    unsafe { large_object::dealloc(ptr.as_ptr()) }
+}
+
+/// Returns as many unused slabs as possible to the page allocator,
+/// while honoring the minimum number of resident slabs of each size class
+/// (see `set_min_resident`).
+///
+/// Returns the number of bytes returned to the page allocator.
+pub fn shrink_to_fit() -> usize {
+    HEAP_ALLOC.shrink_to_fit()
+}
+
+/// Returns the unused slabs of one size class to the page allocator,
+/// but keeps at least `nr_resident_slabs` slabs resident,
+/// in addition to honoring the minimum set by `set_min_resident`.
+///
+/// Returns the number of bytes returned to the page allocator,
+/// or an error if `slot_size` is not the slot size of any size class.
+pub fn try_reclaim_class(slot_size: usize, nr_resident_slabs: usize) -> Result<usize, Error> {
+    HEAP_ALLOC.try_reclaim_class(slot_size, nr_resident_slabs)
+}
+
+/// Returns whether the slab allocators have been injected,
+/// i.e., whether the heap is serving from the slab allocators.
+pub fn is_injected() -> bool {
+    HEAP_ALLOC.have_injected_slabs.load(Acquire)
+}
+
+/// Returns the name of the backend that the heap is currently serving from,
+/// which is either `"early_heap"` or `"slab_caches"`.
+///
+/// This is for diagnostics, e.g., panic messages and logs during boot.
+pub fn current_backend_name() -> &'static str {
+    match HEAP_ALLOC.current_backend() {
+        CurrentBackend::EarlyHeap(_) => "early_heap",
+        CurrentBackend::SlabCaches(_) => "slab_caches",
+    }
+}
+
+/// Returns the number of slots that the injected slab allocators
+/// can allocate before they have to allocate new slabs.
+///
+/// Returns zero if the slab allocators have not been injected.
+/// Like `SlabSlotAlloc::capacity_hint`, the number is best-effort.
+pub fn resident_capacity() -> usize {
+    let Some(slab_allocators) = HEAP_ALLOC.backend.slab_caches.get() else {
+        return 0;
+    };
+    slab_allocators.size16.capacity_hint()
+        + slab_allocators.size32.capacity_hint()
+        // ...
+        + slab_allocators.size2048.capacity_hint()
+}
+
+// The size above which allocations are rejected.
+static MAX_ALLOCATION: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Sets the maximum size of an allocation, in bytes.
+///
+/// Allocations larger than the maximum fail fast with a null pointer,
+/// instead of attempting a huge allocation from the page allocator.
+/// This guards against buggy callers that request pathologically large memory.
+/// By default, there is no maximum.
+pub fn set_max_allocation(bytes: usize) {
+    MAX_ALLOCATION.store(bytes, Relaxed);
+}
+
+/// The handler that the page allocator invokes when it runs low on memory.
+///
+/// The handler tries to free `nr_pages` pages back to the page allocator
+/// and returns the number of the pages that have actually been freed.
+///
+/// The handler frees the pages synchronously, by dropping the unused slabs
+/// (see `Slab`), which returns their pages to the page allocator.
+/// It takes the locks of the slab allocators to do so. So the page allocator
+/// must invoke the handler without holding any of its own locks, e.g.,
+/// after it has failed an allocation and released its locks, not in the middle of one.
+/// Allocating slabs while the page allocator is, in turn, short of pages is harmless,
+/// as the handler does nothing on a CPU that is inside the heap allocator.
+pub type PagePressureHandler = fn(nr_pages: usize) -> usize;
+
+/// Registers the page-pressure handler of the heap with the page allocator,
+/// via the registration function `register` of the page allocator.
+///
+/// Upon page pressure, the heap returns its unused slabs to the page allocator
+/// like `shrink_to_fit`, until `nr_pages` pages are freed.
+/// This allows reclaiming slab-held pages on demand, rather than periodically.
+pub fn on_page_pressure(register: impl FnOnce(PagePressureHandler)) {
+    register(handle_page_pressure);
+}
+
+fn handle_page_pressure(nr_pages: usize) -> usize {
+    // The page allocator may be short of pages while the heap is allocating a slab,
+    // in which case the locks of the slab allocators may be held by this CPU.
+    if in_allocator() {
+        return 0;
+    }
+    let CurrentBackend::SlabCaches(slab_allocators) = HEAP_ALLOC.current_backend() else {
+        return 0;
+    };
+
+    let mut nr_freed_bytes = 0;
+    for &size_class in SizeClass::ALL.iter() {
+        if nr_freed_bytes >= nr_pages * PAGE_SIZE {
+            break;
+        }
+        let min_resident_slabs = HEAP_ALLOC.min_resident(size_class);
+        nr_freed_bytes += HeapAlloc::shrink_class(slab_allocators, size_class, min_resident_slabs);
+    }
+    nr_freed_bytes / PAGE_SIZE
+}
+
+// In tests, the heap is served through `TestGlobalAlloc` instead.
+#[cfg_attr(not(test), global_allocator)]
 static HEAP_ALLOC: HeapAlloc = {
     // SAFETY: The global heap allocator is created only once.
     unsafe {
@@ -37,6 +900,15 @@ static HEAP_ALLOC: HeapAlloc = {
 struct HeapAlloc {
     have_injected_slabs: AtomicBool,
     backend: HeapAllocBackend,
+    // The floor of resident slabs that the reclamation paths honor, per size class.
+    min_resident_slabs: [AtomicUsize; NR_SLAB_CLASSES],
+    // Whether an allocation is shrinking the other size classes to retry
+    // (see `HeapAlloc::shrink_for_retry`).
+    is_shrinking_for_retry: AtomicBool,
+    // The number of the consecutive retries that have reclaimed nothing.
+    nr_fruitless_retries: AtomicU32,
+    // The number of the failed allocations that fail fast before the next retry.
+    nr_failures_before_retry: AtomicU32,
 }
 
 struct HeapAllocBackend {
@@ -65,129 +937,1137 @@ impl HeapAlloc {
             backend: HeapAllocBackend {
                 early_heap: SpinLock::new(early_heap),
                 slab_caches: Once::new(),
-            }
+            },
+            min_resident_slabs: [const { AtomicUsize::new(0) }; NR_SLAB_CLASSES],
+            is_shrinking_for_retry: AtomicBool::new(false),
+            nr_fruitless_retries: AtomicU32::new(0),
+            nr_failures_before_retry: AtomicU32::new(0),
         }
     }
 
-    pub fn inject_slab_allocators(&self, slab_allocators: SlabAllocators) {
-        self.slab_allocators.call_once(|| {
+    pub fn set_min_resident(&self, size_class: SizeClass, nr_slabs: usize) {
+        self.min_resident_slabs[size_class.index()].store(nr_slabs, Relaxed);
+    }
+
+    fn min_resident(&self, size_class: SizeClass) -> usize {
+        self.min_resident_slabs[size_class.index()].load(Relaxed)
+    }
+
+    pub fn shrink_to_fit(&self) -> usize {
+        let CurrentBackend::SlabCaches(slab_allocators) = self.current_backend() else {
+            return 0;
+        };
+
+        SizeClass::ALL
+            .iter()
+            .map(|&size_class| {
+                Self::shrink_class(slab_allocators, size_class, self.min_resident(size_class))
+            })
+            .sum()
+    }
+
+    pub fn try_reclaim_class(&self, slot_size: usize, nr_resident_slabs: usize) -> Result<usize, Error> {
+        let size_class = SizeClass::from_bytes(slot_size).ok_or(Error::InvalidSlotSize(slot_size))?;
+        let CurrentBackend::SlabCaches(slab_allocators) = self.current_backend() else {
+            return Ok(0);
+        };
+
+        let min_resident_slabs = nr_resident_slabs.max(self.min_resident(size_class));
+        Ok(Self::shrink_class(slab_allocators, size_class, min_resident_slabs))
+    }
+
+    // Shrinks the slab allocator of a size class,
+    // returning the number of bytes returned to the page allocator.
+    fn shrink_class(
+        slab_allocators: &SlabAllocators,
+        size_class: SizeClass,
+        min_resident_slabs: usize,
+    ) -> usize {
+        // The slab allocator is shrunk with IRQs disabled, so that
+        // an IRQ handler that allocates memory cannot deadlock on its locks.
+        // IRQs are re-enabled between the size classes to bound the latency.
+        let _irq_disabled_guard = irq::disable_local();
+        let nr_slabs = match size_class {
+            SizeClass::B16 => slab_allocators.size16.shrink(min_resident_slabs),
+            // ...
+            SizeClass::B2048 => slab_allocators.size2048.shrink(min_resident_slabs),
+        };
+        nr_slabs * pages_per_slab(size_class) * PAGE_SIZE
+    }
+
+    pub fn inject_slab_allocators(&self, slab_allocators: SlabAllocators) -> Result<(), Error> {
+        check_page_allocator_ready()?;
+
+        // The slab allocators must be ready before the flag is set,
+        // so that whoever observes the flag can use them.
+        self.backend.slab_caches.call_once(|| {
             slab_allocators
         });
 
         if self.have_injected_slabs.swap(true, AcqRel) == true {
             panic!("the slab cache set must NOT be injected more than once");
         }
-    } 
+        Ok(())
+    }
+
+    /// Resets the heap allocator to the early-heap backend,
+    /// so that a fresh set of slab allocators can be injected again.
+    ///
+    /// All the other global states of the heap are reset, too:
+    /// the hooks (e.g., `set_clock`), the tunables (e.g., `set_max_allocation`),
+    /// the per-class counters of slabs, the page stashes and the emergency reserves.
+    ///
+    /// This method is for tests ONLY, which allows multiple tests
+    /// in the same process to inject their own slab allocators.
+    /// It is unsound in production: all memory allocated from
+    /// the previously-injected slab allocators is leaked,
+    /// and freeing such memory afterwards is undefined behavior.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that no other CPU or thread is using
+    /// the heap allocator and that no memory allocated from
+    /// the injected slab allocators is freed after the reset.
+    #[cfg(test)]
+    pub unsafe fn reset_for_test(&self) {
+        self.have_injected_slabs.store(false, Release);
+        // SAFETY: The caller guarantees that no one else is accessing
+        // the slab allocators, and the old `SlabAllocators` only holds
+        // static references, which need not be dropped.
+        unsafe { reset_once(&self.backend.slab_caches) };
+        for min_resident_slabs in self.min_resident_slabs.iter() {
+            min_resident_slabs.store(0, Relaxed);
+        }
+        self.is_shrinking_for_retry.store(false, Relaxed);
+        self.nr_fruitless_retries.store(0, Relaxed);
+        self.nr_failures_before_retry.store(0, Relaxed);
+
+        // The global states of the heap outside of `HeapAlloc` are reset as well,
+        // so that no test observes the slabs, the hooks or the tunables of a previous test.
+        MAX_ALLOCATION.store(usize::MAX, Relaxed);
+        // SAFETY: The caller guarantees that no one else is using the heap.
+        // The old hooks are function pointers, which need not be dropped.
+        unsafe {
+            reset_once(&CLOCK);
+            reset_once(&SLAB_CREATED_HOOK);
+            reset_once(&BAD_FREE_HANDLER);
+            reset_once(&FOREIGN_FREE_HANDLER);
+            #[cfg(feature = "memcg")]
+            memcg::reset_for_test();
+            page_stash::reset_for_test();
+            emergency::reset_for_test();
+        }
+        slab::reset_for_test();
+        large_object::reset_for_test();
+    }
 
+    // Returns the current backend.
+    //
+    // An operation must call this method only once and stick to the returned backend,
+    // even if the slab allocators are injected concurrently in the middle of the operation.
+    // Memory allocated from the early heap is always recognized by `early_heap::contains_ptr`,
+    // so it is never freed to the slab allocators.
     fn current_backend(&self) -> CurrentBackend<'_> {
         if self.have_injected_slabs.load(Acquire) {
-            CurrentBackend {
-                slab_allocators: self.backend.slab_allocators.get().unwrap()
-            }
+            CurrentBackend::SlabCaches(self.backend.slab_caches.get().unwrap())
         } else {
-            CurrentBackend {
-                early_heap: self.backend.early_heap.get().unwrap()
-            }
+            CurrentBackend::EarlyHeap(&self.backend.early_heap)
+        }
+    }
+}
+
+/// Allocates memory like `GlobalAlloc::alloc`, counting the allocation
+/// under the call-site tag for profiling.
+///
+/// The untagged allocations are counted under the empty tag.
+///
+/// # Safety
+///
+/// The safety requirements are the same as `GlobalAlloc::alloc`.
+#[cfg(feature = "heap_profile")]
+pub unsafe fn alloc_tagged(layout: Layout, tag: &'static str) -> *mut u8 {
+    unsafe { HEAP_ALLOC.alloc_with_tag(layout, tag) }
+}
+
+/// A handle to the heap that implements `Allocator`.
+///
+/// It allows collections to opt into the heap explicitly,
+/// e.g., `Box::new_in(value, heap_allocator())`,
+/// with the same dispatch as the global allocator:
+/// across all size classes of slabs and the large objects.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeapAllocator;
+
+/// Returns a handle to the heap that implements `Allocator`.
+pub fn heap_allocator() -> HeapAllocator {
+    HeapAllocator
+}
+
+// SAFETY: The memory blocks are allocated from and freed to the global heap,
+// which is shared by all copies of the handle.
+unsafe impl Allocator for HeapAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Zero-sized layouts are supported by `HeapAlloc`.
+        let ptr = unsafe { HEAP_ALLOC.alloc(layout) };
+        let ptr = NonNull::new(ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: The caller guarantees that the memory is allocated with the layout.
+        unsafe { HEAP_ALLOC.dealloc(ptr.as_ptr(), layout) }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: The caller upholds the safety requirements of `Allocator::grow`.
+        unsafe { self.reallocate(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: The caller upholds the safety requirements of `Allocator::shrink`.
+        unsafe { self.reallocate(ptr, old_layout, new_layout) }
+    }
+}
+
+impl HeapAllocator {
+    // Reallocates a memory block to the new layout.
+    unsafe fn reallocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // `GlobalAlloc::realloc` keeps the alignment,
+        // and does not support zero-sized layouts.
+        if new_layout.align() == old_layout.align()
+            && old_layout.size() > 0
+            && new_layout.size() > 0
+        {
+            // SAFETY: The memory is allocated with `old_layout`, and the new size
+            // is valid for the alignment as `new_layout` is a valid layout.
+            let new_ptr = unsafe { HEAP_ALLOC.realloc(ptr.as_ptr(), old_layout, new_layout.size()) };
+            let new_ptr = NonNull::new(new_ptr).ok_or(AllocError)?;
+            return Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()));
         }
+
+        let new_block = self.allocate(new_layout)?;
+        // SAFETY: Both blocks are valid for the copied bytes and do not overlap.
+        // The old block is never used after being freed.
+        unsafe {
+            let nr_copied = old_layout.size().min(new_layout.size());
+            ptr::copy_nonoverlapping(ptr.as_ptr(), new_block.as_ptr() as *mut u8, nr_copied);
+            self.deallocate(ptr, old_layout);
+        }
+        Ok(new_block)
     }
 }
 
 unsafe impl GlobalAlloc for HeapAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if layout.size >= MAX_SLAB_SLOT {
-            return todo!("use the page allocator directly, instead of slab allocators");
+        #[cfg(any(feature = "heap_profile", feature = "heap_trace"))]
+        return unsafe { self.alloc_with_tag(layout, "") };
+
+        #[cfg(not(any(feature = "heap_profile", feature = "heap_trace")))]
+        unsafe { self.alloc_untagged(layout) }
+    }
+
+    // The default `alloc_zeroed`, which zeroes the memory returned by `alloc`,
+    // overwrites the pattern of the `slab_alloc_poison` feature.
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        // A reallocation that stays within the slab slot needs no copy,
+        // e.g., for the slots with headroom from `alloc_growable`.
+        // But a shrink must stay in the size class of the slot,
+        // so that the new layout passes the check of `dealloc`.
+        //
+        // With memcg, the size change must be charged through the hooks,
+        // so the reallocation always goes through `alloc` and `dealloc`.
+        #[cfg(not(feature = "memcg"))]
+        if layout.size() > 0 && layout.size() <= slab::MAX_SLAB_SLOT_SIZE {
+            if let Some(slot_size) = slab::slot_size_of(ptr) {
+                if is_slot_size_compatible(slot_size, new_size)
+                    && (new_size >= layout.size() || determine_slot_size(new_size).bytes() == slot_size)
+                {
+                    #[cfg(feature = "heap_trace")]
+                    self.with_hooks(|| trace::resize(ptr, new_size));
+                    return ptr;
+                }
+            }
+        }
+
+        // Fall back to allocating, copying, and freeing, like the default `realloc`.
+        // SAFETY: The caller guarantees that `new_size` is a valid size for the alignment.
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        // SAFETY: The caller upholds the safety requirements of `GlobalAlloc::realloc`.
+        unsafe {
+            let new_ptr = self.alloc(new_layout);
+            if !new_ptr.is_null() {
+                ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                // The memory may be from `alloc_growable`, whose layout may imply a smaller slot.
+                self.dealloc_with_check(ptr, layout, LayoutCheck::FitsInSlot);
+            }
+            new_ptr
         }
+    }
 
-        let slot_size = determine_slot_size(layout.size());
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.dealloc_with_check(ptr, layout, LayoutCheck::Exact) }
+    }
+}
 
-        // Ensure that the slabs can satisfiy the allocation's alignment requirement.
-        // Currently, our allocator cannot handle the possible but unlikely use cases
-        // where alignment is larger than slot size.
-        assert!({
-            let obj_align = layout.align();
-            let slot_align = slot_size; 
-            slot_align % obj_align == 0
+impl HeapAlloc {
+    // Deallocates like `GlobalAlloc::dealloc`, checking the layout as `layout_check` specifies.
+    unsafe fn dealloc_with_check(&self, ptr: *mut u8, layout: Layout, layout_check: LayoutCheck) {
+        let irq_disabled_guard = irq::disable_local();
+        let Some(_entry) = AllocatorEntry::enter(&irq_disabled_guard) else {
+            // Skip all the hooks when the allocator is re-entered.
+            unsafe { self.do_dealloc(ptr, layout, layout_check) };
+            return;
+        };
+
+        // The memory may be larger than the layout implies (e.g., `alloc_growable`).
+        // So the backing size is queried before the memory is freed.
+        #[cfg(feature = "memcg")]
+        let backing_size = backing_size_of(ptr, layout);
+
+        unsafe { self.do_dealloc(ptr, layout, layout_check) };
+
+        #[cfg(feature = "memcg")]
+        memcg::uncharge(backing_size);
+
+        #[cfg(feature = "heap_trace")]
+        trace::untrack(ptr);
+    }
+}
+
+impl HeapAlloc {
+    #[cfg(any(feature = "heap_profile", feature = "heap_trace"))]
+    unsafe fn alloc_with_tag(&self, layout: Layout, tag: &'static str) -> *mut u8 {
+        let ptr = unsafe { self.alloc_untagged(layout) };
+        if ptr.is_null() {
+            return ptr;
+        }
+
+        // Profiling and tracing are skipped when the allocator is re-entered.
+        self.with_hooks(|| {
+            // Only the allocations served by the slabs are counted.
+            #[cfg(feature = "heap_profile")]
+            if let Some(size_class) = SizeClass::for_size(layout.size()) {
+                profile::record(tag, size_class);
+            }
+
+            #[cfg(feature = "heap_trace")]
+            trace::track(ptr, layout, CallSite { tag });
         });
+        ptr
+    }
+
+    // Runs the bookkeeping hooks of an operation,
+    // unless the allocator is re-entered.
+    #[cfg(any(feature = "heap_profile", feature = "heap_trace"))]
+    fn with_hooks(&self, f: impl FnOnce()) {
+        let irq_disabled_guard = irq::disable_local();
+        if let Some(_entry) = AllocatorEntry::enter(&irq_disabled_guard) {
+            f();
+        }
+    }
+
+    unsafe fn alloc_untagged(&self, layout: Layout) -> *mut u8 {
+        let irq_disabled_guard = irq::disable_local();
+        let Some(_entry) = AllocatorEntry::enter(&irq_disabled_guard) else {
+            return self.alloc_reentrant(layout);
+        };
+
+        // The size of the memory that backs the allocation is charged,
+        // which is also what `dealloc` uncharges (see `backing_size_of`).
+        #[cfg(feature = "memcg")]
+        let Some(backing_size) = usable_size_of_layout(layout) else {
+            return ptr::null_mut();
+        };
+        #[cfg(feature = "memcg")]
+        if !memcg::charge(backing_size) {
+            return ptr::null_mut();
+        }
+
+        let ptr = unsafe { self.do_alloc(layout) };
+
+        #[cfg(feature = "memcg")]
+        if ptr.is_null() {
+            memcg::uncharge(backing_size);
+        }
+
+        ptr
+    }
+
+    // Allocates memory when the allocator is re-entered,
+    // e.g., by a hook that allocates memory.
+    //
+    // Re-entering the main logic could deadlock on a lock that is already held
+    // or corrupt the per-CPU states of the slab allocators. So the memory is
+    // allocated from the early heap instead, which is
+    // never locked while running the hooks.
+    //
+    // With the `no_early_heap` feature, the re-entrant allocations fail instead.
+    #[cfg(not(feature = "no_early_heap"))]
+    fn alloc_reentrant(&self, layout: Layout) -> *mut u8 {
+        let Some(size_class) = SizeClass::for_size(layout.size()) else {
+            return ptr::null_mut();
+        };
+        if size_class.bytes() % layout.align() != 0 {
+            return ptr::null_mut();
+        }
+        self.backend.early_heap.lock().alloc(size_class.bytes())
+    }
+
+    #[cfg(feature = "no_early_heap")]
+    fn alloc_reentrant(&self, _layout: Layout) -> *mut u8 {
+        ptr::null_mut()
+    }
+
+    // Allocates memory directly from the page allocator,
+    // for objects that are too large or too strongly aligned for slabs.
+    fn alloc_large(&self, layout: Layout) -> *mut u8 {
+        large_object::alloc(layout)
+    }
+
+    // Deallocates memory that has been allocated by `alloc_large`.
+    unsafe fn dealloc_large(&self, ptr: *mut u8, _layout: Layout) {
+        // SAFETY: The pointer is returned by `alloc_large`.
+        unsafe { large_object::dealloc(ptr) }
+    }
+
+    unsafe fn do_alloc(&self, layout: Layout) -> *mut u8 {
+        // A zero-sized allocation needs no memory. But the pointer must still
+        // be non-null and aligned as required by the layout.
+        if layout.size() == 0 {
+            return ptr::without_provenance_mut(layout.align());
+        }
+
+        if layout.size() > MAX_ALLOCATION.load(Relaxed) {
+            return ptr::null_mut();
+        }
+
+        // `MAX_SLAB_SLOT_SIZE` is the largest slot size of slabs.
+        // So only the objects that are strictly larger go to the page allocator.
+        if layout.size() > slab::MAX_SLAB_SLOT_SIZE {
+            return self.alloc_large(layout);
+        }
+
+        let size_class = determine_slot_size(layout.size());
+        let slot_size = size_class.bytes();
+
+        // The slabs cannot satisfy the possible but unlikely use cases
+        // where alignment is larger than slot size.
+        // Such allocations are served by the page allocator.
+        if layout.align() > slot_size {
+            return self.alloc_large(layout);
+        }
 
         let slab_allocators = match self.current_backend() {
             EarlyHeap(early_heap) => {
-                let mut early_heap_guard = early_heap.lock();
-                return early_heap.alloc(slot_size);
+                // The early heap has no pages with the `no_early_heap` feature,
+                // so its allocation code is compiled out, not just skipped.
+                #[cfg(feature = "no_early_heap")]
+                {
+                    let _ = early_heap;
+                    panic!(
+                        "allocation before the slab allocators are injected ({}), \
+                        but the early heap is disabled by the `no_early_heap` feature",
+                        DisplayLayout(layout),
+                    );
+                }
+                #[cfg(not(feature = "no_early_heap"))]
+                {
+                    let mut early_heap_guard = early_heap.lock();
+                    return early_heap_guard.alloc(slot_size);
+                }
             }
             SlabCaches(slab_allocators) => slab_allocators,
         };
 
+        // The allocations that are aligned to the slot size are served
+        // by the aligned slabs (see `SlabSlotAlloc::alloc_aligned`).
+        let needs_aligned_slot = layout.align() == slot_size || layout.align() > slot_align_of(slot_size);
+
         let irq_disabled_guard = irq::disable_local();
-        match slot_size {
-            16 => {
-                let free_slab_slot = slab_allocators.size16.alloc(&irq_disabled_guard);
+        let nr_slab_ooms = slab::nr_slab_ooms();
+        let mut slot_ptr = Self::alloc_slot(slab_allocators, size_class, needs_aligned_slot, &irq_disabled_guard);
+        // The slab allocator may fail to allocate a new slab as the page allocator
+        // is out of memory, while the pages are held by the unused slabs of other size classes.
+        // Retry exactly once after returning those slabs to the page allocator,
+        // so that an allocation that cannot be satisfied never livelocks.
+        //
+        // The failures due to the policies of the slab allocator (e.g., its capacity
+        // or the low memory watermark) are not retried, as shrinking cannot help them.
+        if slot_ptr.is_null()
+            && slab::nr_slab_ooms() != nr_slab_ooms
+            && self.shrink_for_retry(slab_allocators, size_class)
+        {
+            slot_ptr = Self::alloc_slot(slab_allocators, size_class, needs_aligned_slot, &irq_disabled_guard);
+        }
+        heap_assert!(full, slot_ptr.is_null() || slot_ptr as usize % layout.align() == 0);
+        slot_ptr
+    }
+
+    // Allocates a slot from the slab allocator of a size class,
+    // returning a null pointer if the slab allocator is out of memory.
+    //
+    // If `needs_aligned_slot` is true, the slot is aligned to the slot size.
+    fn alloc_slot(
+        slab_allocators: &SlabAllocators,
+        size_class: SizeClass,
+        needs_aligned_slot: bool,
+        irq_disabled_guard: &DisabledLocalIrqGuard,
+    ) -> *mut u8 {
+        match size_class {
+            SizeClass::B16 => {
+                let slab_allocator = slab_allocators.size16;
+                let free_slab_slot = if needs_aligned_slot {
+                    slab_allocator.alloc_aligned(irq_disabled_guard)
+                } else {
+                    slab_allocator.alloc(irq_disabled_guard)
+                };
                 free_slab_slot.into_raw()
             }
             // ...
-            2048 => {
-                let free_slab_slot = slab_allocators.size2048.alloc(&irq_disabled_guard);
-                free_slab_slot.into_raw()                
+            SizeClass::B2048 => {
+                let slab_allocator = slab_allocators.size2048;
+                let free_slab_slot = if needs_aligned_slot {
+                    slab_allocator.alloc_aligned(irq_disabled_guard)
+                } else {
+                    slab_allocator.alloc(irq_disabled_guard)
+                };
+                free_slab_slot.into_raw()
             }
-            _ => unreachable!()
         }
     }
 
-    unsafe fn dealloc(&self, slot_ptr: *mut u8, layout: Layout) {
-        if layout.size >= MAX_SLAB_SLOT {
-            return todo!("use the page allocator directly, instead of slab allocators");
+    // Returns the unused slabs of all size classes but `size_class`
+    // to the page allocator, honoring the minimum resident slabs.
+    //
+    // The slab allocator of `size_class` has just failed to allocate,
+    // so it has no unused slab to return.
+    //
+    // Returns the number of bytes returned to the page allocator.
+    fn shrink_other_classes(&self, slab_allocators: &SlabAllocators, size_class: SizeClass) -> usize {
+        SizeClass::ALL
+            .iter()
+            .filter(|&&other| other != size_class)
+            .map(|&other| Self::shrink_class(slab_allocators, other, self.min_resident(other)))
+            .sum()
+    }
+
+    // The maximum number of the failed allocations that fail fast
+    // after the retries that have reclaimed nothing, as a power of two.
+    const MAX_RETRY_BACKOFF_SHIFT: u32 = 10;
+
+    // Shrinks the other size classes for an allocation of `size_class`, which has failed
+    // as the page allocator is out of memory. Returns whether any memory is reclaimed,
+    // in which case the allocation should be retried.
+    //
+    // Shrinking walks all the size classes, so it is rate-limited.
+    // Only one CPU shrinks at a time, and the allocations that fail meanwhile fail fast.
+    // After `n` consecutive retries that reclaim nothing, the next `2^(n-1) - 1`
+    // failed allocations fail fast, so a heap that is really out of memory
+    // is not shrunk on every failure.
+    fn shrink_for_retry(&self, slab_allocators: &SlabAllocators, size_class: SizeClass) -> bool {
+        let is_backing_off = self
+            .nr_failures_before_retry
+            .fetch_update(Relaxed, Relaxed, |nr_failures| nr_failures.checked_sub(1))
+            .is_ok();
+        if is_backing_off || self.is_shrinking_for_retry.swap(true, Acquire) {
+            return false;
         }
 
-        let slot_size = self.determine_slot_size(layout.size());
+        let nr_reclaimed_bytes = self.shrink_other_classes(slab_allocators, size_class);
+        if nr_reclaimed_bytes > 0 {
+            self.nr_fruitless_retries.store(0, Relaxed);
+        } else {
+            let nr_fruitless_retries = self.nr_fruitless_retries.load(Relaxed).saturating_add(1);
+            self.nr_fruitless_retries.store(nr_fruitless_retries, Relaxed);
+            let backoff_shift = (nr_fruitless_retries - 1).min(Self::MAX_RETRY_BACKOFF_SHIFT);
+            self.nr_failures_before_retry.store((1 << backoff_shift) - 1, Relaxed);
+        }
 
-        let slab_allocators = match self.current_backend() {
-            EarlyHeap(early_heap) => {
-                let mut early_heap_guard = early_heap.lock();
-                // SAFETY: the memory represented by pointer and size 
-                // is valid and must have been allocated from the early heap.
-                unsafe {
-                    early_heap.dealloc(slot_ptr, slot_size)
-                };
-            }
-            SlabCaches(slab_allocators) => slab_allocators,
-        };
+        self.is_shrinking_for_retry.store(false, Release);
+        nr_reclaimed_bytes > 0
+    }
+
+    unsafe fn do_dealloc(&self, slot_ptr: *mut u8, layout: Layout, layout_check: LayoutCheck) {
+        // Zero-sized allocations own no memory.
+        if layout.size() == 0 {
+            return;
+        }
+
+        // A pointer that belongs to none of the regions of the heap
+        // would corrupt a free list or the page allocator.
+        // All the checks only query the address ranges or the page metadata,
+        // so they take constant time without any lock.
+        if !early_heap::contains_ptr(slot_ptr)
+            && slab::slot_size_of(slot_ptr).is_none()
+            && !large_object::contains_ptr(slot_ptr)
+        {
+            report_foreign_free(slot_ptr, layout);
+            return;
+        }
+
+        // The layout is only checked as a diagnostic.
+        // The memory is routed by the region that it belongs to.
+        #[cfg(debug_assertions)]
+        check_dealloc_layout(slot_ptr, layout, layout_check);
+        #[cfg(not(debug_assertions))]
+        let _ = layout_check;
+
+        // Take one snapshot of the backend for the whole operation.
+        let backend = self.current_backend();
 
         // We MUST NOT use the injected slab caches
         // to deallocate memory allocated from the early heap.
         if early_heap::contains_ptr(slot_ptr) {
-            // It is ok to simply "leak" memory in the early heap,
-            // instead of deallocating it from the early heap.
+            match backend {
+                EarlyHeap(early_heap) => {
+                    // The early heap does not record the slot sizes,
+                    // so the slot size is implied by the layout.
+                    let slot_size = determine_slot_size(layout.size()).bytes();
+                    let mut early_heap_guard = early_heap.lock();
+                    // SAFETY: the memory represented by pointer and size 
+                    // is valid and must have been allocated from the early heap.
+                    unsafe {
+                        early_heap_guard.dealloc(slot_ptr, slot_size)
+                    };
+                }
+                // It is ok to simply "leak" memory in the early heap,
+                // instead of deallocating it from the early heap.
+                SlabCaches(_) => {}
+            }
             return;
         }
 
+        // The foreign check above leaves only the slab slots and the large objects.
+        // The slab records its slot size, which may be larger than the layout implies
+        // (e.g., `alloc_growable` and `alloc_in_class`), or even smaller if
+        // the layout is wrong. So the slot is routed by its slab, ignoring the layout.
+        let Some(slot_size) = slab::slot_size_of(slot_ptr) else {
+            return unsafe { self.dealloc_large(slot_ptr, layout) };
+        };
+        let size_class = SizeClass::from_bytes(slot_size).unwrap();
+
+        // Any memory that is not from the early heap must have been allocated
+        // after the injection, which happens before this deallocation.
+        let SlabCaches(_) = backend else {
+            unreachable!("the memory must be allocated from the early heap before injection");
+        };
+
+        // Check the pointer before reconstructing the slot from it,
+        // so that an interior pointer or a slot of a dead slab never corrupts a free list.
+        // The check is cheap, so it is enabled in release builds, too.
+        if let Err(bad_free) = check_slab_free(slot_ptr, slot_size) {
+            report_bad_free(bad_free);
+            return;
+        }
+
+        // `size_class` is the class of the slab that the slot belongs to.
+        // So each arm below reconstructs the slot with the slot size of its slab.
         let irq_disabled_guard = irq::disable_local();
-        match slot_size {
-            16 => {
+        match size_class {
+            SizeClass::B16 => {
                 let free_slab_slot = unsafe { FreeSlabSlot::<16>::new(slot_ptr) };
-                let slab_meta = free_slab_slot.slab_meta();
-                let recyle_slot_fn = slab_meta.recycle_slot_fn;
-                recycle_slot_fn(free_slab_slot, &irq_disabled_guard);
+                free_slab_slot.recycle(&irq_disabled_guard);
             }
             // ...
-            2048 => {
+            SizeClass::B2048 => {
                 let free_slab_slot = unsafe { FreeSlabSlot::<2048>::new(slot_ptr) };
-                let slab_meta = free_slab_slot.slab_meta();
-                let recyle_slot_fn = slab_meta.recycle_slot_fn;
-                recycle_slot_fn(free_slab_slot, &irq_disabled_guard);
-            }
-            _ => {
-                todo!("deallocate via page allocator")
+                free_slab_slot.recycle(&irq_disabled_guard);
             }
         }
     }
 }
 
-// Determine the slab slot size that matches the object size.
-fn determine_slot_size(&self, obj_size: usize) -> usize {
-    debug_assert!(obj_size <= MAX_SLAB_SLOT_SIZE);
+// Resets a `Once` in place, for `HeapAlloc::reset_for_test`.
+//
+// `Once` offers no way to be reset. So it is overwritten in place.
+//
+// # Safety
+//
+// No one may access the `Once` concurrently,
+// and the old value must need no drop, as it is leaked.
+#[cfg(test)]
+unsafe fn reset_once<T>(once: &Once<T>) {
+    let once_ptr = once as *const Once<T> as *mut Once<T>;
+    // SAFETY: The caller guarantees that no one else is accessing the `Once`.
+    unsafe { ptr::write(once_ptr, Once::new()) };
+}
 
-    let slot_size = if obj_size <= MIN_SLAB_SLOT_SIZE {
-        MIN_SLAB_SLOT_SIZE
-    } else {
-        obj_size.next_power_of_two()
+// Sets a hook of the heap, which can only be set once.
+//
+// A hook may have been observed by the allocation paths of other CPUs,
+// so silently replacing or ignoring it would hide a misconfiguration.
+fn set_hook_once<T>(hook_slot: &Once<T>, hook: T, name: &str) {
+    let mut is_set = false;
+    hook_slot.call_once(|| {
+        is_set = true;
+        hook
+    });
+    if !is_set {
+        panic!("the {} must NOT be set more than once", name);
+    }
+}
+
+static CLOCK: Once<fn() -> u64> = Once::new();
+
+/// Sets the clock source of the heap, which returns monotonic ticks.
+///
+/// The clock is used to record the age of slabs (see `AgeExt`).
+/// It must be cheap and must not allocate memory.
+///
+/// # Panics
+///
+/// This function will panic if it is called more than once.
+pub fn set_clock(clock: fn() -> u64) {
+    set_hook_once(&CLOCK, clock, "clock");
+}
+
+// Returns the current tick, or zero if no clock has been set.
+fn now_tick() -> u64 {
+    CLOCK.get().map_or(0, |clock| clock())
+}
+
+static SLAB_CREATED_HOOK: Once<fn(usize, *const u8, usize)> = Once::new();
+
+/// Sets the hook that is invoked whenever a new slab is created by any slab cache,
+/// with the slot size, the base address and the number of pages of the slab.
+///
+/// The hook observes the heap at the granularity of slabs,
+/// e.g., to account slab pages against a memory zone or to log slab growth.
+/// It runs inside the heap allocator, so it must not allocate memory.
+///
+/// # Panics
+///
+/// This function will panic if it is called more than once.
+pub fn set_slab_created_hook(hook: fn(slot_size: usize, base: *const u8, nr_pages: usize)) {
+    set_hook_once(&SLAB_CREATED_HOOK, hook, "slab-created hook");
+}
+
+// Notifies the slab-created hook, if any, of a new slab.
+fn notify_slab_created(slot_size: usize, base: *const u8, nr_pages: usize) {
+    if let Some(hook) = SLAB_CREATED_HOOK.get() {
+        hook(slot_size, base, nr_pages);
+    }
+}
+
+/// The region of the heap that a pointer belongs to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeapRegion {
+    /// The early heap.
+    EarlyHeap,
+    /// A slab slot of the given size.
+    Slab { slot_size: usize },
+    /// A large object allocated directly from the page allocator.
+    LargeObject,
+}
+
+/// Returns the region of the heap that a pointer belongs to,
+/// or `None` if the pointer does not belong to the heap.
+///
+/// This function never dereferences a pointer that does not belong to a slab.
+/// So it is safe to call with arbitrary pointers, e.g., from a kernel debugger.
+pub fn owner_of(ptr: *mut u8) -> Option<HeapRegion> {
+    if early_heap::contains_ptr(ptr) {
+        return Some(HeapRegion::EarlyHeap);
+    }
+    if large_object::contains_ptr(ptr) {
+        return Some(HeapRegion::LargeObject);
+    }
+    let slot_size = slab::slot_size_of(ptr)?;
+    Some(HeapRegion::Slab { slot_size })
+}
+
+/// Returns the usable size of the live allocation that the pointer refers to,
+/// i.e., the number of bytes that the allocation may grow to in place.
+///
+/// The usable size is the slot size for slab slots,
+/// or the size rounded up to pages for the large objects.
+/// Returns `None` if the pointer does not belong to the heap
+/// or refers to the early heap, which does not record the slot sizes.
+///
+/// Like `owner_of`, this function never dereferences a pointer
+/// that does not belong to a slab.
+pub fn usable_size(ptr: *mut u8) -> Option<usize> {
+    match owner_of(ptr)? {
+        HeapRegion::EarlyHeap => None,
+        HeapRegion::Slab { slot_size } => Some(slot_size),
+        HeapRegion::LargeObject => large_object::nr_pages_of(ptr).map(|nr_pages| nr_pages * PAGE_SIZE),
+    }
+}
+
+/// Returns a read-only view of the slab that contains the object
+/// that the pointer refers to, for debugging the object.
+///
+/// Returns `None` if the pointer does not refer to a slab slot.
+/// Like `owner_of`, this function never dereferences a pointer
+/// that does not belong to a slab.
+pub fn slab_of(ptr: *mut u8) -> Option<SlabView<'static>> {
+    let Some(HeapRegion::Slab { .. }) = owner_of(ptr) else {
+        return None;
     };
+    slab::slab_view_of(ptr)
+}
+
+/// An invalid deallocation detected by the heap.
+#[derive(Clone, Copy, Debug)]
+pub enum BadFree {
+    /// The pointer is not aligned to the slots of the slot size (see `slot_align_of`).
+    Misaligned { ptr: *mut u8, slot_size: usize },
+    /// The pointer does not refer to a slab slot of the expected size.
+    NotSlabSlot { ptr: *mut u8, slot_size: usize, actual: Option<HeapRegion> },
+    /// The pointer refers to a slot of a slab that has been torn down.
+    DeadSlab { ptr: *mut u8 },
+}
+
+/// The errors of the heap.
+///
+/// The error type does not allocate memory, so it can be used
+/// where the heap is unavailable, e.g., in the allocation paths.
+#[derive(Clone, Copy, Debug)]
+pub enum Error {
+    /// The page allocator is out of memory.
+    NoMemory,
+    /// The layout cannot be served by the heap.
+    InvalidLayout(Layout),
+    /// The size is not the slot size of any size class.
+    InvalidSlotSize(usize),
+    /// An invalid deallocation.
+    BadFree(BadFree),
+    /// A slab is corrupted.
+    SlabCorruption(SlabCorruption),
+    /// The page allocator has not been initialized.
+    PageAllocatorNotReady,
+}
+
+impl From<BadFree> for Error {
+    fn from(bad_free: BadFree) -> Self {
+        Self::BadFree(bad_free)
+    }
+}
+
+impl From<SlabCorruption> for Error {
+    fn from(slab_corruption: SlabCorruption) -> Self {
+        Self::SlabCorruption(slab_corruption)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMemory => write!(f, "out of memory"),
+            Self::InvalidLayout(layout) => write!(f, "invalid layout: {}", DisplayLayout(*layout)),
+            Self::InvalidSlotSize(slot_size) => write!(f, "invalid slot size: {}", slot_size),
+            Self::BadFree(bad_free) => write!(f, "invalid deallocation: {:?}", bad_free),
+            Self::SlabCorruption(slab_corruption) => write!(f, "slab corruption: {:?}", slab_corruption),
+            Self::PageAllocatorNotReady => write!(f, "the page allocator is not ready"),
+        }
+    }
+}
+
+static BAD_FREE_HANDLER: Once<fn(BadFree)> = Once::new();
+
+/// Sets the handler for invalid deallocations.
+///
+/// The invalid deallocation is skipped (i.e., the memory is leaked)
+/// after the handler returns. By default, the heap panics.
+///
+/// # Panics
+///
+/// This function will panic if it is called more than once.
+pub fn set_bad_free_handler(handler: fn(BadFree)) {
+    set_hook_once(&BAD_FREE_HANDLER, handler, "bad-free handler");
+}
+
+fn report_bad_free(bad_free: BadFree) {
+    match BAD_FREE_HANDLER.get() {
+        Some(handler) => handler(bad_free),
+        None => panic!("invalid deallocation: {:?}", bad_free),
+    }
+}
+
+static FOREIGN_FREE_HANDLER: Once<fn(*mut u8, Layout)> = Once::new();
+
+/// Sets the handler for the deallocations of foreign pointers,
+/// i.e., the pointers that belong to no region of the heap (see `owner_of`).
+///
+/// The handler may log, ignore, or panic. The memory is leaked
+/// after the handler returns. By default, the heap panics in debug builds
+/// and silently leaks the memory in release builds.
+///
+/// # Panics
+///
+/// This function will panic if it is called more than once.
+pub fn set_foreign_free_handler(handler: fn(*mut u8, Layout)) {
+    set_hook_once(&FOREIGN_FREE_HANDLER, handler, "foreign-free handler");
+}
+
+fn report_foreign_free(ptr: *mut u8, layout: Layout) {
+    match FOREIGN_FREE_HANDLER.get() {
+        Some(handler) => handler(ptr, layout),
+        None => {
+            #[cfg(debug_assertions)]
+            panic!(
+                "deallocation of a pointer that does not belong to the heap: {:p} ({})",
+                ptr,
+                DisplayLayout(layout),
+            );
+        }
+    }
+}
+
+// Checks whether a pointer to be freed refers to a slab slot of `slot_size`.
+fn check_slab_free(ptr: *mut u8, slot_size: usize) -> Result<(), BadFree> {
+    // The slots of colored slabs are less aligned than their sizes.
+    if (ptr as usize) % slot_align_of(slot_size) != 0 {
+        return Err(BadFree::Misaligned { ptr, slot_size });
+    }
+    // Query the region of the pointer, without dereferencing a non-slab pointer.
+    match owner_of(ptr) {
+        Some(HeapRegion::Slab { slot_size: actual_slot_size }) if actual_slot_size == slot_size => Ok(()),
+        actual => Err(BadFree::NotSlabSlot { ptr, slot_size, actual }),
+    }
+}
+
+// Checks that the layout of a deallocation fits the slab slot
+// that the pointer refers to, if any, to catch the callers that pass a wrong layout.
+//
+// The deallocation is routed by the slab of the slot regardless of the layout.
+// But the memory allocated with a layout is in the slot of the size class
+// that the layout implies, so any other class reveals a wrong layout.
+// Only the memory with headroom (see `dealloc_in_larger_slot`) may have a layout
+// that implies a smaller slot, which must still fit in the slot.
+#[cfg(debug_assertions)]
+fn check_dealloc_layout(ptr: *mut u8, layout: Layout, layout_check: LayoutCheck) {
+    let Some(HeapRegion::Slab { slot_size }) = owner_of(ptr) else {
+        return;
+    };
+    let fits_in_slot = layout.size() <= slot_size && layout.align() <= slot_size;
+    let is_layout_valid = match layout_check {
+        LayoutCheck::Exact => fits_in_slot && determine_slot_size(layout.size()).bytes() == slot_size,
+        LayoutCheck::FitsInSlot => fits_in_slot,
+    };
+    assert!(
+        is_layout_valid,
+        "deallocation with a mismatched layout ({}): the slot size is {} bytes",
+        DisplayLayout(layout),
+        slot_size,
+    );
+}
+
+cpu_local! {
+    // Whether the current CPU is running inside the heap allocator.
+    static IN_ALLOCATOR: Cell<bool> = Cell::new(false);
+}
+
+/// Returns whether the current CPU is running inside the heap allocator.
+///
+/// Hooks that run inside the heap allocator (e.g., the memcg hooks)
+/// can use this function to avoid allocating memory.
+/// If they do allocate, the allocation is served by a minimal emergency path.
+pub fn in_allocator() -> bool {
+    let irq_disabled_guard = irq::disable_local();
+    IN_ALLOCATOR.get_with(&irq_disabled_guard).get()
+}
+
+// A guard that marks the current CPU as running inside the heap allocator.
+struct AllocatorEntry<'a> {
+    in_allocator: &'a Cell<bool>,
+}
+
+impl<'a> AllocatorEntry<'a> {
+    // Enters the heap allocator.
+    //
+    // Returns `None` if the current CPU is already inside the heap allocator.
+    fn enter(irq_disabled_guard: &'a DisabledLocalIrqGuard) -> Option<Self> {
+        let in_allocator = IN_ALLOCATOR.get_with(irq_disabled_guard);
+        if in_allocator.replace(true) {
+            return None;
+        }
+        Some(Self { in_allocator })
+    }
+}
+
+impl Drop for AllocatorEntry<'_> {
+    fn drop(&mut self) {
+        self.in_allocator.set(false);
+    }
+}
+
+/// Reports that the heap has failed to allocate memory, e.g., for a `Box`.
+///
+/// The panic points into the allocator rather than the caller,
+/// so the requested layout is included to identify the offending allocation.
+#[cfg_attr(not(test), alloc_error_handler)]
+fn handle_alloc_error(layout: Layout) -> ! {
+    panic!("the heap is out of memory: {}", DisplayLayout(layout));
+}
+
+/// Formats a `Layout` for diagnostic messages.
+///
+/// Panics inside the allocator point into the allocator itself,
+/// so the requested layout is the most
+/// useful information to identify the offending allocation.
+struct DisplayLayout(Layout);
+
+impl fmt::Display for DisplayLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Layout {{ size: {}, align: {} }}", self.0.size(), self.0.align())
+    }
+}
+
+// How a deallocation checks its layout against the slot of the memory
+// (see `check_dealloc_layout`).
+#[derive(Clone, Copy)]
+enum LayoutCheck {
+    // The layout implies the size class of the slot.
+    Exact,
+    // The layout fits in the slot (see `dealloc_in_larger_slot`).
+    FitsInSlot,
+}
+
+// Checks whether an object of `size` bytes may live in a slot of `slot_size`.
+//
+// This holds if the slot size is the size class of the object,
+// or the next larger one (see `alloc_growable`).
+fn is_slot_size_compatible(slot_size: usize, size: usize) -> bool {
+    if size == 0 || size > slab::MAX_SLAB_SLOT_SIZE {
+        return false;
+    }
+    let size_class = determine_slot_size(size);
+    size_class.bytes() == slot_size || size_class.next().is_some_and(|next| next.bytes() == slot_size)
+}
+
+// Determine the slab slot size that matches the object size,
+// which is at most `MAX_SLAB_SLOT_SIZE` (inclusive).
+const fn determine_slot_size(obj_size: usize) -> SizeClass {
+    heap_assert!(full, obj_size <= slab::MAX_SLAB_SLOT_SIZE);
+
+    match SizeClass::for_size(obj_size) {
+        Some(size_class) => size_class,
+        None => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "Layout { size: 3000, align: 64 }")]
+    fn alloc_error_reports_the_layout() {
+        handle_alloc_error(Layout::from_size_align(3000, 64).unwrap());
+    }
+
+    #[test]
+    fn zero_sized_alloc_returns_an_aligned_dangling_pointer() {
+        let _test_heap = TestHeap::lock(16);
+        for align in [1, 8, 4096, 1 << 20] {
+            let layout = Layout::from_size_align(0, align).unwrap();
+            // SAFETY: The heap accepts zero-sized layouts (see `do_alloc`).
+            let ptr = unsafe { HEAP_ALLOC.alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % align, 0);
+            // A dangling pointer belongs to no region of the heap.
+            assert_eq!(usable_size(ptr), None);
+            // SAFETY: The pointer is allocated with the layout above.
+            unsafe { HEAP_ALLOC.dealloc(ptr, layout) };
+        }
+        verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "heap_assert_full")]
+    #[should_panic(expected = "obj_size <= slab::MAX_SLAB_SLOT_SIZE")]
+    fn full_checks_fire_with_heap_assert_full() {
+        // Without the full check, the size would hit the `unreachable!` below it instead.
+        determine_slot_size(slab::MAX_SLAB_SLOT_SIZE + 1);
+    }
+
+    #[test]
+    #[cfg(not(feature = "heap_assert_none"))]
+    #[should_panic(expected = "the cheap check fires")]
+    fn cheap_checks_fire_by_default() {
+        heap_assert!(cheap, 1 + 1 == 3, "the cheap check fires");
+    }
+
+    #[test]
+    #[cfg(feature = "heap_assert_none")]
+    fn checks_compile_out_with_heap_assert_none() {
+        let mut is_evaluated = false;
+        heap_assert!(cheap, { is_evaluated = true; false });
+        heap_assert!(full, { is_evaluated = true; false });
+        assert!(!is_evaluated);
+    }
+
+    #[test]
+    fn every_size_maps_to_the_smallest_fitting_class() {
+        for size in 1..=slab::MAX_SLAB_SLOT_SIZE {
+            let slot_size = determine_slot_size(size).bytes();
+            assert!(slot_size >= size, "size {size}");
+            assert!(SLAB_SIZE_CLASSES.contains(&slot_size), "size {size}");
+            let smallest = SLAB_SIZE_CLASSES.iter().copied().find(|&class_size| class_size >= size);
+            assert_eq!(Some(slot_size), smallest, "size {size}");
+        }
+    }
+
+    #[test]
+    fn guarded_alloc_reserves_and_frees_the_guard_pages() {
+        let _test_heap = TestHeap::lock(16);
+        let nr_free_pages = page_alloc::nr_free_pages();
+        let layout = Layout::from_size_align(PAGE_SIZE + 100, 8).unwrap();
+
+        let ptr = alloc_guarded(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % PAGE_SIZE, 0);
+        // Two pages of the object and a guard page on each side.
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages - 4);
+        // SAFETY: The object is valid for its size.
+        unsafe { ptr.write_bytes(0x5A, layout.size()) };
+        assert_eq!(owner_of(ptr.wrapping_add(layout.size() - 1)), Some(HeapRegion::LargeObject));
+        assert_eq!(usable_size(ptr), Some(2 * PAGE_SIZE));
+        // The guard pages are not a part of the object.
+        assert_eq!(owner_of(ptr.wrapping_sub(1)), None);
+        assert_eq!(owner_of(ptr.wrapping_add(2 * PAGE_SIZE)), None);
+
+        // SAFETY: The pointer is allocated by `alloc_guarded` with the layout.
+        unsafe { dealloc_guarded(ptr, layout) };
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages);
+        verify_no_leaks().unwrap();
+    }
+
+    #[test]
+    fn pages_are_allocated_as_a_large_object() {
+        let _test_heap = TestHeap::lock(16);
+        let nr_free_pages = page_alloc::nr_free_pages();
+
+        let ptr = alloc_pages(3).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % PAGE_SIZE, 0);
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages - 3);
+        // SAFETY: The pages are valid for their size.
+        unsafe { ptr.as_ptr().write_bytes(0x5A, 3 * PAGE_SIZE) };
+        assert_eq!(owner_of(ptr.as_ptr()), Some(HeapRegion::LargeObject));
+        assert_eq!(owner_of(ptr.as_ptr().wrapping_add(3 * PAGE_SIZE - 1)), Some(HeapRegion::LargeObject));
+        assert_eq!(usable_size(ptr.as_ptr()), Some(3 * PAGE_SIZE));
+
+        // SAFETY: The pages are allocated above and not used afterwards.
+        unsafe { free_pages(ptr, 3) };
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages);
+        assert_eq!(owner_of(ptr.as_ptr()), None);
+        assert!(alloc_pages(0).is_none());
+    }
 }
@@ -0,0 +1,123 @@
+//! The per-CPU stashes of spare pages for the slow path of slabs.
+//!
+//! The latency of allocating a slab mostly comes from the page allocator.
+//! So each CPU keeps a small stash of free pages, which is refilled to its size
+//! from the page allocator, one page at a time, when it runs empty.
+//! The refills gather the trips to the page allocator on a few slow paths
+//! (or ahead of time, see [`warm_page_cache`]). A slab of one page takes
+//! its page from the stash of the current CPU (see `Slab::alloc`),
+//! without entering the page allocator. The slabs of multiple pages
+//! need contiguous and aligned pages, so they bypass the stashes.
+//!
+//! The stashes are disabled by default (see [`set_page_stash_size`]).
+
+// The maximum number of pages in the stash of a CPU.
+const MAX_PAGE_STASH_SIZE: usize = 32;
+
+// The number of pages that the stash of each CPU is refilled to.
+// Zero means that the stashes are disabled.
+static PAGE_STASH_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+struct PageStash {
+    pages: [Option<NonNull<u8>>; MAX_PAGE_STASH_SIZE],
+    len: usize,
+}
+
+impl PageStash {
+    const fn new() -> Self {
+        Self {
+            pages: [None; MAX_PAGE_STASH_SIZE],
+            len: 0,
+        }
+    }
+
+    // Refills the stash to `nr_pages` pages, allocating them one by one.
+    //
+    // The pages are allocated separately, as each page of the stash
+    // is later freed on its own, along with its slab (see `Slab::drop`).
+    //
+    // Stops early if the page allocator is out of memory.
+    fn refill(&mut self, nr_pages: usize) {
+        while self.len < nr_pages {
+            let Some(page) = page_alloc::alloc_pages(1) else {
+                return;
+            };
+            self.pages[self.len] = Some(page);
+            self.len += 1;
+        }
+    }
+
+    fn pop(&mut self) -> Option<NonNull<u8>> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.pages[self.len].take()
+    }
+}
+
+cpu_local! {
+    static PAGE_STASH: RefCell<PageStash> = RefCell::new(PageStash::new());
+}
+
+/// Sets the number of spare pages that each CPU stashes for the slabs of one page.
+///
+/// Zero disables the stashes, which is the default. Shrinking the size
+/// does not return the stashed pages, which are consumed by later slabs.
+///
+/// # Panics
+///
+/// This function will panic if `nr_pages` exceeds the maximum size of a stash (32).
+pub fn set_page_stash_size(nr_pages: usize) {
+    assert!(
+        nr_pages <= MAX_PAGE_STASH_SIZE,
+        "the page stash size must be at most {}, got {}",
+        MAX_PAGE_STASH_SIZE,
+        nr_pages
+    );
+    PAGE_STASH_SIZE.store(nr_pages, Relaxed);
+}
+
+/// Fills the page stash of the current CPU up to its size (see `set_page_stash_size`),
+/// so that the upcoming slabs of one page are allocated without
+/// entering the page allocator, e.g., before a latency-critical section.
+pub fn warm_page_cache() {
+    let irq_disabled_guard = irq::disable_local();
+    let mut page_stash = PAGE_STASH.get_with(&irq_disabled_guard).borrow_mut();
+    page_stash.refill(PAGE_STASH_SIZE.load(Relaxed));
+}
+
+/// Allocates a page for a slab of one page, from the stash of the current CPU.
+///
+/// The stash is refilled to its size when it is empty. If the stashes are disabled,
+/// the page is allocated from the page allocator directly.
+pub(super) fn alloc_page() -> Option<NonNull<u8>> {
+    let nr_stashed_pages = PAGE_STASH_SIZE.load(Relaxed);
+    if nr_stashed_pages == 0 {
+        return page_alloc::alloc_pages(1);
+    }
+
+    let irq_disabled_guard = irq::disable_local();
+    let mut page_stash = PAGE_STASH.get_with(&irq_disabled_guard).borrow_mut();
+    if page_stash.len == 0 {
+        page_stash.refill(nr_stashed_pages);
+    }
+    page_stash.pop()
+}
+
+// Empties the page stashes of all CPUs and disables them,
+// for `HeapAlloc::reset_for_test`.
+//
+// The stashed pages belong to the page source of the previous test,
+// so they are forgotten instead of being returned to the page allocator.
+//
+// # Safety
+//
+// No one may use the page stashes concurrently.
+#[cfg(test)]
+pub(super) unsafe fn reset_for_test() {
+    PAGE_STASH_SIZE.store(0, Relaxed);
+    for cpu_i in 0..cpu::num_cpus() {
+        *PAGE_STASH.get_on_cpu(cpu_i).borrow_mut() = PageStash::new();
+    }
+}
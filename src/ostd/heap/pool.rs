@@ -0,0 +1,193 @@
+//! Typed object pools on top of slab slots.
+
+/// A pool of objects of type `T`, which recycles slab slots of `SLOT_SIZE`.
+///
+/// The slots of the released objects are kept on the free list of the pool,
+/// instead of being freed to the slab allocator. So acquiring an object
+/// from a warm pool neither goes through the global heap nor the slab allocator.
+///
+/// An object can also be recycled by resetting it in place
+/// (see `PoolBox::recycle_reset`), which keeps the object initialized
+/// for the next `acquire_with`, avoiding the drop and the reconstruction.
+///
+/// `SLOT_SIZE` must be the size class of `T`, i.e.,
+/// `SizeClass::for_size(size_of::<T>())`.
+pub struct SlabPool<T, const SLOT_SIZE: usize> {
+    slab_allocator: &'static dyn SlabSlotAlloc<SLOT_SIZE>,
+    free_slots: SpinLock<FreeSlabSlotList<SLOT_SIZE>>,
+    reset_objs: SpinLock<ResetObjs<T>>,
+    phantom: PhantomData<T>,
+}
+
+// The maximum number of the reset objects kept by a pool.
+const MAX_NR_RESET_OBJS: usize = 64;
+
+// The objects that have been reset in place, which are still initialized.
+//
+// The links of a `FreeSlabSlotList` would overwrite the objects,
+// so the reset objects are kept in a fixed-size array instead.
+// The objects beyond the capacity are dropped and their slots are freed.
+struct ResetObjs<T> {
+    objs: [Option<NonNull<T>>; MAX_NR_RESET_OBJS],
+    len: usize,
+}
+
+// SAFETY: The objects are owned by the pool, like `Box<T>`.
+unsafe impl<T: Send> Send for ResetObjs<T> {}
+
+impl<T, const SLOT_SIZE: usize> SlabPool<T, SLOT_SIZE> {
+    /// Creates an empty pool whose slots are allocated from the given slab allocator.
+    pub const fn new(slab_allocator: &'static dyn SlabSlotAlloc<SLOT_SIZE>) -> Self {
+        static_assert!(super::determine_slot_size(mem::size_of::<T>()).bytes() == SLOT_SIZE);
+
+        Self {
+            slab_allocator,
+            free_slots: SpinLock::new(FreeSlabSlotList::new()),
+            reset_objs: SpinLock::new(ResetObjs {
+                objs: [None; MAX_NR_RESET_OBJS],
+                len: 0,
+            }),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Acquires an object from the pool, initialized with `obj`.
+    ///
+    /// Returns `None` if the pool is empty and the slab allocator
+    /// is out of memory.
+    pub fn acquire(&self, obj: T) -> Option<PoolBox<'_, T, SLOT_SIZE>> {
+        let irq_disabled_guard = irq::disable_local();
+        let free_slot = match self.free_slots.lock().pop() {
+            Some(free_slot) => free_slot,
+            None => self.slab_allocator.alloc(&irq_disabled_guard)?,
+        };
+        Some(PoolBox {
+            obj: ManuallyDrop::new(free_slot.into_box(obj)),
+            pool: self,
+        })
+    }
+
+    /// Acquires an object that has been reset in place, if any.
+    /// Otherwise, acquires an object initialized with `init`.
+    ///
+    /// Returns `None` if a new object is needed, but the pool is empty
+    /// and the slab allocator is out of memory.
+    pub fn acquire_with(&self, init: impl FnOnce() -> T) -> Option<PoolBox<'_, T, SLOT_SIZE>> {
+        let reset_obj = {
+            let _irq_disabled_guard = irq::disable_local();
+            let mut reset_objs = self.reset_objs.lock();
+            match reset_objs.len {
+                0 => None,
+                len => {
+                    reset_objs.len -= 1;
+                    reset_objs.objs[len - 1].take()
+                }
+            }
+        };
+        let Some(obj_ptr) = reset_obj else {
+            return self.acquire(init());
+        };
+        Some(PoolBox {
+            // SAFETY: The reset object is initialized and owned by the pool,
+            // which was leaked from a `Box<T>` in `PoolBox::recycle_reset`.
+            obj: ManuallyDrop::new(unsafe { Box::from_raw(obj_ptr.as_ptr()) }),
+            pool: self,
+        })
+    }
+
+    // Keeps an object that has been reset in place.
+    //
+    // Returns the object back if the pool keeps too many reset objects.
+    fn park_reset(&self, boxed_obj: Box<T>) -> Result<(), Box<T>> {
+        let _irq_disabled_guard = irq::disable_local();
+        let mut reset_objs = self.reset_objs.lock();
+        let len = reset_objs.len;
+        if len == MAX_NR_RESET_OBJS {
+            return Err(boxed_obj);
+        }
+        reset_objs.objs[len] = Some(NonNull::from(Box::leak(boxed_obj)));
+        reset_objs.len += 1;
+        Ok(())
+    }
+
+    fn release(&self, free_slot: FreeSlabSlot<SLOT_SIZE>) {
+        let _irq_disabled_guard = irq::disable_local();
+        self.free_slots.lock().push(free_slot);
+    }
+}
+
+impl<T, const SLOT_SIZE: usize> Drop for SlabPool<T, SLOT_SIZE> {
+    // No object acquired from the pool is alive, as it borrows the pool.
+    // So the pool owns all the reset objects and the slots on its free list,
+    // which are recycled to their slabs.
+    fn drop(&mut self) {
+        let irq_disabled_guard = irq::disable_local();
+        let mut reset_objs = self.reset_objs.lock();
+        let len = mem::replace(&mut reset_objs.len, 0);
+        for obj_ptr in reset_objs.objs[..len].iter_mut().filter_map(Option::take) {
+            // SAFETY: The reset object is initialized and owned by the pool,
+            // which was leaked from a `Box<T>` in `PoolBox::recycle_reset`.
+            let boxed_obj = unsafe { Box::from_raw(obj_ptr.as_ptr()) };
+            FreeSlabSlot::from_box(boxed_obj).recycle(&irq_disabled_guard);
+        }
+        drop(reset_objs);
+
+        let mut free_slots = self.free_slots.lock();
+        while let Some(free_slot) = free_slots.pop() {
+            free_slot.recycle(&irq_disabled_guard);
+        }
+    }
+}
+
+/// An object acquired from a `SlabPool`.
+///
+/// When dropped, the object is dropped and its slot is returned to the pool.
+pub struct PoolBox<'a, T, const SLOT_SIZE: usize> {
+    obj: ManuallyDrop<Box<T>>,
+    pool: &'a SlabPool<T, SLOT_SIZE>,
+}
+
+impl<T, const SLOT_SIZE: usize> PoolBox<'_, T, SLOT_SIZE> {
+    /// Returns the object to the pool after resetting it in place with `reset_fn`,
+    /// instead of dropping it.
+    ///
+    /// The reset object is handed out again by `SlabPool::acquire_with`.
+    /// If the pool already keeps too many reset objects,
+    /// the object is dropped like a regular release.
+    pub fn recycle_reset(mut self, reset_fn: impl FnOnce(&mut T)) {
+        reset_fn(&mut self.obj);
+        // SAFETY: The box is never used after being taken,
+        // as the destructor is skipped.
+        let boxed_obj = unsafe { ManuallyDrop::take(&mut self.obj) };
+        let pool = self.pool;
+        mem::forget(self);
+
+        if let Err(boxed_obj) = pool.park_reset(boxed_obj) {
+            pool.release(FreeSlabSlot::from_box(boxed_obj));
+        }
+    }
+}
+
+impl<T, const SLOT_SIZE: usize> Deref for PoolBox<'_, T, SLOT_SIZE> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.obj
+    }
+}
+
+impl<T, const SLOT_SIZE: usize> DerefMut for PoolBox<'_, T, SLOT_SIZE> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.obj
+    }
+}
+
+impl<T, const SLOT_SIZE: usize> Drop for PoolBox<'_, T, SLOT_SIZE> {
+    fn drop(&mut self) {
+        // SAFETY: The box is never used after being taken.
+        let boxed_obj = unsafe { ManuallyDrop::take(&mut self.obj) };
+        // Drop the object in place, keeping its slot in use.
+        let free_slot = FreeSlabSlot::from_box(boxed_obj);
+        self.pool.release(free_slot);
+    }
+}
@@ -0,0 +1,83 @@
+//! The profiling of heap allocations by call-site tags.
+//!
+//! A caller can tag its allocations with [`alloc_tagged`](super::alloc_tagged),
+//! and the number of allocations is counted per tag and per size class.
+//! The untagged allocations are counted under the empty tag.
+//!
+//! The counters are kept in a fixed-size table, so that profiling
+//! never allocates memory from the heap. The allocations of the tags
+//! that do not fit in the table are not counted.
+
+const MAX_NR_TAGS: usize = 64;
+
+#[derive(Clone, Copy)]
+struct TagCounters {
+    tag: &'static str,
+    nr_allocs: [usize; NR_SLAB_CLASSES],
+}
+
+impl TagCounters {
+    fn total(&self) -> usize {
+        self.nr_allocs.iter().sum()
+    }
+}
+
+static TAG_COUNTERS: SpinLock<[Option<TagCounters>; MAX_NR_TAGS]> =
+    SpinLock::new([None; MAX_NR_TAGS]);
+
+/// Counts an allocation of the size class under the tag.
+pub(super) fn record(tag: &'static str, size_class: SizeClass) {
+    let mut tag_counters = TAG_COUNTERS.lock();
+    // Tags are compared by contents, as the same literal may have different addresses.
+    let entry = match tag_counters.iter().position(|entry| entry.is_some_and(|entry| entry.tag == tag)) {
+        Some(index) => &mut tag_counters[index],
+        None => {
+            let Some(entry) = tag_counters.iter_mut().find(|entry| entry.is_none()) else {
+                return;
+            };
+            *entry = Some(TagCounters {
+                tag,
+                nr_allocs: [0; NR_SLAB_CLASSES],
+            });
+            entry
+        }
+    };
+    entry.as_mut().unwrap().nr_allocs[size_class.index()] += 1;
+}
+
+/// Fills `top` with the tags of the most allocations, in descending order,
+/// along with the numbers of their allocations over all size classes.
+///
+/// Returns the number of the filled entries.
+pub fn heap_profile_top(top: &mut [(&'static str, usize)]) -> usize {
+    // Copy the table to avoid sorting with the lock held.
+    let tag_counters = *TAG_COUNTERS.lock();
+
+    let mut nr_filled = 0;
+    for entry in tag_counters.iter().flatten() {
+        let total = entry.total();
+        // Insertion sort into the bounded output.
+        let mut i = nr_filled;
+        while i > 0 && top[i - 1].1 < total {
+            if i < top.len() {
+                top[i] = top[i - 1];
+            }
+            i -= 1;
+        }
+        if i < top.len() {
+            top[i] = (entry.tag, total);
+            nr_filled = (nr_filled + 1).min(top.len());
+        }
+    }
+    nr_filled
+}
+
+/// Returns the number of allocations counted under the tag, for a size class.
+pub fn heap_profile_count(tag: &'static str, size_class: SizeClass) -> usize {
+    TAG_COUNTERS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|entry| entry.tag == tag)
+        .map_or(0, |entry| entry.nr_allocs[size_class.index()])
+}
@@ -0,0 +1,151 @@
+//! The size classes of slab slots.
+
+/// A size class of slab slots.
+///
+/// Each size class corresponds to one slab allocator in `SlabAllocators`.
+#[repr(usize)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum SizeClass {
+    B16 = 16,
+    B32 = 32,
+    B64 = 64,
+    B128 = 128,
+    B256 = 256,
+    B512 = 512,
+    B1024 = 1024,
+    B2048 = 2048,
+}
+
+/// The number of slab size classes.
+pub const NR_SLAB_CLASSES: usize = SizeClass::ALL.len();
+
+/// The slot sizes of all size classes, in ascending order.
+pub const SLAB_SIZE_CLASSES: [usize; NR_SLAB_CLASSES] = {
+    let mut slot_sizes = [0; NR_SLAB_CLASSES];
+    let mut i = 0;
+    while i < NR_SLAB_CLASSES {
+        slot_sizes[i] = SizeClass::ALL[i].bytes();
+        i += 1;
+    }
+    slot_sizes
+};
+
+impl SizeClass {
+    /// All size classes, in ascending order.
+    pub const ALL: [SizeClass; 8] = [
+        SizeClass::B16,
+        SizeClass::B32,
+        SizeClass::B64,
+        SizeClass::B128,
+        SizeClass::B256,
+        SizeClass::B512,
+        SizeClass::B1024,
+        SizeClass::B2048,
+    ];
+
+    /// Returns the smallest size class that can hold an object of `size` bytes.
+    ///
+    /// Returns `None` if the object is too large for any size class.
+    pub const fn for_size(size: usize) -> Option<SizeClass> {
+        let mut i = 0;
+        while i < Self::ALL.len() {
+            if size <= Self::ALL[i].bytes() {
+                return Some(Self::ALL[i]);
+            }
+            i += 1;
+        }
+        None
+    }
+
+    /// Returns the size class whose slot size is exactly `slot_size` bytes.
+    pub const fn from_bytes(slot_size: usize) -> Option<SizeClass> {
+        match Self::for_size(slot_size) {
+            Some(size_class) if size_class.bytes() == slot_size => Some(size_class),
+            _ => None,
+        }
+    }
+
+    /// Returns the slot size in bytes.
+    pub const fn bytes(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the next larger size class, or `None` for the largest one.
+    pub const fn next(self) -> Option<SizeClass> {
+        let index = self.index();
+        if index + 1 < Self::ALL.len() {
+            Some(Self::ALL[index + 1])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of the size class, starting from zero for the smallest one.
+    pub const fn index(self) -> usize {
+        (self.bytes().trailing_zeros() - SizeClass::B16.bytes().trailing_zeros()) as usize
+    }
+}
+
+/// Returns the index of the size class whose slot size is `slot_size`.
+///
+/// This function is usable in const context, so code that knows
+/// the slot size at compile time (e.g., `FreeSlabSlot::<SLOT_SIZE>::INDEX`)
+/// pays no runtime cost for mapping the slot size to the index.
+///
+/// # Panics
+///
+/// This function will panic (or fail to compile in const context)
+/// if `slot_size` is not the slot size of a size class.
+pub const fn size_class_index(slot_size: usize) -> usize {
+    match SizeClass::from_bytes(slot_size) {
+        Some(size_class) => size_class.index(),
+        None => panic!("the slot size must be one of the size classes"),
+    }
+}
+static_assert!({
+    let mut is_consistent = true;
+    let mut i = 0;
+    while i < NR_SLAB_CLASSES {
+        if size_class_index(SizeClass::ALL[i].bytes()) != i {
+            is_consistent = false;
+        }
+        i += 1;
+    }
+    is_consistent
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_map_to_the_smallest_fitting_class() {
+        let cases = [
+            (1, Some(SizeClass::B16)),
+            (16, Some(SizeClass::B16)),
+            (17, Some(SizeClass::B32)),
+            (40, Some(SizeClass::B64)),
+            (100, Some(SizeClass::B128)),
+            (256, Some(SizeClass::B256)),
+            (1000, Some(SizeClass::B1024)),
+            (2028, Some(SizeClass::B2048)),
+            (2048, Some(SizeClass::B2048)),
+            (2049, None),
+        ];
+        for (size, size_class) in cases {
+            assert_eq!(SizeClass::for_size(size), size_class, "size {size}");
+        }
+    }
+
+    #[test]
+    fn slot_sizes_convert_to_and_from_classes() {
+        for (i, &size_class) in SizeClass::ALL.iter().enumerate() {
+            assert_eq!(size_class.index(), i);
+            assert_eq!(SLAB_SIZE_CLASSES[i], size_class.bytes());
+            assert_eq!(SizeClass::from_bytes(size_class.bytes()), Some(size_class));
+            assert_eq!(SizeClass::from_bytes(size_class.bytes() + 1), None);
+            assert_eq!(size_class.next(), SizeClass::ALL.get(i + 1).copied());
+        }
+    }
+}
+
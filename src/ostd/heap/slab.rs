@@ -2,23 +2,473 @@
 //! that are divided into a number of fixed-sized slots,
 //! each of which can be used as the storage for an object
 //! whose size is no greater than the slot size.
+//!
+//! With the `slab_ro_meta` feature, the immutable parts of the metadata
+//! of a slab (see `SlabMetaHeader`) reside in the first page of the slab,
+//! which is mapped read-only after the slab is initialized,
+//! so that a heap overflow cannot hijack the recycle function of the slab.
+//! The mutable parts of the metadata follow on the second page.
+//!
+//! With the `slab_coloring` feature, the slots of a slab may start at
+//! a color, i.e., an offset of some cache lines after the metadata region,
+//! so that the slots of different slabs of a class do not always map to
+//! the same cache sets. The slots of such slabs are only aligned to
+//! `SLAB_COLOR_ALIGN` (see `Slab::alloc_aligned`).
+
+use super::{SizeClass, NR_SLAB_CLASSES, PAGE_SIZE};
+
+/// The byte pattern that fills a slot when it is handed out as raw memory,
+/// with the `slab_alloc_poison` feature.
+pub const ALLOC_POISON_BYTE: u8 = 0xCC;
+
+/// The smallest slot size of slabs.
+pub const MIN_SLAB_SLOT_SIZE: usize = 16;
+/// The largest slot size of slabs.
+pub const MAX_SLAB_SLOT_SIZE: usize = 2048;
+
+/// The maximum number of pages of a slab.
+pub const MAX_PAGES_PER_SLAB: usize = 8;
+
+// The default number of pages per slab, for each size class.
+//
+// Smaller size classes have larger slabs by default
+// so that the slab metadata is amortized over more slots.
+const DEFAULT_PAGES_PER_SLAB: [usize; NR_SLAB_CLASSES] = [
+    4, // 16 bytes
+    4, // 32 bytes
+    2, // 64 bytes
+    2, // 128 bytes
+    1, // 256 bytes
+    1, // 512 bytes
+    1, // 1024 bytes
+    1, // 2048 bytes
+];
+
+// The number of pages per slab, for each size class (see `set_pages_per_slab`).
+static PAGES_PER_SLAB: [AtomicUsize; NR_SLAB_CLASSES] = {
+    let mut pages_per_slab = [const { AtomicUsize::new(0) }; NR_SLAB_CLASSES];
+    let mut size_class_i = 0;
+    while size_class_i < NR_SLAB_CLASSES {
+        pages_per_slab[size_class_i] = AtomicUsize::new(DEFAULT_PAGES_PER_SLAB[size_class_i]);
+        size_class_i += 1;
+    }
+    pages_per_slab
+};
+
+// Whether any slab of a size class has been allocated,
+// after which the number of pages per slab of the class is fixed.
+static HAS_ALLOCATED_SLABS: [AtomicBool; NR_SLAB_CLASSES] =
+    [const { AtomicBool::new(false) }; NR_SLAB_CLASSES];
+
+// The mask that recovers the base of a slab from the address of its slot,
+// for each size class (see `slab_base_of`).
+//
+// The mask is cached when the number of pages per slab of the class is fixed,
+// i.e., before the first slab of the class is allocated, so that the hot paths
+// need not recompute it from `PAGES_PER_SLAB`.
+static SLAB_BASE_MASKS: [AtomicUsize; NR_SLAB_CLASSES] =
+    [const { AtomicUsize::new(0) }; NR_SLAB_CLASSES];
+
+/// Sets the number of pages per slab for a size class.
+///
+/// # Panics
+///
+/// This method will panic if `nr_pages` is not a power of two,
+/// exceeds `MAX_PAGES_PER_SLAB`, or any slab of the size class
+/// has already been allocated.
+pub fn set_pages_per_slab(size_class: SizeClass, nr_pages: usize) {
+    assert!(nr_pages.is_power_of_two() && nr_pages <= MAX_PAGES_PER_SLAB);
+    assert!(
+        !HAS_ALLOCATED_SLABS[size_class.index()].load(Acquire),
+        "the number of pages per slab must be set before any slab of the class is allocated"
+    );
+    PAGES_PER_SLAB[size_class.index()].store(nr_pages, Release);
+}
+
+/// Returns the number of pages per slab for a size class.
+///
+/// With the `slab_ro_meta` feature, a slab has at least two pages,
+/// as its first page is occupied by the read-only header.
+pub fn pages_per_slab(size_class: SizeClass) -> usize {
+    PAGES_PER_SLAB[size_class.index()].load(Relaxed).max(MIN_PAGES_PER_SLAB)
+}
+
+// The minimum number of pages of a slab, which has room for
+// the metadata region and at least one slot.
+#[cfg(not(feature = "slab_ro_meta"))]
+const MIN_PAGES_PER_SLAB: usize = 1;
+#[cfg(feature = "slab_ro_meta")]
+const MIN_PAGES_PER_SLAB: usize = 2;
+
+/// The granularity of the colors of slabs, which is also the alignment
+/// of the slots of colored slabs (see `Slab::is_colored`).
+pub const SLAB_COLOR_ALIGN: usize = CACHE_LINE_SIZE;
+
+/// Returns the alignment that every slot of `slot_size` is guaranteed to have.
+///
+/// With the `slab_coloring` feature, it is `SLAB_COLOR_ALIGN` for the size classes
+/// that can be colored. Otherwise, the slots are aligned to their sizes.
+pub const fn slot_align_of(slot_size: usize) -> usize {
+    if cfg!(feature = "slab_coloring") && slot_size > SLAB_COLOR_ALIGN {
+        SLAB_COLOR_ALIGN
+    } else {
+        slot_size
+    }
+}
+
+// The color of the next colored slab of each size class,
+// in units of `SLAB_COLOR_ALIGN` (see `Slab::slot_layout_of`).
+static NEXT_SLAB_COLORS: [AtomicUsize; NR_SLAB_CLASSES] =
+    [const { AtomicUsize::new(0) }; NR_SLAB_CLASSES];
+
+// The number of slabs that have been reclaimed (see `Slab::try_begin_reclaim`).
+static HEAP_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+// The number of slabs that could not be allocated as the page allocator is out of memory.
+static NR_SLAB_OOMS: AtomicU64 = AtomicU64::new(0);
+
+// Returns the number of slabs that could not be allocated
+// as the page allocator is out of memory.
+//
+// The allocation path compares two snapshots to tell whether a failure is
+// due to the lack of pages, or due to a policy of the slab cache (see `HeapAlloc::do_alloc`).
+pub(super) fn nr_slab_ooms() -> u64 {
+    NR_SLAB_OOMS.load(Relaxed)
+}
+
+/// Returns the reclaim epoch of the heap, which advances each time
+/// a slab is claimed for reclamation (see `Slab::try_begin_reclaim`).
+///
+/// The epoch is for diagnostics, e.g., to tell whether any slab
+/// has been reclaimed between two snapshots of the heap.
+pub fn heap_epoch() -> u64 {
+    HEAP_EPOCH.load(Relaxed)
+}
+
+// The number of free pages below which the memory is considered low.
+// Zero means that the admission of new slabs is never restricted.
+static LOW_MEMORY_WATERMARK: AtomicUsize = AtomicUsize::new(0);
+
+/// Sets the low memory watermark, in the number of free pages.
+///
+/// When the page allocator has fewer free pages than the watermark,
+/// a slab cache can only allocate a new slab if all of its slabs are full
+/// (see `admits_new_slab`). Otherwise, the allocation of the slab fails fast,
+/// so that the caller can retry with the free slots cached elsewhere,
+/// leaving the scarce pages to the slab caches that really need them.
+pub fn set_low_memory_watermark(nr_pages: usize) {
+    LOW_MEMORY_WATERMARK.store(nr_pages, Relaxed);
+}
+
+/// Decides whether a slab cache may allocate a new slab,
+/// given the number of its slabs that still have free slots.
+///
+/// The slabs are counted per cache, not per size class, as the free slots
+/// of another cache (e.g., of another CPU) cannot serve the allocations of this cache.
+/// A slab cache should call this before `Slab::alloc` when it grows.
+pub fn admits_new_slab(nr_nonfull_slabs: usize) -> bool {
+    let watermark = LOW_MEMORY_WATERMARK.load(Relaxed);
+    if watermark == 0 || page_alloc::nr_free_pages() >= watermark {
+        return true;
+    }
+    nr_nonfull_slabs == 0
+}
+
+// Resets the global states of the slabs, for `HeapAlloc::reset_for_test`.
+//
+// The slabs of the previous test are leaked along with their page source,
+// so they no longer count as allocated slabs.
+#[cfg(test)]
+pub(super) fn reset_for_test() {
+    for size_class_i in 0..NR_SLAB_CLASSES {
+        // A test may have configured the size of the slabs of a class.
+        PAGES_PER_SLAB[size_class_i].store(DEFAULT_PAGES_PER_SLAB[size_class_i], Relaxed);
+        HAS_ALLOCATED_SLABS[size_class_i].store(false, Release);
+        SLAB_BASE_MASKS[size_class_i].store(0, Relaxed);
+        NEXT_SLAB_COLORS[size_class_i].store(0, Relaxed);
+    }
+    NR_SLAB_OOMS.store(0, Relaxed);
+    LOW_MEMORY_WATERMARK.store(0, Relaxed);
+}
+
+// Returns the size of a slab whose slot size is `slot_size`.
+//
+// A slab is aligned to its size, so that the slab (as well as its metadata)
+// containing a slot can be located by masking the slot address.
+fn slab_size_of(slot_size: usize) -> usize {
+    let size_class = SizeClass::from_bytes(slot_size).unwrap();
+    pages_per_slab(size_class) * PAGE_SIZE
+}
+
+/// Returns the slot size of the slab that contains the pointer,
+/// or `None` if the pointer does not belong to any slab.
+///
+/// The pointer is never dereferenced unless it belongs to a slab.
+pub(super) fn slot_size_of(ptr: *const u8) -> Option<usize> {
+    let slab_base = slab_base_of_ptr(ptr)?;
+    // SAFETY: The slab metadata resides at `slab_meta_addr` of the slab.
+    let slab_meta = unsafe { &*(slab_meta_addr(slab_base) as *const SlabMeta<()>) };
+    Some(slab_meta.slot_size as usize)
+}
+
+/// Returns a read-only view of the slab that contains the pointer,
+/// or `None` if the pointer does not belong to any slab.
+pub(super) fn slab_view_of(ptr: *const u8) -> Option<SlabView<'static>> {
+    let slab_base = slab_base_of_ptr(ptr)?;
+    // SAFETY: The slab metadata resides at `slab_meta_addr` of the slab.
+    let slab_meta = unsafe { &*(slab_meta_addr(slab_base) as *const SlabMeta<()>) };
+    Some(SlabView { slab_meta })
+}
+
+/// A read-only view of a slab, for inspection.
+///
+/// The view reads the metadata of the slab, whose type has been erased.
+/// It must only be used while the slab is alive, e.g., while the object
+/// that the view is obtained from has not been freed.
+/// The numbers are snapshots and may be stale as soon as they are returned.
+#[derive(Clone, Copy)]
+pub struct SlabView<'a> {
+    slab_meta: &'a SlabMeta<()>,
+}
+
+impl SlabView<'_> {
+    /// Returns the base address of the slab.
+    pub fn base_ptr(&self) -> *const u8 {
+        self.slab_meta.slab_base_ptr()
+    }
+
+    /// Returns the slot size of the slab.
+    pub fn slot_size(&self) -> usize {
+        self.slab_meta.slot_size as usize
+    }
+
+    /// Returns the number of the in-use slots, including the borrowed ones.
+    pub fn nr_inuse_slots(&self) -> usize {
+        self.slab_meta.nr_inuse_slots.load(Relaxed) as usize
+    }
+
+    /// Returns the total number of slots of the slab.
+    pub fn nr_total_slots(&self) -> usize {
+        self.slab_meta.nr_total_slots as usize
+    }
+
+    /// Returns whether the slots of the slab are shifted by a color
+    /// (see `Slab::is_colored`).
+    pub fn is_colored(&self) -> bool {
+        self.slab_meta.is_colored
+    }
+
+    /// Returns the extension of the slab.
+    pub fn extension(&self) -> &dyn Any {
+        self.slab_meta.extension_as_any()
+    }
+}
+
+impl fmt::Debug for SlabView<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SlabView")
+            .field("base", &self.base_ptr())
+            .field("slot_size", &self.slot_size())
+            .field("nr_total", &self.nr_total_slots())
+            .field("nr_inuse", &self.nr_inuse_slots())
+            .finish()
+    }
+}
+
+// Returns the base address of the slab of `SLOT_SIZE` that contains the slot.
+//
+// A slab is aligned to its size, which may span multiple pages (see `slab_size_of`).
+// So the base is found by masking the slot address with the cached mask of the class.
+// The slab metadata resides at the base.
+//
+// All the conversions from a slot to its slab must go through this function.
+fn slab_base_of<const SLOT_SIZE: usize>(slot_ptr: *const u8) -> *const u8 {
+    let slab_base_mask = SLAB_BASE_MASKS[FreeSlabSlot::<SLOT_SIZE>::INDEX].load(Relaxed);
+    heap_assert!(full, slab_base_mask == !(slab_size_of(SLOT_SIZE) - 1));
+    (slot_ptr as usize & slab_base_mask) as *const u8
+}
+
+// Returns the base address of the slab that contains the pointer,
+// or `None` if the pointer does not belong to any slab.
+//
+// This function only queries the page metadata maintained by
+// the page allocator, with which `Slab::alloc` tags every page of a slab.
+// So the pointer is never dereferenced.
+fn slab_base_of_ptr(ptr: *const u8) -> Option<*const u8> {
+    match page_alloc::page_usage_of(ptr)? {
+        PageUsage::Slab { slab_base } => Some(slab_base as *const u8),
+        _ => None,
+    }
+}
+
 pub struct Slab<const SLOT_SIZE: usize, Ext> {
     page: NonNull<u8>,
 }
 
 impl<const SLOT_SIZE: usize, Ext> Slab<SLOT_SIZE, Ext> {
-    /// Allocate a page-sized slab with the user-given slab extension.
+    /// Allocate a slab with the user-given slab extension.
+    ///
+    /// The number of pages of the slab is configured per size class
+    /// (see `set_pages_per_slab`).
+    ///
+    /// Returns `None` if the page allocator is out of memory
+    /// or has not been initialized.
+    ///
+    /// With the `slab_coloring` feature, the slab may be colored
+    /// (see `Slab::is_colored`), so its slots may be aligned to
+    /// `SLAB_COLOR_ALIGN` only.
     pub fn alloc(
         slot_recyle_fn: SlotRecycleFn<SLOT_SIZE>,
         extension: Ext
     ) -> Option<Self> {
+        Self::alloc_with_coloring(slot_recyle_fn, extension, cfg!(feature = "slab_coloring"))
+    }
+
+    /// Allocate a slab like `alloc`, but the slab is never colored,
+    /// so every slot of the slab is aligned to `SLOT_SIZE`.
+    ///
+    /// A slab cache should serve the allocations that are aligned to the slot size
+    /// (see `SlabSlotAlloc::alloc_aligned`) from such slabs.
+    pub fn alloc_aligned(
+        slot_recyle_fn: SlotRecycleFn<SLOT_SIZE>,
+        extension: Ext
+    ) -> Option<Self> {
+        Self::alloc_with_coloring(slot_recyle_fn, extension, false)
+    }
+
+    fn alloc_with_coloring(
+        slot_recyle_fn: SlotRecycleFn<SLOT_SIZE>,
+        extension: Ext,
+        is_colorable: bool,
+    ) -> Option<Self> {
+        // Even a slab of one page must have room for a slot after the metadata.
+        const { assert!(slab_meta_region_size::<SLOT_SIZE, Ext>() < MIN_PAGES_PER_SLAB * PAGE_SIZE) };
+
+        if !page_alloc::is_initialized() {
+            return None;
+        }
+        let size_class = SizeClass::from_bytes(SLOT_SIZE).unwrap();
+        // The slots of the slab are handed out only after the mask is cached,
+        // so any slot that `slab_base_of` is given sees the mask.
+        let nr_pages = pages_per_slab(size_class);
+        SLAB_BASE_MASKS[size_class.index()].store(!(nr_pages * PAGE_SIZE - 1), Relaxed);
+        HAS_ALLOCATED_SLABS[size_class.index()].store(true, Release);
+
         todo!("
-            Step 1. Allocate a new page for slab with the specified metadata and extension.
-            Step 2. Partition the slab as an array of FreeSlabSlot.
+            Step 1. Allocate `nr_pages` contiguous pages, which are aligned to `nr_pages * PAGE_SIZE`,
+                    for slab with the specified metadata (including `nr_pages`, `slot_size`,
+                    `slot_offset`, `nr_total_slots` and `is_colored` computed by
+                    `slot_layout_of(nr_pages, is_colorable)` and an idle `reclaim_guard`)
+                    at `slab_meta_addr`, and the header (including `SLAB_META_MAGIC`,
+                    `slot_recyle_fn` and `extension_vtable`) at `SlabMeta::header`,
+                    and extension. Tag every page with `PageUsage::Slab { slab_base }`.
+                    A slab of one page takes its page from the stash of the current CPU
+                    with `super::page_stash::alloc_page`.
+                    If the pages cannot be allocated, increment `NR_SLAB_OOMS` and return `None`.
+                    (With `slab_ro_meta`) Map the header page read-only
+                    with `page_alloc::protect_pages`.
+            Step 2. Partition the slab from `slot_offset` (see `slot_ptr`)
+                    as an array of FreeSlabSlot.
             Step 3. Link all FreeSlabSlots into a list.
+                    (With `slab_fifo_free_list`) Point `free_list_tail` to the last slot.
+                    (With `slab_aslr`) Shuffle the list with `shuffle_free_list`,
+                    seeded by the slab base and `now_tick`.
+            Step 4. Mark all slots as freed in the bitmap.
+                    Set no `on_slab_destroyed` or `on_slot_allocated` hook,
+                    and no links to other slabs.
+            Step 5. Invoke `super::notify_slab_created` with `SLOT_SIZE`,
+                    the slab base and `nr_pages`.
         ")
     }
 
+    /// Allocate a slab with an extension that hooks into the lifecycle of the slab.
+    ///
+    /// The extension is notified after the slab is created
+    /// and before the slab is destroyed (see `SlabLifecycle`).
+    pub fn alloc_with_lifecycle(
+        slot_recyle_fn: SlotRecycleFn<SLOT_SIZE>,
+        extension: Ext
+    ) -> Option<Self>
+    where
+        Ext: SlabLifecycle,
+    {
+        let slab = Self::alloc(slot_recyle_fn, extension)?;
+        // SAFETY: The slab has just been allocated, so no one else is accessing the metadata.
+        unsafe {
+            let slab_meta_ptr = slab.slab_meta_ptr();
+            (*slab_meta_ptr).on_slab_destroyed = Some(Ext::on_slab_destroyed);
+        }
+        slab.slab_extension().on_slab_created(slab.base_ptr(), slab.nr_total_slots());
+        Some(slab)
+    }
+
+    /// Allocate a slab with an extension that is notified of every slot allocation.
+    ///
+    /// See `SlotAllocHook` for the cost of the hook.
+    pub fn alloc_with_slot_hook(
+        slot_recyle_fn: SlotRecycleFn<SLOT_SIZE>,
+        extension: Ext
+    ) -> Option<Self>
+    where
+        Ext: SlotAllocHook,
+    {
+        Self::alloc(slot_recyle_fn, extension).map(Self::install_slot_hook)
+    }
+
+    /// Allocate a slab like `alloc_with_slot_hook`, but the slab is never colored
+    /// (see `alloc_aligned`).
+    pub fn alloc_aligned_with_slot_hook(
+        slot_recyle_fn: SlotRecycleFn<SLOT_SIZE>,
+        extension: Ext
+    ) -> Option<Self>
+    where
+        Ext: SlotAllocHook,
+    {
+        Self::alloc_aligned(slot_recyle_fn, extension).map(Self::install_slot_hook)
+    }
+
+    // Installs the hook of `SlotAllocHook` on a slab that has just been allocated.
+    fn install_slot_hook(slab: Self) -> Self
+    where
+        Ext: SlotAllocHook,
+    {
+        // SAFETY: The slab has just been allocated, so no one else is accessing the metadata.
+        unsafe {
+            let slab_meta_ptr = slab.slab_meta_ptr();
+            (*slab_meta_ptr).on_slot_allocated = Some(Ext::on_slot_allocated);
+        }
+        slab
+    }
+
+    /// Re-homes the slab to another slab cache, by replacing
+    /// the `SlotRecycleFn` and the extension of the slab.
+    ///
+    /// After the call, all the slots of the slab, including the ones in use,
+    /// are recycled to the new owner. Returns the old extension.
+    ///
+    /// Re-homing moves the ownership of a whole slab, e.g.,
+    /// migrating a slab between the caches of two CPUs.
+    /// A single slot can never be re-homed on its own, since
+    /// the routing of recycling is a property of the slab metadata.
+    ///
+    /// # Safety
+    ///
+    /// No slot of the slab may be recycled concurrently,
+    /// which would read the recycle function and the extension
+    /// while they are being replaced. E.g., the caller may hold the locks
+    /// of both the old and the new slab caches, through which all
+    /// the recycling of the slab goes.
+    pub unsafe fn rehome(&mut self, slot_recyle_fn: SlotRecycleFn<SLOT_SIZE>, extension: Ext) -> Ext {
+        // The extension has the same type, so `extension_vtable` remains valid.
+        let slab_meta_ptr = self.slab_meta_ptr();
+        // SAFETY: The slab is exclusively borrowed, and the caller guarantees
+        // that no slot recycling reads the metadata concurrently.
+        unsafe {
+            self.update_header(|header| header.slot_recyle_fn = slot_recyle_fn);
+            mem::replace(&mut (*slab_meta_ptr).extension, extension)
+        }
+    }
+
     pub fn new_slot(&mut self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
         let slab_meta = self.slab_meta();
 
@@ -32,6 +482,20 @@ impl<const SLOT_SIZE: usize, Ext> Slab<SLOT_SIZE, Ext> {
             head.next
         };
         slab_meta.free_list.store(new_head_ptr, Relaxed);
+        if new_head_ptr == ptr::null_mut() {
+            // The slab has become full.
+            #[cfg(feature = "slab_fifo_free_list")]
+            slab_meta.free_list_tail.store(ptr::null_mut(), Relaxed);
+        }
+
+        // The next allocation reads the link of the new head,
+        // so start fetching it into the cache now.
+        #[cfg(feature = "slab_prefetch")]
+        if new_head_ptr != ptr::null_mut() {
+            prefetch_slot(new_head_ptr as *const u8);
+        }
+
+        slab_meta.freed_bitmap.clear(self.slot_index(head_ptr as _));
 
         // SAFETY: The pointer refers to a valid and unused free slot
         let new_slab_slot = unsafe {
@@ -40,6 +504,10 @@ impl<const SLOT_SIZE: usize, Ext> Slab<SLOT_SIZE, Ext> {
 
         slab_meta.nr_inuse_slots.fetch_add(1, Relaxed);
 
+        if let Some(on_slot_allocated) = slab_meta.on_slot_allocated {
+            on_slot_allocated(&slab_meta.extension);
+        }
+
         Some(new_slab_slot)
     }
 
@@ -47,31 +515,124 @@ impl<const SLOT_SIZE: usize, Ext> Slab<SLOT_SIZE, Ext> {
         let slab_meta = self.slab_meta();
 
         // Safety invariant: a free slot is always returned to its parent slab.
-        assert!({
-            let expected_meta_ptr = slab_meta as *const SlabMeta;
-            let actual_meta_ptr == free_slot.slab_meta() as _;
-            actual_meta_ptr == expected_meta_ptr
-        });
+        heap_assert!(cheap, slab_base_of::<SLOT_SIZE>(free_slot.as_ptr()) == slab_meta.slab_base_ptr());
 
-        let old_head_ptr = slab_meta.free_list.load(Relaxed);
+        // Pushing the same slot twice would create a cycle in the free list,
+        // which later hands out the same slot to two callers.
+        // The bitmap is always maintained, but the check is only enforced
+        // at every assertion level with `slab_double_free_check`.
+        let slot_ptr = free_slot.as_ptr();
+        let is_double_free = slab_meta.freed_bitmap.test_and_set(self.slot_index(slot_ptr));
+        #[cfg(feature = "slab_double_free_check")]
+        if is_double_free {
+            panic!("double free detected: slot {:p} is already free", slot_ptr);
+        }
+        #[cfg(not(feature = "slab_double_free_check"))]
+        heap_assert!(full, !is_double_free, "double free detected: slot {:p} is already free", slot_ptr);
 
         let new_head_ptr = {
             let linked_slot_ptr = free_slot.as_ptr() as *mut LinkedSlabSlot;
             // Avoid pointer aliasing due to the coexistence of FreeSlabSlot and LinkedSlabSlot.
-            drop(free_slot);
+            //
+            // The slot is forgotten, not dropped, as the in-use counter
+            // is decremented below. Dropping it would decrement the counter twice.
+            mem::forget(free_slot);
             linked_slot_ptr
         };
-        let new_head = unsafe {
-            &mut *new_head_ptr
-        };
-        new_head.next = old_head_ptr;
+        // With a LIFO free list, the slot is pushed to the head,
+        // so it is reused first while it is still hot in the cache.
+        #[cfg(not(feature = "slab_fifo_free_list"))]
+        {
+            let old_head_ptr = slab_meta.free_list.load(Relaxed);
+            let new_head = unsafe {
+                &mut *new_head_ptr
+            };
+            new_head.next = old_head_ptr;
+            slab_meta.free_list.store(new_head_ptr, Relaxed);
+        }
+        // With a FIFO free list, the slot is appended to the tail,
+        // so it is reused last.
+        #[cfg(feature = "slab_fifo_free_list")]
+        {
+            let new_tail_ptr = new_head_ptr;
+            // SAFETY: The slot is no longer in use, and the slab is exclusively borrowed.
+            unsafe { (*new_tail_ptr).next = ptr::null_mut() };
+            let old_tail_ptr = slab_meta.free_list_tail.load(Relaxed);
+            if old_tail_ptr == ptr::null_mut() {
+                slab_meta.free_list.store(new_tail_ptr, Relaxed);
+            } else {
+                // SAFETY: The tail is a free slot of the slab.
+                unsafe { (*old_tail_ptr).next = new_tail_ptr };
+            }
+            slab_meta.free_list_tail.store(new_tail_ptr, Relaxed);
+        }
+
+        // The `Release` ordering pairs with the `Acquire` load in `Slab::drop`,
+        // so all accesses to the slot happen before the slab is torn down.
+        let old_count = slab_meta.nr_inuse_slots.fetch_sub(1, Release);
+        heap_assert!(full, old_count >= 1);
+    }
+
+    /// Returns the total number of slots of the slab.
+    ///
+    /// The slots that would overlap the metadata at the base of the slab
+    /// are excluded.
+    pub fn nr_total_slots(&self) -> usize {
+        self.slab_meta().nr_total_slots as usize
+    }
+
+    /// Returns whether the slots of the slab are shifted by a color.
+    ///
+    /// The slots of a colored slab are only aligned to `SLAB_COLOR_ALIGN`.
+    /// A slab is never colored without the `slab_coloring` feature,
+    /// or if it is allocated with `alloc_aligned`.
+    pub fn is_colored(&self) -> bool {
+        self.slab_meta().is_colored
+    }
+
+    // Returns the number of slots of a slab of `nr_pages` pages.
+    //
+    // The number is computed once upon the creation of the slab
+    // and stored in the metadata, as it depends on the number of pages
+    // and the size of the metadata region.
+    fn nr_slots_of(nr_pages: usize) -> u16 {
+        let nr_slots = (nr_pages * PAGE_SIZE - slab_meta_region_size::<SLOT_SIZE, Ext>()) / SLOT_SIZE;
+        u16::try_from(nr_slots).expect("too many slots in a slab")
+    }
+
+    // Returns the offset of the first slot, the number of slots
+    // and whether the slab is colored, for a new slab of `nr_pages` pages.
+    //
+    // A colorable slab is colored if its slots span more than one color
+    // and it has a slot to spare. The color is a non-zero multiple of
+    // `SLAB_COLOR_ALIGN` below `SLOT_SIZE`, which takes the place of the last slot.
+    // The colors cycle through the new slabs of the size class.
+    fn slot_layout_of(nr_pages: usize, is_colorable: bool) -> (u32, u16, bool) {
+        let meta_region_size = slab_meta_region_size::<SLOT_SIZE, Ext>();
+        let nr_slots = Self::nr_slots_of(nr_pages);
+        let nr_colors = SLOT_SIZE / SLAB_COLOR_ALIGN;
+        if !is_colorable || nr_colors <= 1 || nr_slots <= 1 {
+            return (meta_region_size as u32, nr_slots, false);
+        }
+
+        let size_class = SizeClass::from_bytes(SLOT_SIZE).unwrap();
+        let color = 1 + NEXT_SLAB_COLORS[size_class.index()].fetch_add(1, Relaxed) % (nr_colors - 1);
+        ((meta_region_size + color * SLAB_COLOR_ALIGN) as u32, nr_slots - 1, true)
+    }
 
-        let old_count = slab_meta.nr_inuse_slots.fetch_sub(1, Relaxed);
-        debug_assert!(old_count >= 1);
+    /// Returns the number of slots that are borrowed by slab caches.
+    ///
+    /// Borrowed slots are included in `nr_used_slots`. So the number of slots
+    /// that are actually used by objects is `nr_used_slots - nr_borrowed_slots`.
+    /// A slab whose in-use slots are all borrowed can only be reclaimed
+    /// after the cache-level free lists are drained back to the slab.
+    pub fn nr_borrowed_slots(&self) -> usize {
+        self.slab_meta().nr_borrowed_slots.load(Relaxed) as _
     }
 
-    pub const fn nr_total_slots(&self) -> usize {
-        PAGE_SIZE / SLOT_SIZE 
+    /// Returns the number of pages of the slab.
+    pub fn nr_pages(&self) -> usize {
+        self.slab_meta().nr_pages as usize
     }
 
     pub fn has_unused_slots(&self) -> bool {
@@ -79,145 +640,1273 @@ impl<const SLOT_SIZE: usize, Ext> Slab<SLOT_SIZE, Ext> {
         slab_meta.free_list.load(Relaxed) != ptr::null()
     }
 
+    /// Returns the number of slots in use.
+    ///
+    /// The returned value is only a snapshot, which is good for statistics
+    /// but must not be used to decide whether the slab can be torn down.
     pub fn nr_used_slots(&self) -> usize {
-        slab_meta.nr_used_slots.load(Relaxed) as _
+        let slab_meta = self.slab_meta();
+        slab_meta.nr_inuse_slots.load(Relaxed) as _
     }
 
-    pub(crate) fn slab_meta(&self) -> &SlabMeta<Ext> {
-        &self.slab_meta()
+    /// Marks the slab as busy with an in-flight allocation,
+    /// so that the slab cannot be reclaimed until the guard is dropped.
+    ///
+    /// This is for the slab caches that allocate from a slab and reclaim it
+    /// without holding a common lock. Such a cache must allocate a slot
+    /// while holding the guard, and reclaim the slab only after
+    /// a successful `try_begin_reclaim`.
+    ///
+    /// Returns `None` if the slab is busy with another allocation,
+    /// or has been claimed for reclamation.
+    pub fn try_begin_alloc(&self) -> Option<SlabAllocGuard<'_>> {
+        let reclaim_guard = &self.slab_meta().reclaim_guard;
+        reclaim_guard
+            .compare_exchange(RECLAIM_GUARD_IDLE, RECLAIM_GUARD_BUSY, Acquire, Relaxed)
+            .ok()?;
+        Some(SlabAllocGuard { reclaim_guard })
     }
 
-    pub fn slab_extension(&self) -> &Ext {
-        &self.slab_meta().extension
+    /// Claims the slab for reclamation, if no slot is in use
+    /// and no allocation is in flight (see `try_begin_alloc`).
+    ///
+    /// Once claimed, the slab never admits an allocation again,
+    /// so it can be torn down safely. Each successful claim advances
+    /// the reclaim epoch of the heap (see `heap_epoch`).
+    pub fn try_begin_reclaim(&self) -> bool {
+        let slab_meta = self.slab_meta();
+        if slab_meta
+            .reclaim_guard
+            .compare_exchange(RECLAIM_GUARD_IDLE, RECLAIM_GUARD_RECLAIMING, Acquire, Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        // The `Acquire` ordering synchronizes with the `Release`
+        // of the last allocation guard, so any slot allocated with it is counted.
+        if slab_meta.nr_inuse_slots.load(Acquire) != 0 {
+            slab_meta.reclaim_guard.store(RECLAIM_GUARD_IDLE, Release);
+            return false;
+        }
+        HEAP_EPOCH.fetch_add(1, Relaxed);
+        true
     }
-}
 
-impl<const SLOT_SIZE: usize, Ext> Drop for Slab<SLOT_SIZE, Ext> {
-    fn drop(&mut self) {
-        assert!(self.nr_used_slots() == 0);
+    /// Visits every slot in use, i.e., every slot that is not on the free list.
+    ///
+    /// This method takes O(nr_total_slots) time. It is meant for
+    /// tearing down a subsystem (e.g., running the destructors of
+    /// all live objects) or auditing the heap, not for hot paths.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if any slot is borrowed (see `FreeSlabSlot::mark_borrowed`).
+    /// A borrowed slot is not on the free list but holds no object,
+    /// so the cache-level free lists must be drained to the slab first.
+    pub fn for_each_used_slot(&self, mut f: impl FnMut(NonNull<u8>)) {
+        assert_eq!(
+            self.nr_borrowed_slots(),
+            0,
+            "the slots borrowed by slab caches are not objects in use"
+        );
+        let nr_total_slots = self.nr_total_slots();
+        let freed_bitmap = self.free_slot_bitmap();
+
+        for slot_idx in 0..nr_total_slots {
+            if freed_bitmap.test(slot_idx) {
+                continue;
+            }
+            let slot_ptr = self.slot_ptr(slot_idx);
+            // SAFETY: The slots of a slab are not null.
+            f(unsafe { NonNull::new_unchecked(slot_ptr) });
+        }
     }
-}
 
-const fn does_slot_size_match_obj_size(real_slot_size: usize, obj_size: usize) {
-    let expected_slot_size = super::determine_slot_size(obj_size);
-    real_slot_size == expected_slot_size
-}
+    /// Rebuilds the free list in the ascending order of slot addresses.
+    ///
+    /// After many allocations and deallocations, the free slots are linked
+    /// in a scattered order, which hurts the cache locality of the subsequent
+    /// allocations. This is a maintenance operation for, e.g., an idle pass.
+    ///
+    /// The operation takes O(`nr_total_slots`) time.
+    pub fn coalesce_free_list(&mut self) {
+        let slab_meta = self.slab_meta();
+        let nr_total_slots = self.nr_total_slots();
+        let freed_bitmap = self.free_slot_bitmap();
 
-pub struct FreeSlabSlot<const SLOT_SIZE: usize> {
-    ptr: NonNull<[u8; SLOT_SIZE]>,
-}
+        // Link the free slots from the highest address to the lowest one,
+        // so that the head of the free list is the free slot of the lowest address.
+        let mut new_head_ptr = ptr::null_mut();
+        for slot_idx in (0..nr_total_slots).rev() {
+            if !freed_bitmap.test(slot_idx) {
+                continue;
+            }
+            let slot_ptr = self.slot_ptr(slot_idx) as *mut LinkedSlabSlot;
+            // The first linked slot, of the highest address, is the tail.
+            #[cfg(feature = "slab_fifo_free_list")]
+            if new_head_ptr == ptr::null_mut() {
+                slab_meta.free_list_tail.store(slot_ptr, Relaxed);
+            }
+            // SAFETY: The slot is free and lies within the slab.
+            // And the slab is exclusively borrowed, so no one else is using the free list.
+            unsafe { (*slot_ptr).next = new_head_ptr };
+            new_head_ptr = slot_ptr;
+        }
+        slab_meta.free_list.store(new_head_ptr, Relaxed);
+    }
 
-impl<const SLOT_SIZE: usize> FreeSlabSlot<SLOT_SIZE> {
-    pub const ALIGN_SIZE: usize = SLOT_SIZE;
+    // Rebuilds the free list in a pseudo-random order, seeded by `seed`.
+    //
+    // The free slots are visited in the order of `(offset + i * stride) % nr_total_slots`,
+    // where the stride is coprime to `nr_total_slots`, so that every slot is visited once.
+    // This permutes the slots without any memory for the permutation.
+    // The order only needs to be unpredictable enough to hinder heap grooming,
+    // so the PRNG is not cryptographic.
+    #[cfg(feature = "slab_aslr")]
+    fn shuffle_free_list(&mut self, seed: u64) {
+        let slab_meta = self.slab_meta();
+        let nr_total_slots = self.nr_total_slots();
+        let freed_bitmap = self.free_slot_bitmap();
 
-    pub unsafe fn new(ptr: *mut u8) -> Self {
-        static_assert!(SLOT_SIZE.is_power_of_two());
-        debug_assert!((ptr as usize) % Self::ALIGN_SIZE == 0);
+        let mut rng = XorShift64::new(seed);
+        let mut slot_idx = rng.next() as usize % nr_total_slots;
+        let mut stride = rng.next() as usize % nr_total_slots;
+        while gcd(stride, nr_total_slots) != 1 {
+            stride = (stride + 1) % nr_total_slots;
+        }
 
-        Self {
-            ptr: NonNull::new_unchecked(ptr)
+        let mut new_head_ptr = ptr::null_mut();
+        for _ in 0..nr_total_slots {
+            if freed_bitmap.test(slot_idx) {
+                let slot_ptr = self.slot_ptr(slot_idx) as *mut LinkedSlabSlot;
+                // The first linked slot is the tail.
+                #[cfg(feature = "slab_fifo_free_list")]
+                if new_head_ptr == ptr::null_mut() {
+                    slab_meta.free_list_tail.store(slot_ptr, Relaxed);
+                }
+                // SAFETY: The slot is free and lies within the slab.
+                // And the slab is exclusively borrowed, so no one else is using the free list.
+                unsafe { (*slot_ptr).next = new_head_ptr };
+                new_head_ptr = slot_ptr;
+            }
+            slot_idx = (slot_idx + stride) % nr_total_slots;
         }
+        slab_meta.free_list.store(new_head_ptr, Relaxed);
     }
 
-    pub fn into_raw(self) -> *mut u8 {
-        todo!()        
+    /// Allocates `nr_slots` contiguous slots as a single span.
+    ///
+    /// This is for the custom pools that occasionally need an object
+    /// slightly larger than the slot size. The spanned slots are taken off
+    /// the free list until they are freed with `free_span`.
+    ///
+    /// Returns `None` if there are not enough contiguous free slots.
+    /// The operation takes O(`nr_total_slots`) time.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if `nr_slots` is zero.
+    pub fn alloc_span(&mut self, nr_slots: usize) -> Option<NonNull<u8>> {
+        assert!(nr_slots > 0);
+        let nr_total_slots = self.nr_total_slots();
+        let freed_bitmap = self.free_slot_bitmap();
+
+        let mut nr_contiguous = 0;
+        let first_idx = (0..nr_total_slots).find(|&slot_idx| {
+            if freed_bitmap.test(slot_idx) {
+                nr_contiguous += 1;
+            } else {
+                nr_contiguous = 0;
+            }
+            nr_contiguous == nr_slots
+        })? + 1 - nr_slots;
+
+        // Unlink the spanned slots from the free list.
+        let slab_meta = self.slab_meta();
+        let span_start = self.slot_ptr(first_idx);
+        let span_end = span_start.wrapping_add(nr_slots * SLOT_SIZE);
+        let mut link_ptr = slab_meta.free_list.as_ptr();
+        // The last free slot that remains on the free list.
+        #[cfg(feature = "slab_fifo_free_list")]
+        let mut last_ptr = ptr::null_mut();
+        // SAFETY: The links are those of the free slots of the slab,
+        // which is exclusively borrowed.
+        unsafe {
+            while *link_ptr != ptr::null_mut() {
+                let slot_ptr = *link_ptr;
+                if (span_start..span_end).contains(&(slot_ptr as *mut u8)) {
+                    *link_ptr = (*slot_ptr).next;
+                } else {
+                    #[cfg(feature = "slab_fifo_free_list")]
+                    {
+                        last_ptr = slot_ptr;
+                    }
+                    link_ptr = &mut (*slot_ptr).next;
+                }
+            }
+        }
+        #[cfg(feature = "slab_fifo_free_list")]
+        slab_meta.free_list_tail.store(last_ptr, Relaxed);
+
+        for slot_idx in first_idx..first_idx + nr_slots {
+            slab_meta.freed_bitmap.clear(slot_idx);
+        }
+
+        slab_meta.nr_inuse_slots.fetch_add(nr_slots as u16, Relaxed);
+
+        // SAFETY: The slots of a slab are not null.
+        Some(unsafe { NonNull::new_unchecked(span_start) })
     }
 
-    pub unsafe fn from_raw(raw: *mut u8) -> Self {
-        todo!()        
+    /// Frees a span of slots that has been allocated with `alloc_span`.
+    ///
+    /// # Safety
+    ///
+    /// The span must have been allocated from this slab with `alloc_span`
+    /// of the same `nr_slots`, and must not be used afterwards.
+    pub unsafe fn free_span(&mut self, span_ptr: NonNull<u8>, nr_slots: usize) {
+        for slot_i in 0..nr_slots {
+            let slot_ptr = span_ptr.as_ptr().wrapping_add(slot_i * SLOT_SIZE);
+            // SAFETY: Each slot of the span is in use and belongs to this slab.
+            let free_slot = unsafe { FreeSlabSlot::new(slot_ptr) };
+            self.recycle_slot(free_slot);
+        }
     }
 
-    pub fn into_box<T>(self, obj: T) -> Box<T> {
-        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<T>()));
-        static_assert!(SLOT_SIZE % mem::align_of::<T>() == 0);
+    // Returns the bitmap of the slots on the free list, which is embedded in the metadata.
+    //
+    // The bitmap is read in place. A copy on the stack would take up to
+    // `FreedSlotBitmap::NR_WORDS` words, i.e., kilobytes with large pages.
+    fn free_slot_bitmap(&self) -> &FreedSlotBitmap {
+        &self.slab_meta().freed_bitmap
+    }
 
-        let obj_ptr = self.ptr.as_mut_ptr() as *mut T;
-        // SAFETY: 
-        // 1. The slot is free;
-        // 2. The size and alignment of the slot satisfies all the 
-        // requirements by `T`.
-        unsafe {
-            obj_ptr.write(obj);
-        }
+    // Returns the index of the slot that the pointer refers to.
+    fn slot_index(&self, slot_ptr: *const u8) -> usize {
+        let offset = slot_ptr as usize - self.slot_ptr(0) as usize;
+        heap_assert!(full, offset % SLOT_SIZE == 0);
+        offset / SLOT_SIZE
     }
 
-    pub fn from_box<T>(boxed_obj: Box<T>) -> Self {
-        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<T>()));
+    // Returns the pointer to the slot of the index.
+    //
+    // The slots start right after the metadata region of the slab,
+    // shifted by the color of the slab, if any (see `slot_layout_of`).
+    fn slot_ptr(&self, slot_idx: usize) -> *mut u8 {
+        self.page
+            .as_ptr()
+            .wrapping_add(self.slab_meta().slot_offset as usize + slot_idx * SLOT_SIZE)
+    }
 
-        let obj_ptr = Box::leak(boxed_obj) as *mut T;
-        // SAFETY: The pointer refer to a valid object.
-        // And after the in-place drop, the object will not be used any more.
+    pub(crate) fn slab_meta(&self) -> &SlabMeta<Ext> {
+        // SAFETY: The slab metadata resides at `slab_meta_addr` of the slab.
+        unsafe { &*self.slab_meta_ptr() }
+    }
+
+    fn slab_meta_ptr(&self) -> *mut SlabMeta<Ext> {
+        slab_meta_addr(self.page.as_ptr()) as *mut SlabMeta<Ext>
+    }
+
+    // Updates the header of the slab.
+    //
+    // With `slab_ro_meta`, the header page is mapped writable
+    // only for the duration of the update.
+    //
+    // # Safety
+    //
+    // No one may read the header concurrently, e.g., by recycling a slot.
+    unsafe fn update_header(&self, f: impl FnOnce(&mut SlabMetaHeader)) {
+        let header_ptr = self.slab_meta().header() as *const SlabMetaHeader as *mut SlabMetaHeader;
+        // SAFETY: The header page belongs to the slab, and the caller guarantees
+        // that the header is not read concurrently.
         unsafe {
-            ptr::drop_in_place(obj_ptr);
+            #[cfg(feature = "slab_ro_meta")]
+            page_alloc::unprotect_pages(self.base_ptr(), 1);
+            f(&mut *header_ptr);
+            #[cfg(feature = "slab_ro_meta")]
+            page_alloc::protect_pages(self.base_ptr(), 1);
         }
+    }
 
-        let slot_ptr = obj_ptr as *mut u8;
-        // SAFETY: Every object of Box<T> corresponds to an object of 
-        // FreeSlabSlot<SLOT_SIZE>. The orignal object of Box<T> is no longer
-        // used; so it can be converted to the corresponding FreeSlabSlot. 
+    /// Returns the base address of the slab.
+    pub fn base_ptr(&self) -> *const u8 {
+        self.page.as_ptr()
+    }
+
+    /// Replaces the extension of the slab, e.g., when the slab is migrated.
+    ///
+    /// The old extension is dropped, and the vtable of the extension
+    /// is recomputed from the new one.
+    ///
+    /// # Safety
+    ///
+    /// No slot of the slab may be recycled concurrently, as the in-flight
+    /// `SlotRecycleFn` calls may still be reading the old extension.
+    pub unsafe fn set_extension(&mut self, extension: Ext)
+    where
+        Ext: 'static,
+    {
+        let slab_meta_ptr = self.slab_meta_ptr();
+        // SAFETY: The slab is exclusively borrowed, and the caller guarantees
+        // that no slot recycling reads the metadata concurrently.
         unsafe {
-            Self::new(slot_ptr)
+            (*slab_meta_ptr).extension = extension;
+            let extension_ref: &dyn Any = &(*slab_meta_ptr).extension;
+            let extension_vtable = ptr::metadata(extension_ref);
+            self.update_header(|header| header.extension_vtable = extension_vtable);
         }
     }
 
-    pub fn into_arc<T>(self, data: T) -> Arc<T> {
-        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<ArcInner<T>>()));
-        static_assert!(SLOT_SIZE % mem::align_of::<ArcInner<T>>() == 0);
+    /// Links the slab right after `prev` in an intrusive list of slabs.
+    ///
+    /// The links reside in the slab metadata, so a slab cache can thread
+    /// its slabs into lists (e.g., of partial and full slabs) without
+    /// allocating any memory, holding only a head pointer per list
+    /// (see `into_raw` and `from_raw`).
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the slab is already linked.
+    pub fn link_after(&mut self, prev: &mut Self) {
+        assert!(!self.is_linked(), "the slab is already in a list");
+        let self_ptr = self.page.as_ptr();
+        let next_ptr = prev.slab_meta().next_slab.load(Relaxed);
 
-        // Same as what `Arc::new` does.
-        let inner = ArcInner {
-            strong: atomic::AtomicUsize::new(1),
-            weak: atomic::AtomicUsize::new(1),
-            data,
-        };
-        let ptr = Box::leak(self.into_box(inner)) as *mut ArcInner<T>;
-        // SAFETY: The data structure of `Arc<T>` is defined as 
-        //
-        // pub struct Arc<T: ?Sized> {
-        //     ptr: NonNull<ArcInner<T>>,
-        //     phantom: PhantomData<ArcInner<T>>,
-        // }
-        //
-        // and `ptr` is not null. So this transmute is correct. 
-        unsafe {
-            mem::transmute(ptr)
+        self.slab_meta().prev_slab.store(prev.page.as_ptr(), Relaxed);
+        self.slab_meta().next_slab.store(next_ptr, Relaxed);
+        if next_ptr != ptr::null_mut() {
+            // SAFETY: A linked slab is alive, and its metadata resides at `slab_meta_addr`.
+            let next_meta = unsafe { &*(slab_meta_addr(next_ptr) as *const SlabMeta<SLOT_SIZE, Ext>) };
+            next_meta.prev_slab.store(self_ptr, Relaxed);
         }
+        prev.slab_meta().next_slab.store(self_ptr, Relaxed);
     }
 
-    pub fn from_arc<T>(arc: Arc<T>) -> Self {
-        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<ArcInner<T>>()));
+    /// Unlinks the slab from its intrusive list of slabs, if any.
+    pub fn unlink(&mut self) {
+        let slab_meta = self.slab_meta();
+        let prev_ptr = slab_meta.prev_slab.swap(ptr::null_mut(), Relaxed);
+        let next_ptr = slab_meta.next_slab.swap(ptr::null_mut(), Relaxed);
+        // SAFETY: The linked slabs are alive, and their metadata resides at `slab_meta_addr`.
+        unsafe {
+            if prev_ptr != ptr::null_mut() {
+                (*(slab_meta_addr(prev_ptr) as *const SlabMeta<SLOT_SIZE, Ext>)).next_slab.store(next_ptr, Relaxed);
+            }
+            if next_ptr != ptr::null_mut() {
+                (*(slab_meta_addr(next_ptr) as *const SlabMeta<SLOT_SIZE, Ext>)).prev_slab.store(prev_ptr, Relaxed);
+            }
+        }
+    }
 
-        todo!()
+    /// Returns whether the slab is linked to other slabs.
+    pub fn is_linked(&self) -> bool {
+        let slab_meta = self.slab_meta();
+        slab_meta.next_slab.load(Relaxed) != ptr::null_mut()
+            || slab_meta.prev_slab.load(Relaxed) != ptr::null_mut()
     }
 
-    pub fn take_next_slot(&mut self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
-        todo!()
+    /// Returns the base of the next slab in the intrusive list of slabs, if any.
+    ///
+    /// The next slab can be recovered with `from_raw` by the owner of the list.
+    pub fn next_slab(&self) -> Option<NonNull<u8>> {
+        NonNull::new(self.slab_meta().next_slab.load(Relaxed))
     }
 
-    pub fn replace_next_slot(&mut self, new: FreeSlabSlot<SLOT_SIZE>) -> Option<FreeSlabSlot<SLOT_SIZE>> {
-        todo!()
+    /// Converts the slab into the base of the slab, leaking the ownership,
+    /// e.g., to an intrusive list of slabs.
+    pub fn into_raw(self) -> NonNull<u8> {
+        let page = self.page;
+        mem::forget(self);
+        page
     }
 
-    pub fn next_slot(&self) -> Option<&FreeSlabSlot<SLOT_SIZE>> {
-        todo!()
+    /// Recovers a slab from the base returned by `into_raw`.
+    ///
+    /// # Safety
+    ///
+    /// The base must have been returned by `into_raw` of a slab
+    /// of the same `SLOT_SIZE` and `Ext`, and must be recovered only once.
+    pub unsafe fn from_raw(slab_base: NonNull<u8>) -> Self {
+        Self {
+            page: slab_base,
+        }
     }
 
-    fn slab_meta(&self) -> &SlabMeta<()> {
-        todo!()
+    /// Returns whether the slot belongs to the slab.
+    pub fn contains_slot(&self, free_slot: &FreeSlabSlot<SLOT_SIZE>) -> bool {
+        slab_base_of::<SLOT_SIZE>(free_slot.ptr.as_ptr() as *const u8) == self.base_ptr()
     }
-}
 
-impl<const SLOT_SIZE: usize, Ext> Drop for FreeSlabSlot<SLOT_SIZE, Ext> {
-    fn drop(&mut self) {
-        // The parent slab can only be droppped if this counter is reduced to zero
-        self.slab_meta().nr_inuse_slots.fetch_sub(1, Release);
+    pub fn slab_extension(&self) -> &Ext {
+        &self.slab_meta().extension
     }
-}
 
-// The inner data structure of Arc<T> on the heap.
-//
-// The interface of `Arc<T, A>` already allows
-// specifying a custom allocator of type `A: Allocator`.
+    /// Verifies the internal consistency of the slab.
+    ///
+    /// The free list is walked for at most `nr_total_slots` entries,
+    /// so a corrupted free list cannot cause an infinite loop.
+    pub fn validate(&self) -> Result<(), SlabCorruption> {
+        let slab_meta = self.slab_meta();
+        let magic = slab_meta.header().magic.load(Relaxed);
+        if magic != SLAB_META_MAGIC {
+            return Err(SlabCorruption::BadMagic { magic });
+        }
+
+        let nr_total_slots = self.nr_total_slots();
+        let nr_inuse_slots = slab_meta.nr_inuse_slots.load(Relaxed) as usize;
+        if nr_inuse_slots > nr_total_slots {
+            return Err(SlabCorruption::TooManyInuseSlots { nr_inuse_slots });
+        }
+
+        let region_start = self.slot_ptr(0) as usize;
+        let region_end = region_start + nr_total_slots * SLOT_SIZE;
+        let mut nr_free_slots = 0;
+        let mut slot_ptr = slab_meta.free_list.load(Relaxed);
+        while slot_ptr != ptr::null_mut() {
+            if nr_free_slots == nr_total_slots {
+                return Err(SlabCorruption::FreeListCycle);
+            }
+
+            let slot_addr = slot_ptr as usize;
+            if slot_addr < region_start || slot_addr >= region_end {
+                return Err(SlabCorruption::SlotOutOfRange { slot_addr });
+            }
+            if (slot_addr - region_start) % SLOT_SIZE != 0 {
+                return Err(SlabCorruption::MisalignedSlot { slot_addr });
+            }
+            if slab_base_of::<SLOT_SIZE>(slot_ptr as _) != slab_meta.slab_base_ptr() {
+                return Err(SlabCorruption::ForeignSlot { slot_addr });
+            }
+
+            nr_free_slots += 1;
+            // SAFETY: The slot is on the free list and lies within the slab.
+            slot_ptr = unsafe { (*slot_ptr).next };
+        }
+
+        if nr_free_slots != nr_total_slots - nr_inuse_slots {
+            return Err(SlabCorruption::CountMismatch { nr_free_slots, nr_inuse_slots });
+        }
+        Ok(())
+    }
+}
+
+/// A guard of an in-flight allocation from a slab (see `Slab::try_begin_alloc`).
+///
+/// The slab cannot be claimed for reclamation while the guard is alive.
+pub struct SlabAllocGuard<'a> {
+    reclaim_guard: &'a AtomicU8,
+}
+
+impl Drop for SlabAllocGuard<'_> {
+    fn drop(&mut self) {
+        // The `Release` ordering publishes the slots allocated with the guard
+        // to the reclamation.
+        self.reclaim_guard.store(RECLAIM_GUARD_IDLE, Release);
+    }
+}
+
+/// The inconsistency of a slab found by [`Slab::validate`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SlabCorruption {
+    /// The magic of the metadata is overwritten.
+    BadMagic { magic: u32 },
+    /// The in-use counter exceeds the total number of slots.
+    TooManyInuseSlots { nr_inuse_slots: usize },
+    /// The free list contains a cycle.
+    FreeListCycle,
+    /// A free list entry lies outside the slot region.
+    SlotOutOfRange { slot_addr: usize },
+    /// A free list entry is not aligned with the slot boundaries.
+    MisalignedSlot { slot_addr: usize },
+    /// A free list entry does not belong to this slab.
+    ForeignSlot { slot_addr: usize },
+    /// The length of the free list does not match the in-use counter.
+    CountMismatch { nr_free_slots: usize, nr_inuse_slots: usize },
+}
+
+impl<const SLOT_SIZE: usize, Ext> fmt::Debug for Slab<SLOT_SIZE, Ext> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Slab")
+            .field("slot_size", &SLOT_SIZE)
+            .field("nr_total", &self.nr_total_slots())
+            .field("nr_inuse", &self.nr_used_slots())
+            .field("has_free", &self.has_unused_slots())
+            .finish()
+    }
+}
+
+impl<const SLOT_SIZE: usize, Ext> Drop for Slab<SLOT_SIZE, Ext> {
+    fn drop(&mut self) {
+        // The memory ordering of `nr_inuse_slots` is as follows.
+        // 1. Increments (`Slab::new_slot`) may be `Relaxed`
+        // since they happen under the exclusive access to the slab.
+        // 2. Decrements (`Slab::recycle_slot` or `FreeSlabSlot::drop`) must be `Release`,
+        // which may happen on other CPUs.
+        // 3. The teardown must `Acquire` the counter to synchronize with
+        // all the decrements before the slab memory is freed.
+        //
+        // The checks cannot be compiled out by `heap_assert_level`, as freeing
+        // the pages of a slab that is still referenced is a use-after-free.
+        let nr_borrowed_slots = self.slab_meta().nr_borrowed_slots.load(Acquire);
+        assert!(
+            nr_borrowed_slots == 0,
+            "the cache-level free lists must be drained before the slab is dropped"
+        );
+        let nr_inuse_slots = self.slab_meta().nr_inuse_slots.load(Acquire);
+        assert!(nr_inuse_slots == 0, "a slab with {} slots in use is dropped", nr_inuse_slots);
+        // Otherwise, the neighbors would be left with a dangling link.
+        assert!(!self.is_linked(), "a slab must be unlinked before it is dropped");
+
+        let slab_meta = self.slab_meta();
+        if let Some(on_slab_destroyed) = slab_meta.on_slab_destroyed {
+            on_slab_destroyed(&slab_meta.extension, self.base_ptr(), self.nr_total_slots());
+        }
+
+        // Poison the magic before the pages are freed, so that a slot freed
+        // after the slab is gone can be detected (see `FreeSlabSlot::recycle`),
+        // as long as the pages are not reused.
+        //
+        // With `slab_ro_meta`, the header page is left writable,
+        // as it is returned to the page allocator along with the slab.
+        #[cfg(feature = "slab_ro_meta")]
+        // SAFETY: The header page belongs to the slab, which is being torn down.
+        unsafe {
+            page_alloc::unprotect_pages(self.base_ptr(), 1);
+        }
+        slab_meta.header().magic.store(DEAD_SLAB_MAGIC, Relaxed);
+
+        let nr_pages = self.nr_pages();
+        let slab_meta_ptr = self.slab_meta_ptr();
+        // SAFETY: No slot of the slab is in use or borrowed, and the slab is
+        // exclusively owned. So no one accesses the metadata or the slots anymore,
+        // and the pages can be returned.
+        unsafe {
+            ptr::drop_in_place(&raw mut (*slab_meta_ptr).extension);
+            // Returning the pages to the page allocator clears their `PageUsage::Slab` tags,
+            // so that `owner_of` no longer takes the pages for a slab.
+            // Even the page of a slab of one page is not stashed,
+            // as a stashed page would keep the tag of the dead slab.
+            page_alloc::dealloc_pages(self.base_ptr() as *mut u8, nr_pages);
+        }
+    }
+}
+
+const fn does_slot_size_match_obj_size(real_slot_size: usize, obj_size: usize) {
+    let expected_slot_size = super::determine_slot_size(obj_size).bytes();
+    real_slot_size == expected_slot_size
+}
+
+pub struct FreeSlabSlot<const SLOT_SIZE: usize> {
+    ptr: NonNull<[u8; SLOT_SIZE]>,
+}
+
+impl<const SLOT_SIZE: usize> FreeSlabSlot<SLOT_SIZE> {
+    /// The alignment of every slot of the size class.
+    ///
+    /// The slots of colored slabs are only aligned to `SLAB_COLOR_ALIGN`
+    /// (see `Slab::is_colored`).
+    pub const ALIGN_SIZE: usize = slot_align_of(SLOT_SIZE);
+    /// The index of the size class of the slot.
+    pub const INDEX: usize = super::size_class_index(SLOT_SIZE);
+
+    pub unsafe fn new(ptr: *mut u8) -> Self {
+        static_assert!(SLOT_SIZE.is_power_of_two());
+        heap_assert!(cheap, (ptr as usize) % Self::ALIGN_SIZE == 0);
+        // A slot of another size class would mis-size everything downstream,
+        // e.g., the masking from the slot to its slab.
+        heap_assert!(
+            full,
+            slot_size_of(ptr) == Some(SLOT_SIZE),
+            "a slot of {:?} bytes is reconstructed as a slot of {} bytes",
+            slot_size_of(ptr),
+            SLOT_SIZE,
+        );
+
+        Self {
+            ptr: NonNull::new_unchecked(ptr)
+        }
+    }
+
+    /// Converts the slot into a raw pointer.
+    ///
+    /// With the `slab_alloc_poison` feature, the slot is filled with
+    /// `ALLOC_POISON_BYTE` so that reads of uninitialized memory
+    /// see obviously-wrong values instead of stale data.
+    pub fn into_raw(self) -> *mut u8 {
+        let raw = self.into_raw_unpoisoned();
+        #[cfg(feature = "slab_alloc_poison")]
+        // SAFETY: The slot is valid for writes of `SLOT_SIZE` bytes.
+        unsafe {
+            ptr::write_bytes(raw, ALLOC_POISON_BYTE, SLOT_SIZE);
+        }
+        raw
+    }
+
+    // Converts the slot into a raw pointer without poisoning it.
+    //
+    // This is for the conversions that immediately initialize the object
+    // in the slot, e.g., `into_box`.
+    fn into_raw_unpoisoned(self) -> *mut u8 {
+        let raw = self.ptr.as_ptr() as *mut u8;
+        // The slot remains in use after being converted into a raw pointer.
+        // So it must not be dropped, which decrements the in-use counter.
+        mem::forget(self);
+        raw
+    }
+
+    pub unsafe fn from_raw(raw: *mut u8) -> Self {
+        Self::new(raw)
+    }
+
+    /// Converts the slot into a raw pointer and its size, which is `SLOT_SIZE`.
+    ///
+    /// This is useful for handing the slot to a component that tracks
+    /// `(pointer, size)` pairs and returns both later for deallocation.
+    pub fn into_raw_parts(self) -> (*mut u8, usize) {
+        (self.into_raw(), SLOT_SIZE)
+    }
+
+    /// Reconstructs a slot from a raw pointer and its size.
+    ///
+    /// Returns `None` if `size` is not `SLOT_SIZE`.
+    ///
+    /// # Safety
+    ///
+    /// The pointer and the size must have been returned by `into_raw_parts`
+    /// (of any slot size) and the pointer must not be used afterwards.
+    pub unsafe fn from_raw_parts(ptr: *mut u8, size: usize) -> Option<Self> {
+        if SizeClass::from_bytes(size).is_none() || size != SLOT_SIZE {
+            return None;
+        }
+        // SAFETY: The pointer refers to a slot of `SLOT_SIZE` bytes.
+        Some(unsafe { Self::from_raw(ptr) })
+    }
+
+    /// Returns the slot as a slice of possibly-uninitialized bytes.
+    ///
+    /// # Safety
+    ///
+    /// The first word of the slot stores the next-slot link
+    /// (see `set_next_slot`). If the first word is overwritten,
+    /// the caller must not access the link before resetting it
+    /// with `set_next_slot`.
+    pub unsafe fn as_uninit_slice(&mut self) -> &mut [MaybeUninit<u8>; SLOT_SIZE] {
+        // SAFETY: The slot is free and exclusively owned by `self`.
+        // Any byte pattern is valid for `MaybeUninit<u8>`.
+        unsafe { &mut *(self.ptr.as_ptr() as *mut [MaybeUninit<u8>; SLOT_SIZE]) }
+    }
+
+    /// Writes a value at the given byte offset inside the slot.
+    ///
+    /// This is useful for building a composite object in place,
+    /// field by field, before committing the slot.
+    /// The value is not dropped when the slot is recycled.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the value does not fit in the slot,
+    /// i.e., `offset + size_of::<T>() > SLOT_SIZE`,
+    /// or the address is not aligned for `T`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that writing the value does not overwrite
+    /// any part of another value that will be read later.
+    pub unsafe fn write_at<T>(&mut self, offset: usize, value: T) {
+        let value_ptr = self.value_ptr_at::<T>(offset);
+        // SAFETY: The pointer is in bounds and aligned for `T`.
+        unsafe { value_ptr.write(value) };
+    }
+
+    /// Reads a value at the given byte offset inside the slot.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the value does not fit in the slot,
+    /// i.e., `offset + size_of::<T>() > SLOT_SIZE`,
+    /// or the address is not aligned for `T`.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that the bytes at the offset hold
+    /// a valid value of `T` and the value is not read twice
+    /// unless `T` is `Copy`.
+    pub unsafe fn read_at<T>(&self, offset: usize) -> T {
+        let value_ptr = self.value_ptr_at::<T>(offset);
+        // SAFETY: The pointer is in bounds and aligned for `T`.
+        unsafe { value_ptr.read() }
+    }
+
+    fn value_ptr_at<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset.checked_add(mem::size_of::<T>()).is_some_and(|end| end <= SLOT_SIZE),
+            "the value is out of the slot bounds"
+        );
+        let value_ptr = (self.ptr.as_ptr() as *mut u8).wrapping_add(offset) as *mut T;
+        assert!(value_ptr.is_aligned(), "the value is misaligned");
+        value_ptr
+    }
+
+    /// Re-binds the slot to the slot size `M`, which must equal `SLOT_SIZE`.
+    ///
+    /// This bridges generic code where the two const generics are known
+    /// to be equal only at runtime. On mismatch, the original slot is returned.
+    pub fn cast<const M: usize>(self) -> Result<FreeSlabSlot<M>, Self> {
+        if M != SLOT_SIZE {
+            return Err(self);
+        }
+        let ptr = self.ptr.cast::<[u8; M]>();
+        // The slot stays in use under the new type.
+        mem::forget(self);
+        Ok(FreeSlabSlot { ptr })
+    }
+
+    /// Fills the slot with zeros.
+    ///
+    /// The next-slot link is left as `None`, whose representation is all zeros.
+    /// This is consistent with the slot being owned, not on any list.
+    pub fn zeroed(self) -> Self {
+        // SAFETY: The slot is free and exclusively owned by `self`.
+        unsafe { ptr::write_bytes(self.ptr.as_ptr() as *mut u8, 0, SLOT_SIZE) };
+        self
+    }
+
+    /// Converts the slot into a boxed slice of `len` uninitialized elements.
+    ///
+    /// The contents of the slot are kept, e.g., the zeros written by `zeroed`:
+    ///
+    /// ```ignore
+    /// let bytes: Box<[u8]> = unsafe { slot.zeroed().into_box_slice::<u8>(len).assume_init() };
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the slice of `len` elements does not match the slot size,
+    /// i.e., the slice would be deallocated as a slot of another size.
+    pub fn into_box_slice<T>(self, len: usize) -> Box<[MaybeUninit<T>]> {
+        static_assert!(Self::ALIGN_SIZE % mem::align_of::<T>() == 0);
+        let slice_size = len.checked_mul(mem::size_of::<T>()).unwrap();
+        assert!(
+            slice_size > 0
+                && slice_size <= MAX_SLAB_SLOT_SIZE
+                && does_slot_size_match_obj_size(SLOT_SIZE, slice_size),
+            "the slice of {} bytes does not match the slot of {} bytes",
+            slice_size,
+            SLOT_SIZE,
+        );
+
+        let elem_ptr = self.into_raw_unpoisoned() as *mut MaybeUninit<T>;
+        // SAFETY: The slot is free and is large enough and properly aligned for the slice.
+        // Any byte pattern is valid for `MaybeUninit<T>`.
+        unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(elem_ptr, len)) }
+    }
+
+    pub fn into_box<T>(self, obj: T) -> Box<T> {
+        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<T>()));
+        static_assert!(Self::ALIGN_SIZE % mem::align_of::<T>() == 0);
+
+        let obj_ptr = self.into_raw_unpoisoned() as *mut T;
+        // SAFETY: 
+        // 1. The slot is free;
+        // 2. The size and alignment of the slot satisfies all the 
+        // requirements by `T`.
+        unsafe {
+            obj_ptr.write(obj);
+            Box::from_raw(obj_ptr)
+        }
+    }
+
+    /// Converts the slot into a pinned box of `obj`,
+    /// for the objects that must not move, e.g., intrusive list nodes.
+    ///
+    /// Pinning is sound as slabs never move their slots.
+    /// The pinned object is freed like any box of the heap.
+    pub fn into_pin_box<T>(self, obj: T) -> Pin<Box<T>> {
+        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<T>()));
+        static_assert!(Self::ALIGN_SIZE % mem::align_of::<T>() == 0);
+
+        Box::into_pin(self.into_box(obj))
+    }
+
+    pub fn from_box<T>(boxed_obj: Box<T>) -> Self {
+        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<T>()));
+
+        let obj_ptr = Box::leak(boxed_obj) as *mut T;
+        // SAFETY: The pointer refer to a valid object.
+        // And after the in-place drop, the object will not be used any more.
+        unsafe {
+            ptr::drop_in_place(obj_ptr);
+        }
+
+        let slot_ptr = obj_ptr as *mut u8;
+        // SAFETY: Every object of Box<T> corresponds to an object of 
+        // FreeSlabSlot<SLOT_SIZE>. The orignal object of Box<T> is no longer
+        // used; so it can be converted to the corresponding FreeSlabSlot. 
+        unsafe {
+            Self::new(slot_ptr)
+        }
+    }
+
+    /// Moves an object into the slot, returning a pointer to the object.
+    ///
+    /// This is like `into_box`, but the caller manages the lifetime of the object.
+    /// The slot can be recovered with `from_nonnull` after the object is dropped.
+    pub fn into_nonnull<T>(self, obj: T) -> NonNull<T> {
+        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<T>()));
+        static_assert!(Self::ALIGN_SIZE % mem::align_of::<T>() == 0);
+
+        let obj_ptr = self.into_raw_unpoisoned() as *mut T;
+        // SAFETY:
+        // 1. The slot is free;
+        // 2. The size and alignment of the slot satisfies all the
+        // requirements by `T`.
+        unsafe {
+            obj_ptr.write(obj);
+            NonNull::new_unchecked(obj_ptr)
+        }
+    }
+
+    /// Recovers the slot from a pointer returned by `into_nonnull`.
+    ///
+    /// Unlike `from_box`, the object is not dropped.
+    ///
+    /// # Safety
+    ///
+    /// The pointer must have been returned by `into_nonnull` of a slot of `SLOT_SIZE`,
+    /// and the object must have been dropped or moved out, and must not be used afterwards.
+    pub unsafe fn from_nonnull<T>(obj_ptr: NonNull<T>) -> Self {
+        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<T>()));
+
+        // SAFETY: The caller guarantees that the pointer refers to
+        // a slot of `SLOT_SIZE` in use, which is no longer used by the object.
+        unsafe {
+            Self::new(obj_ptr.as_ptr() as *mut u8)
+        }
+    }
+
+    pub fn into_arc<T>(self, data: T) -> Arc<T> {
+        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<ArcInner<T>>()));
+        static_assert!(Self::ALIGN_SIZE % mem::align_of::<ArcInner<T>>() == 0);
+
+        #[cfg(debug_assertions)]
+        check_arc_layout();
+
+        // Same as what `Arc::new` does.
+        let inner = ArcInner {
+            strong: atomic::AtomicUsize::new(1),
+            weak: atomic::AtomicUsize::new(1),
+            data,
+        };
+        let ptr = Box::leak(self.into_box(inner)) as *mut ArcInner<T>;
+        // SAFETY: The data structure of `Arc<T>` is defined as 
+        //
+        // pub struct Arc<T: ?Sized> {
+        //     ptr: NonNull<ArcInner<T>>,
+        //     phantom: PhantomData<ArcInner<T>>,
+        // }
+        //
+        // and `ptr` is not null. So this transmute is correct. 
+        unsafe {
+            mem::transmute(ptr)
+        }
+    }
+
+    /// Allocates an `Arc<[T]>` in the slot, with the elements yielded by the iterator.
+    ///
+    /// # Panics
+    ///
+    /// This method will panic if the `ArcInner<[T]>` of the given length
+    /// does not match the slot size, or the iterator yields
+    /// fewer elements than it reports.
+    pub fn into_arc_slice<T>(self, mut iter: impl ExactSizeIterator<Item = T>) -> Arc<[T]> {
+        static_assert!(Self::ALIGN_SIZE % mem::align_of::<ArcInner<[T; 0]>>() == 0);
+
+        let len = iter.len();
+        // The layout of `ArcInner<[T]>` is the header (`strong` and `weak`),
+        // followed by the packed elements. The length is only encoded in the fat pointer.
+        let header_size = mem::offset_of!(ArcInner<[T; 0]>, data);
+        let inner_size = len
+            .checked_mul(mem::size_of::<T>())
+            .and_then(|data_size| data_size.checked_add(header_size))
+            .expect("the slice is too long");
+        let inner_layout = Layout::from_size_align(inner_size, mem::align_of::<ArcInner<[T; 0]>>())
+            .unwrap()
+            .pad_to_align();
+        // The `Arc<[T]>` will be freed with this layout,
+        // which must be mapped to the slot size of this slot.
+        assert!(
+            does_slot_size_match_obj_size(SLOT_SIZE, inner_layout.size()),
+            "the slice of {} elements does not match the slot size of {}",
+            len,
+            SLOT_SIZE,
+        );
+
+        let inner_ptr = self.into_raw_unpoisoned() as *mut ArcInner<[T; 0]>;
+        // SAFETY: The slot is large enough and properly aligned for the header and the elements.
+        unsafe {
+            (&raw mut (*inner_ptr).strong).write(atomic::AtomicUsize::new(1));
+            (&raw mut (*inner_ptr).weak).write(atomic::AtomicUsize::new(1));
+            let elem_ptr = (&raw mut (*inner_ptr).data) as *mut T;
+            for i in 0..len {
+                let elem = iter.next().expect("the iterator yields fewer elements than reported");
+                elem_ptr.add(i).write(elem);
+            }
+        }
+
+        let fat_ptr = ptr::slice_from_raw_parts_mut(inner_ptr as *mut T, len) as *mut ArcInner<[T]>;
+        // SAFETY: Same as the transmute in `into_arc`, but with a fat pointer,
+        // whose metadata is the length of the slice.
+        unsafe {
+            mem::transmute(fat_ptr)
+        }
+    }
+
+    pub fn from_arc<T>(arc: Arc<T>) -> Self {
+        static_assert!(does_slot_size_match_obj_size(SLOT_SIZE, mem::size_of::<ArcInner<T>>()));
+
+        todo!()
+    }
+
+    // The next-slot link, which allows free slots to form intrusive lists
+    // (e.g., `FreeSlabSlotList`), is stored in the first word of the slot.
+    // `Option<FreeSlabSlot<SLOT_SIZE>>` has the same layout as a nullable pointer.
+    //
+    // The link is only initialized when the slot is pushed onto a list,
+    // as `FreeSlabSlot::new` must not write to a slot that it has not validated
+    // (e.g., a slot that is freed twice, or a slot of a dead slab).
+    fn next_slot_ptr(&self) -> *mut Option<FreeSlabSlot<SLOT_SIZE>> {
+        self.ptr.as_ptr() as *mut Option<FreeSlabSlot<SLOT_SIZE>>
+    }
+
+    // Reconstructs a slot from a raw pointer without initializing its next-slot link,
+    // which is already initialized as the slot is on an intrusive list.
+    //
+    // # Safety
+    //
+    // The pointer must refer to a slot on an intrusive list.
+    unsafe fn from_raw_link(ptr: *mut u8) -> Self {
+        Self {
+            ptr: NonNull::new_unchecked(ptr as *mut [u8; SLOT_SIZE]),
+        }
+    }
+
+    /// Sets the next-slot link, overwriting the old one without dropping it.
+    pub fn set_next_slot(&mut self, next: Option<FreeSlabSlot<SLOT_SIZE>>) {
+        // SAFETY: The slot is exclusively owned by `self` and large enough for a pointer.
+        unsafe { self.next_slot_ptr().write(next) };
+    }
+
+    /// Takes the next-slot link, leaving `None` in its place.
+    ///
+    /// # Safety
+    ///
+    /// The link must have been set with `set_next_slot`,
+    /// e.g., the slot is on an intrusive list.
+    pub unsafe fn take_next_slot(&mut self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        // SAFETY: The caller guarantees that the link is initialized.
+        unsafe { self.next_slot_ptr().replace(None) }
+    }
+
+    /// Replaces the next-slot link, returning the old one.
+    ///
+    /// # Safety
+    ///
+    /// The link must have been set with `set_next_slot`,
+    /// e.g., the slot is on an intrusive list.
+    pub unsafe fn replace_next_slot(&mut self, new: FreeSlabSlot<SLOT_SIZE>) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        // SAFETY: The caller guarantees that the link is initialized.
+        unsafe { self.next_slot_ptr().replace(Some(new)) }
+    }
+
+    /// Returns the next slot of the next-slot link.
+    ///
+    /// # Safety
+    ///
+    /// The link must have been set with `set_next_slot`,
+    /// e.g., the slot is on an intrusive list.
+    pub unsafe fn next_slot(&self) -> Option<&FreeSlabSlot<SLOT_SIZE>> {
+        // SAFETY: The caller guarantees that the link is initialized.
+        unsafe { (*self.next_slot_ptr()).as_ref() }
+    }
+
+    /// Marks the slot as borrowed by a slab cache,
+    /// i.e., the slot is parked on a cache-level free list
+    /// (e.g., `FreeSlabSlotList`), instead of being used by an object.
+    ///
+    /// Borrowed slots are still counted as in-use slots of the slab,
+    /// so the slab cannot be torn down until they are drained back to it.
+    pub fn mark_borrowed(&self) {
+        self.slab_meta().nr_borrowed_slots.fetch_add(1, Relaxed);
+    }
+
+    /// Unmarks the slot as borrowed by a slab cache.
+    pub fn unmark_borrowed(&self) {
+        let old_count = self.slab_meta().nr_borrowed_slots.fetch_sub(1, Release);
+        heap_assert!(full, old_count >= 1);
+    }
+
+    /// Recycles the slot to its parent slab.
+    ///
+    /// The slot is recycled via the `SlotRecycleFn` and the extension
+    /// stored in the slab metadata. So no reference to the slab cache
+    /// that has allocated the slot is required,
+    /// i.e., the lifetime of slots is decoupled from that of slab caches.
+    ///
+    /// If the parent slab has already been torn down (a bug of the caller),
+    /// the slot is reported as a bad free and leaked, instead of
+    /// dereferencing the function pointer in the dead metadata.
+    pub fn recycle(self, pin_cpu_guard: &dyn PinCurrentCpu) {
+        // SAFETY: Slabs outlive their slots (see `SlabMeta`).
+        // So the metadata remains valid after the slot is moved
+        // into the recycle function.
+        let slab_meta = unsafe { &*(self.slab_meta() as *const SlabMeta<()>) };
+        // The magic and the recycle function are read from the header,
+        // which is read-only with `slab_ro_meta`.
+        let header = slab_meta.header();
+        if header.magic.load(Relaxed) != SLAB_META_MAGIC {
+            let ptr = self.ptr.as_ptr() as *mut u8;
+            // The counters of the dead slab must not be touched.
+            mem::forget(self);
+            super::report_bad_free(BadFree::DeadSlab { ptr });
+            return;
+        }
+        let slot_recycle_fn = header.slot_recyle_fn;
+        slot_recycle_fn(self, slab_meta.extension_as_any(), pin_cpu_guard);
+    }
+
+    /// Returns a read-only view of the parent slab of the slot,
+    /// e.g., for a reclamation policy to inspect how free the slab is.
+    pub fn slab_view(&self) -> SlabView<'_> {
+        SlabView {
+            slab_meta: self.slab_meta(),
+        }
+    }
+
+    fn slab_meta(&self) -> &SlabMeta<()> {
+        // SAFETY: A slot always lies within its parent slab,
+        // whose metadata resides at `slab_meta_addr` of the slab.
+        let slab_base = slab_base_of::<SLOT_SIZE>(self.ptr.as_ptr() as _);
+        unsafe { &*(slab_meta_addr(slab_base) as *const SlabMeta<()>) }
+    }
+}
+
+/// An intrusive list of free slots, which are linked by their next-slot links.
+///
+/// The slots on the list are borrowed from their slabs
+/// (see `FreeSlabSlot::mark_borrowed`).
+pub struct FreeSlabSlotList<const SLOT_SIZE: usize> {
+    head: Option<FreeSlabSlot<SLOT_SIZE>>,
+    // The next-slot link of the last slot, which is `None`,
+    // or null if the list is empty. It allows splicing in O(1).
+    tail_link: *mut Option<FreeSlabSlot<SLOT_SIZE>>,
+    len: usize,
+}
+
+impl<const SLOT_SIZE: usize> FreeSlabSlotList<SLOT_SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            head: None,
+            tail_link: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    // Creates a list from a chain of slots that are already marked as borrowed.
+    //
+    // The chain is walked to find its tail and length.
+    fn from_chain(head: Option<FreeSlabSlot<SLOT_SIZE>>) -> Self {
+        let Some(head_slot) = head.as_ref() else {
+            return Self::new();
+        };
+        let mut len = 1;
+        let mut tail_slot = head_slot;
+        // SAFETY: The slots of a chain are linked by their next-slot links.
+        while let Some(next_slot) = unsafe { tail_slot.next_slot() } {
+            tail_slot = next_slot;
+            len += 1;
+        }
+        let tail_link = tail_slot.next_slot_ptr();
+        Self { head, tail_link, len }
+    }
+
+    pub fn push(&mut self, mut slot: FreeSlabSlot<SLOT_SIZE>) {
+        slot.mark_borrowed();
+        if self.head.is_none() {
+            self.tail_link = slot.next_slot_ptr();
+        }
+        // The link of a slot is initialized right when it is linked.
+        slot.set_next_slot(self.head.take());
+        self.head = Some(slot);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<FreeSlabSlot<SLOT_SIZE>> {
+        let mut slot = self.head.take()?;
+        // SAFETY: The slot has been linked by `push` or a chain of linked slots.
+        self.head = unsafe { slot.take_next_slot() };
+        if self.head.is_none() {
+            self.tail_link = ptr::null_mut();
+        }
+        self.len -= 1;
+        slot.unmark_borrowed();
+        Some(slot)
+    }
+
+    /// Returns the number of slots on the list.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends all the slots of `other` to the end of the list, in O(1).
+    pub fn splice(&mut self, other: FreeSlabSlotList<SLOT_SIZE>) {
+        let FreeSlabSlotList { head: other_head, tail_link: other_tail_link, len: other_len } = other;
+        if other_head.is_none() {
+            return;
+        }
+        if self.head.is_none() {
+            self.head = other_head;
+        } else {
+            // SAFETY: The tail link belongs to the last slot of the list,
+            // which is owned by the list. The link is `None`, so nothing is leaked.
+            unsafe { self.tail_link.write(other_head) };
+        }
+        self.tail_link = other_tail_link;
+        self.len += other_len;
+    }
+
+    /// Removes the slots that match the predicate from the list
+    /// and returns them as a new list.
+    ///
+    /// The slots that do not match are kept on the list.
+    /// Both lists preserve the relative order of the slots.
+    /// The removed slots remain borrowed, as they are still on a list.
+    pub fn drain_filter(
+        &mut self,
+        mut pred: impl FnMut(&FreeSlabSlot<SLOT_SIZE>) -> bool,
+    ) -> FreeSlabSlotList<SLOT_SIZE> {
+        let mut kept = Self::new();
+        let mut drained = Self::new();
+        let mut next = self.head.take();
+        while let Some(mut slot) = next {
+            // SAFETY: The slot is on the list, so its link is initialized.
+            next = unsafe { slot.take_next_slot() };
+            if pred(&slot) {
+                drained.push_back_borrowed(slot);
+            } else {
+                kept.push_back_borrowed(slot);
+            }
+        }
+        *self = kept;
+        drained
+    }
+
+    // Appends a slot that is already marked as borrowed,
+    // whose next-slot link is `None`.
+    fn push_back_borrowed(&mut self, slot: FreeSlabSlot<SLOT_SIZE>) {
+        let tail_link = slot.next_slot_ptr();
+        if self.head.is_none() {
+            self.head = Some(slot);
+        } else {
+            // SAFETY: The tail link belongs to the last slot of the list,
+            // which is owned by the list. The link is `None`, so nothing is leaked.
+            unsafe { self.tail_link.write(Some(slot)) };
+        }
+        self.tail_link = tail_link;
+        self.len += 1;
+    }
+}
+impl<const SLOT_SIZE: usize> Default for FreeSlabSlotList<SLOT_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A lock-free queue of free slots, which are linked by their next-slot links.
+///
+/// The queue has multiple producers but a single consumer: any CPU can push
+/// slots into the queue, but only the owner of the queue can take them out,
+/// all at once. As the consumer never pops a single slot,
+/// the queue (a Treiber stack) is free from the ABA problem.
+///
+/// The slots in the queue are borrowed from their slabs
+/// (see `FreeSlabSlot::mark_borrowed`).
+pub struct RemoteFreeQueue<const SLOT_SIZE: usize> {
+    head: AtomicPtr<u8>,
+}
+
+impl<const SLOT_SIZE: usize> RemoteFreeQueue<SLOT_SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Pushes a free slot into the queue.
+    pub fn push(&self, mut slot: FreeSlabSlot<SLOT_SIZE>) {
+        slot.mark_borrowed();
+
+        let mut old_head = self.head.load(Relaxed);
+        loop {
+            // SAFETY: A non-null head always refers to a slot in the queue.
+            let next = (!old_head.is_null()).then(|| unsafe { FreeSlabSlot::from_raw_link(old_head) });
+            // The link of the slot is garbage before it is linked, so it is
+            // overwritten, not replaced. The old head is only reconstructed to be linked.
+            slot.set_next_slot(next);
+
+            // The `Release` ordering publishes the next-slot link to the consumer.
+            match self.head.compare_exchange_weak(old_head, slot.ptr.as_ptr() as _, Release, Relaxed) {
+                Ok(_) => break,
+                Err(current_head) => old_head = current_head,
+            }
+        }
+        mem::forget(slot);
+    }
+
+    /// Takes all the slots out of the queue.
+    ///
+    /// The queue is taken as a whole with one atomic swap, so the method is safe
+    /// to call on any CPU, e.g., to return the slots to the slabs of the owner
+    /// when shrinking. The owner is the only one that pops the slots for allocation.
+    pub fn take_all(&self) -> FreeSlabSlotList<SLOT_SIZE> {
+        // The `Acquire` ordering pairs with the `Release` ordering in `push`.
+        let head = self.head.swap(ptr::null_mut(), Acquire);
+        // SAFETY: The slots in the chain are now exclusively owned by the consumer,
+        // and already marked as borrowed.
+        let head = (!head.is_null()).then(|| unsafe { FreeSlabSlot::from_raw_link(head) });
+        FreeSlabSlotList::from_chain(head)
+    }
+}
+
+// The contents of the slot are not printed, as they may be uninitialized.
+impl<const SLOT_SIZE: usize> fmt::Debug for FreeSlabSlot<SLOT_SIZE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FreeSlabSlot")
+            .field("ptr", &self.ptr)
+            .field("slot_size", &SLOT_SIZE)
+            .finish()
+    }
+}
+
+// Each in-use slot decrements `nr_inuse_slots` exactly once:
+// either in `Slab::recycle_slot`, which forgets the slot,
+// or here, when a slot is discarded without being recycled.
+// The paths that keep the slot in use (`into_raw`, `into_box`, etc.)
+// forget the slot so that this `Drop` never runs for them.
+impl<const SLOT_SIZE: usize, Ext> Drop for FreeSlabSlot<SLOT_SIZE, Ext> {
+    fn drop(&mut self) {
+        // The parent slab can only be droppped if this counter is reduced to zero
+        self.slab_meta().nr_inuse_slots.fetch_sub(1, Release);
+    }
+}
+
+// The inner data structure of Arc<T> on the heap.
+//
+// The interface of `Arc<T, A>` already allows
+// specifying a custom allocator of type `A: Allocator`.
 // So in theory, allocating an `Arc` from `FreeSlabSlot`
 // can be expressed as `Arc::<T, FreeSlabSlot>::new()`
 // if we implement the `Allocator` trait for `FreeSlabSlot`.
@@ -239,24 +1928,288 @@ struct ArcInner<T: ?Sized> {
     data: T,
 }
 
+// Checks, once, that the layout of a real `Arc` matches our `ArcInner`.
+//
+// The check turns a silent memory corruption into a loud failure,
+// should a future version of Rust change the layout of `Arc`.
+#[cfg(debug_assertions)]
+fn check_arc_layout() {
+    static CHECKED: Once<()> = Once::new();
+
+    CHECKED.call_once(|| {
+        // A strong count of 1 and a weak count of 2 (including the implicit one).
+        const MARKER: u64 = 0xA5A5_5A5A_A5A5_5A5A;
+        let arc = Arc::new(MARKER);
+        let weak = Arc::downgrade(&arc);
+
+        let data_ptr = Arc::as_ptr(&arc) as *const u8;
+        let inner_ptr = data_ptr.wrapping_sub(mem::offset_of!(ArcInner<u64>, data));
+        // SAFETY: If the layouts match, the fields are in the `ArcInner` of the `Arc`,
+        // which is alive. Otherwise, the check is going to fail anyway.
+        let (strong, weak_count) = unsafe {
+            let strong_ptr = inner_ptr.add(mem::offset_of!(ArcInner<u64>, strong)) as *const usize;
+            let weak_ptr = inner_ptr.add(mem::offset_of!(ArcInner<u64>, weak)) as *const usize;
+            (strong_ptr.read(), weak_ptr.read())
+        };
+        assert!(
+            strong == 1 && weak_count == 2,
+            "the layout of `Arc` does not match `ArcInner`: strong = {}, weak = {}",
+            strong,
+            weak_count,
+        );
+        drop(weak);
+    });
+}
+
+/// The lifecycle hooks of slabs, implemented by slab extensions.
+///
+/// The hooks allow the OSTD user to do custom accounting per slab,
+/// e.g., updating the counter of a NUMA node.
+/// They only take effect for the slabs allocated with `Slab::alloc_with_lifecycle`,
+/// so the extensions that do not need them (e.g., `()`) pay nothing.
+///
+/// The hooks must not allocate memory from the heap.
+pub trait SlabLifecycle {
+    /// Called after a slab of `nr_slots` slots is created at `slab_base`.
+    fn on_slab_created(&self, slab_base: *const u8, nr_slots: usize);
+
+    /// Called before a slab of `nr_slots` slots at `slab_base` is destroyed.
+    fn on_slab_destroyed(&self, slab_base: *const u8, nr_slots: usize);
+}
+
+/// The hook of slab extensions that is called on every slot allocation,
+/// e.g., to record when a slab was last allocated from (see `AgeExt`).
+///
+/// The hook only takes effect for the slabs allocated with `Slab::alloc_with_slot_hook`.
+/// The other slabs pay one branch on the allocation path.
+///
+/// The hook must be cheap and must not allocate memory from the heap.
+pub trait SlotAllocHook {
+    /// Called after a slot is allocated from the slab.
+    fn on_slot_allocated(&self);
+}
+
 /// The metadata for a slab.
+///
+/// A slab must outlive all of its slots, i.e., a slab can only be
+/// torn down after all of its slots have been recycled. This invariant
+/// allows a slot to be recycled solely with the `slot_recyle_fn` and
+/// the extension stored in the metadata, even after the slab cache
+/// that has allocated the slot is gone.
 // It is important to specify `repr(c)` here,
 // which ensures that the memory layout of `SlabMeta<SLOT_SIZE, Ext>` and 
 // `SlabMeta<SLOT_SIZE, ()>` are the same except for the last field.
-#[repr(C)]
+//
+// The hot fields, `free_list`, `nr_inuse_slots`, `nr_borrowed_slots` and
+// `reclaim_guard`, which are written on the allocation and deallocation paths,
+// occupy a cache line of their own.
+// So reading the mostly-immutable fields (e.g., the extension) on other CPUs
+// does not falsely share the cache line with the hot path.
+// The trade-off is that the metadata takes one more cache line from the slab.
+#[repr(C, align(64))]
 pub(crate) struct SlabMeta<const SLOT_SIZE: usize, Ext> {
     free_list: AtomicPtr<LinkedSlabSlot>,
+    // The last slot of the free list, to which the recycled slots are appended.
+    #[cfg(feature = "slab_fifo_free_list")]
+    free_list_tail: AtomicPtr<LinkedSlabSlot>,
     nr_inuse_slots: AtomicU16,
-    slot_recyle_fn: SlotRecycleFn,
+    // The number of in-use slots that are parked on cache-level free lists.
+    nr_borrowed_slots: AtomicU16,
+    // One of `RECLAIM_GUARD_IDLE`, `RECLAIM_GUARD_BUSY` and `RECLAIM_GUARD_RECLAIMING`
+    // (see `Slab::try_begin_alloc` and `Slab::try_begin_reclaim`).
+    reclaim_guard: AtomicU8,
+    // The padding does not depend on `Ext`,
+    // so the layout-compatibility with `SlabMeta<SLOT_SIZE, ()>` is kept.
+    _hot_padding: [u8; SLAB_META_HOT_PADDING],
+    nr_pages: u16,
+    // The slot size, which is also encoded in the type as `SLOT_SIZE`,
+    // but is needed when the type is erased.
+    slot_size: u16,
+    // The total number of slots, excluding the metadata region and the color.
+    nr_total_slots: u16,
+    // The offset of the first slot from the slab base (see `Slab::slot_layout_of`).
+    slot_offset: u32,
+    // Whether the slots are shifted by a color (see `Slab::is_colored`).
+    is_colored: bool,
+    // The immutable parts of the metadata, which reside in
+    // the read-only first page of the slab with `slab_ro_meta` instead.
+    #[cfg(not(feature = "slab_ro_meta"))]
+    header: SlabMetaHeader,
+    // One bit per slot, which is set if and only if the slot is free.
+    // The bitmap is maintained on every allocation and deallocation,
+    // so that the maintenance operations (e.g., `Slab::coalesce_free_list`)
+    // can tell the free slots without walking the free list.
+    //
+    // The bitmap has a fixed size regardless of `Ext`,
+    // so the layout-compatibility with `SlabMeta<SLOT_SIZE, ()>` is kept.
+    freed_bitmap: FreedSlotBitmap,
+    // The hook to call when the slab is destroyed (see `SlabLifecycle`).
+    //
+    // A function pointer has the same layout regardless of `Ext`,
+    // so the layout-compatibility with `SlabMeta<SLOT_SIZE, ()>` is kept.
+    on_slab_destroyed: Option<fn(&Ext, *const u8, usize)>,
+    // The hook to call when a slot is allocated (see `SlotAllocHook`).
+    on_slot_allocated: Option<fn(&Ext)>,
+    // The bases of the neighboring slabs in an intrusive list of slabs
+    // (see `Slab::link_after`), or null if there are none.
+    //
+    // The links are only updated by the owner of the list,
+    // but are atomic so that the metadata can be shared across CPUs.
+    next_slab: AtomicPtr<u8>,
+    prev_slab: AtomicPtr<u8>,
     // The extension provided by the OSTD user is stored in two fields.
     //
-    // The first field stores the vtable of `Ext`` as an `dyn Any` trait object.
-    extension_vtable: DynMetadata<dyn Any + 'static>,
-    // The second first stores the real content of `Ext`.
+    // The first field, `SlabMetaHeader::extension_vtable`, stores the vtable
+    // of `Ext` as an `dyn Any` trait object.
+    // The second field stores the real content of `Ext`.
     extension: Ext,
 }
 
+/// The immutable parts of the metadata of a slab,
+/// which are only written when the slab is initialized,
+/// re-homed (see `Slab::rehome`) or torn down.
+///
+/// The header does not depend on `Ext`, so the type-erased metadata
+/// can recover the recycle function and the extension from it.
+/// With the `slab_ro_meta` feature, the header resides at the base of the slab
+/// in a page of its own, which is mapped read-only
+/// by `page_alloc::protect_pages` except during the updates.
+#[repr(C)]
+pub(crate) struct SlabMetaHeader {
+    // `SLAB_META_MAGIC` while the slab is alive, or `DEAD_SLAB_MAGIC`
+    // after the slab has been torn down.
+    magic: AtomicU32,
+    slot_recyle_fn: SlotRecycleFn,
+    extension_vtable: DynMetadata<dyn Any + 'static>,
+}
+
+// A xorshift PRNG, which is cheap but not cryptographic.
+#[cfg(feature = "slab_aslr")]
+struct XorShift64 {
+    state: u64,
+}
+
+#[cfg(feature = "slab_aslr")]
+impl XorShift64 {
+    fn new(seed: u64) -> Self {
+        // The state must never be zero.
+        Self { state: seed | 1 }
+    }
+
+    fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+// Hints the CPU to fetch the cache line of a slot for reading.
+//
+// A prefetch never faults, so it may be issued for a slot
+// that is allocated or recycled by others in the meantime.
+#[cfg(feature = "slab_prefetch")]
+#[inline(always)]
+fn prefetch_slot(slot_ptr: *const u8) {
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: Prefetching has no side effects on the program semantics,
+    // even if the pointer were invalid.
+    unsafe {
+        use core::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>(slot_ptr as *const i8);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    let _ = slot_ptr;
+}
+
+#[cfg(feature = "slab_aslr")]
+fn gcd(mut a: usize, mut b: usize) -> usize {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+// Returns the size of the metadata region at the base of a slab,
+// including the header page with `slab_ro_meta`.
+//
+// The size of the metadata is rounded up to the slot size,
+// so that the first slot is aligned and does not overlap the metadata.
+const fn slab_meta_region_size<const SLOT_SIZE: usize, Ext>() -> usize {
+    SLAB_META_OFFSET + mem::size_of::<SlabMeta<SLOT_SIZE, Ext>>().next_multiple_of(SLOT_SIZE)
+}
+
+// The offset of `SlabMeta` from the base of a slab.
+//
+// With `slab_ro_meta`, the first page of a slab holds the read-only header,
+// and the mutable metadata is at the beginning of the second page.
+#[cfg(not(feature = "slab_ro_meta"))]
+const SLAB_META_OFFSET: usize = 0;
+#[cfg(feature = "slab_ro_meta")]
+const SLAB_META_OFFSET: usize = PAGE_SIZE;
+
+// Returns the address of the metadata of the slab at `slab_base`.
+//
+// Both the metadata and the header are located from the base of the slab,
+// which is recovered from a slot by masking.
+// So a corrupted field of the metadata never redirects the lookup of the header.
+fn slab_meta_addr(slab_base: *const u8) -> *const u8 {
+    slab_base.wrapping_add(SLAB_META_OFFSET)
+}
+
+// The number of slots of a slab must fit in the counters of `SlabMeta`.
+static_assert!(MAX_PAGES_PER_SLAB * PAGE_SIZE / MIN_SLAB_SLOT_SIZE <= u16::MAX as usize);
+
+// The magic of the metadata of a live slab.
+const SLAB_META_MAGIC: u32 = 0x5AB5_1AB0;
+// The magic of the metadata of a slab that has been torn down.
+const DEAD_SLAB_MAGIC: u32 = 0xDEAD_5AB0;
+
+// The states of the reclaim guard of a slab.
+const RECLAIM_GUARD_IDLE: u8 = 0;
+const RECLAIM_GUARD_BUSY: u8 = 1;
+const RECLAIM_GUARD_RECLAIMING: u8 = 2;
+
+// The size of a cache line.
+const CACHE_LINE_SIZE: usize = 64;
+
+// The padding after the hot fields of `SlabMeta` to fill up a cache line.
+const SLAB_META_HOT_PADDING: usize = CACHE_LINE_SIZE
+    - mem::size_of::<AtomicPtr<LinkedSlabSlot>>() * NR_FREE_LIST_ENDS
+    - mem::size_of::<AtomicU16>() * 2
+    - mem::size_of::<AtomicU8>();
+
+// The number of the ends of the free list tracked by `SlabMeta`.
+#[cfg(not(feature = "slab_fifo_free_list"))]
+const NR_FREE_LIST_ENDS: usize = 1;
+#[cfg(feature = "slab_fifo_free_list")]
+const NR_FREE_LIST_ENDS: usize = 2;
+
+static_assert!(mem::offset_of!(SlabMeta<MIN_SLAB_SLOT_SIZE, ()>, free_list) == 0);
+static_assert!(mem::offset_of!(SlabMeta<MIN_SLAB_SLOT_SIZE, ()>, reclaim_guard) < CACHE_LINE_SIZE);
+static_assert!(mem::offset_of!(SlabMeta<MIN_SLAB_SLOT_SIZE, ()>, nr_pages) == CACHE_LINE_SIZE);
+
 impl<const SLOT_SIZE: usize, Ext> SlabMeta<SLOT_SIZE, Ext> {
+    /// Returns the base address of the slab.
+    pub fn slab_base_ptr(&self) -> *const u8 {
+        (self as *const Self as *const u8).wrapping_sub(SLAB_META_OFFSET)
+    }
+
+    /// Returns the immutable parts of the metadata.
+    #[cfg(not(feature = "slab_ro_meta"))]
+    pub fn header(&self) -> &SlabMetaHeader {
+        &self.header
+    }
+
+    /// Returns the immutable parts of the metadata,
+    /// which reside in the read-only first page of the slab.
+    #[cfg(feature = "slab_ro_meta")]
+    pub fn header(&self) -> &SlabMetaHeader {
+        // SAFETY: The header resides at the base of the slab,
+        // which lives as long as the metadata.
+        unsafe { &*(self.slab_base_ptr() as *const SlabMetaHeader) }
+    }
+
     pub fn extension(&self) -> &Ext {
         &self.extension
     }
@@ -267,12 +2220,1124 @@ impl<const SLOT_SIZE: usize, Ext> SlabMeta<SLOT_SIZE, Ext> {
     /// For example, the `SlotRecycleFn` function signatures
     /// takes the type-erased slab metadata of `SlabMeta<_, ()>`.
     pub fn extension_as_any(&self) -> &dyn Any {
-        todo!("recover the trait object from user_meta_vtable") 
+        todo!("recover the trait object from `self.header().extension_vtable`")
+    }
+}
+
+/// A bitmap that records which slots of a slab are free.
+///
+/// Checking the bitmap upon recycling a slot detects double free in O(1),
+/// and the maintenance operations of a slab query the bitmap for the free slots.
+struct FreedSlotBitmap {
+    bits: [AtomicU64; FreedSlotBitmap::NR_WORDS],
+}
+
+impl FreedSlotBitmap {
+    // A slab has at most `MAX_PAGES_PER_SLAB * PAGE_SIZE / MIN_SLAB_SLOT_SIZE` slots.
+    const NR_WORDS: usize =
+        (MAX_PAGES_PER_SLAB * PAGE_SIZE / MIN_SLAB_SLOT_SIZE).div_ceil(u64::BITS as usize);
+
+    pub const fn new() -> Self {
+        Self {
+            bits: [const { AtomicU64::new(0) }; Self::NR_WORDS],
+        }
+    }
+
+    /// Sets the bit of a slot, returning whether the bit was already set.
+    pub fn test_and_set(&self, slot_idx: usize) -> bool {
+        let (word_idx, mask) = Self::locate(slot_idx);
+        let old_word = self.bits[word_idx].fetch_or(mask, Relaxed);
+        old_word & mask != 0
+    }
+
+    /// Returns whether the bit of a slot is set.
+    pub fn test(&self, slot_idx: usize) -> bool {
+        let (word_idx, mask) = Self::locate(slot_idx);
+        self.bits[word_idx].load(Relaxed) & mask != 0
+    }
+
+    /// Clears the bit of a slot.
+    pub fn clear(&self, slot_idx: usize) {
+        let (word_idx, mask) = Self::locate(slot_idx);
+        self.bits[word_idx].fetch_and(!mask, Relaxed);
+    }
+
+    fn locate(slot_idx: usize) -> (usize, u64) {
+        let bits_per_word = u64::BITS as usize;
+        (slot_idx / bits_per_word, 1 << (slot_idx % bits_per_word))
+    }
+}
+
+/// A slab extension that records the CPU owning the slab.
+///
+/// Per-CPU slab caches implement this trait for their slab extensions
+/// so that a recycled slot can be routed back to its owner CPU
+/// with [`owner_cpu_of`].
+pub trait HasOwnerCpu: Any {
+    fn owner_cpu(&self) -> CpuId;
+}
+
+/// Recovers the owner CPU from a type-erased slab extension.
+///
+/// This is meant to be used in a [`SlotRecycleFn`].
+///
+/// # Panics
+///
+/// This function will panic if the concrete type of `extension` is not `E`.
+pub fn owner_cpu_of<E: HasOwnerCpu>(extension: &dyn Any) -> CpuId {
+    let Some(extension) = extension.downcast_ref::<E>() else {
+        panic!("the slab extension is expected to be {}", core::any::type_name::<E>());
+    };
+    extension.owner_cpu()
+}
+
+/// A slab extension that remembers when a slot was last allocated from the slab.
+///
+/// `Slab::new_slot` updates the tick with the clock set by `heap::set_clock`
+/// for the slabs allocated with `Slab::alloc_with_slot_hook`,
+/// which allows reclaiming the cold slabs first (see `oldest_slab`).
+pub struct AgeExt {
+    owner_cpu: CpuId,
+    last_alloc_tick: AtomicU64,
+}
+
+impl AgeExt {
+    pub fn new(owner_cpu: CpuId) -> Self {
+        Self {
+            owner_cpu,
+            last_alloc_tick: AtomicU64::new(super::now_tick()),
+        }
+    }
+
+    /// Returns the tick when a slot was last allocated from the slab.
+    pub fn last_alloc_tick(&self) -> u64 {
+        self.last_alloc_tick.load(Relaxed)
+    }
+
+}
+
+impl SlotAllocHook for AgeExt {
+    fn on_slot_allocated(&self) {
+        self.last_alloc_tick.store(super::now_tick(), Relaxed);
+    }
+}
+
+impl Clone for AgeExt {
+    fn clone(&self) -> Self {
+        Self::new(self.owner_cpu)
+    }
+}
+
+impl HasOwnerCpu for AgeExt {
+    fn owner_cpu(&self) -> CpuId {
+        self.owner_cpu
     }
 }
 
+/// Returns the slab whose slots were allocated least recently,
+/// which is the preferred victim of reclamation.
+pub fn oldest_slab<'a, const SLOT_SIZE: usize>(
+    slabs: impl IntoIterator<Item = &'a Slab<SLOT_SIZE, AgeExt>>,
+) -> Option<&'a Slab<SLOT_SIZE, AgeExt>> {
+    slabs
+        .into_iter()
+        .min_by_key(|slab| slab.slab_extension().last_alloc_tick())
+}
+
 pub type SlotRecycleFn<const SLOT_SIZE: usize> = fn(
     /* slot: */FreeSlabSlot<SLOT_SIZE>,
     /* extension: */&dyn Any,
     /* pin_cpu_guard: */&dyn PinCurrentCpu,
-);
\ No newline at end of file
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::TestHeap;
+
+    fn recycle_slot(_slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+        unreachable!("the slots of the tests are recycled to their slabs directly");
+    }
+
+    // Overwrites the link of the first free slot of the slab with `corrupt`,
+    // validates the slab, and restores the link.
+    fn validate_with_corrupted_head(
+        slab: &Slab<64, ()>,
+        corrupt: impl FnOnce(*mut LinkedSlabSlot) -> *mut LinkedSlabSlot,
+    ) -> Result<(), SlabCorruption> {
+        let head_ptr = slab.slab_meta().free_list.load(Relaxed);
+        assert!(!head_ptr.is_null());
+        // SAFETY: The head is a free slot of the slab, which is not used by anyone.
+        unsafe {
+            let old_next = (*head_ptr).next;
+            (*head_ptr).next = corrupt(head_ptr);
+            let result = slab.validate();
+            (*head_ptr).next = old_next;
+            result
+        }
+    }
+
+    #[test]
+    fn healthy_slab_is_valid() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        slab.validate().unwrap();
+
+        let slots = [slab.new_slot().unwrap(), slab.new_slot().unwrap()];
+        slab.validate().unwrap();
+        for slot in slots {
+            slab.recycle_slot(slot);
+        }
+        slab.validate().unwrap();
+    }
+
+    #[test]
+    fn corrupted_slabs_are_invalid() {
+        let _test_heap = TestHeap::lock(16);
+        let slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        assert!(slab.nr_total_slots() > 2);
+
+        assert_eq!(
+            validate_with_corrupted_head(&slab, |head_ptr| head_ptr),
+            Err(SlabCorruption::FreeListCycle),
+        );
+        let slab_base = slab.base_ptr() as *mut LinkedSlabSlot;
+        assert_eq!(
+            validate_with_corrupted_head(&slab, |_| slab_base),
+            Err(SlabCorruption::SlotOutOfRange { slot_addr: slab_base as usize }),
+        );
+        let misaligned_ptr = |head_ptr: *mut LinkedSlabSlot| head_ptr.wrapping_byte_add(8);
+        assert_eq!(
+            validate_with_corrupted_head(&slab, misaligned_ptr),
+            Err(SlabCorruption::MisalignedSlot {
+                slot_addr: misaligned_ptr(slab.slab_meta().free_list.load(Relaxed)) as usize,
+            }),
+        );
+        // Cutting off the free list loses all the free slots but the head.
+        assert_eq!(
+            validate_with_corrupted_head(&slab, |_| ptr::null_mut()),
+            Err(SlabCorruption::CountMismatch { nr_free_slots: 1, nr_inuse_slots: 0 }),
+        );
+
+        // The slab is intact after the links are restored.
+        slab.validate().unwrap();
+    }
+
+    #[test]
+    fn remote_free_queue_loses_no_slot_of_concurrent_producers() {
+        const NR_PRODUCERS: usize = 4;
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        // The slots are sent to the producers as addresses, as slots are not `Send`.
+        let mut slot_addrs = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            slot_addrs.push(slot.into_raw() as usize);
+        }
+
+        let queue = RemoteFreeQueue::<64>::new();
+        std::thread::scope(|scope| {
+            for producer_addrs in slot_addrs.chunks(slot_addrs.len().div_ceil(NR_PRODUCERS)) {
+                let queue = &queue;
+                scope.spawn(move || {
+                    for &slot_addr in producer_addrs {
+                        // SAFETY: Each slot is converted back from its raw pointer exactly once.
+                        queue.push(unsafe { FreeSlabSlot::from_raw(slot_addr as *mut u8) });
+                    }
+                });
+            }
+        });
+        assert_eq!(slab.nr_borrowed_slots(), slot_addrs.len());
+
+        let mut taken_slots = queue.take_all();
+        assert_eq!(taken_slots.len(), slot_addrs.len());
+        assert!(queue.take_all().is_empty());
+        let mut taken_addrs = Vec::new();
+        while let Some(slot) = taken_slots.pop() {
+            taken_addrs.push(slot.as_ptr() as usize);
+            slab.recycle_slot(slot);
+        }
+        taken_addrs.sort_unstable();
+        slot_addrs.sort_unstable();
+        assert_eq!(taken_addrs, slot_addrs);
+        slab.validate().unwrap();
+    }
+
+    #[test]
+    fn reclaim_never_races_with_an_in_flight_allocation() {
+        const NR_ALLOCATORS: usize = 3;
+        const NR_ROUNDS: usize = 10_000;
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let epoch_before = heap_epoch();
+
+        // The threads share the slab by a raw pointer. Its slots are allocated
+        // and recycled while holding an allocation guard, which excludes
+        // the other allocators, as a lockless slab cache would do.
+        struct SharedSlab(*mut Slab<64, ()>);
+        // SAFETY: The slab is only mutated while holding an allocation guard.
+        unsafe impl Sync for SharedSlab {}
+        let shared_slab = SharedSlab(&raw mut slab);
+        let is_reclaimed = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            for _ in 0..NR_ALLOCATORS {
+                scope.spawn(|| {
+                    // SAFETY: The slab outlives the threads.
+                    let slab = unsafe { &*shared_slab.0 };
+                    for _ in 0..NR_ROUNDS {
+                        let Some(alloc_guard) = slab.try_begin_alloc() else {
+                            core::hint::spin_loop();
+                            continue;
+                        };
+                        assert!(!is_reclaimed.load(Acquire), "a slot is allocated from a reclaimed slab");
+                        // SAFETY: The guard excludes the other allocators.
+                        let slot = unsafe { (*shared_slab.0).new_slot() }.unwrap();
+                        drop(alloc_guard);
+
+                        // The slot stays usable while the reclamation runs concurrently.
+                        let slot_ptr = slot.into_raw();
+                        // SAFETY: The slot is valid for 64 bytes and owned by this thread.
+                        unsafe {
+                            slot_ptr.write_bytes(0x5A, 64);
+                            assert!((0..64).all(|i| *slot_ptr.add(i) == 0x5A));
+                        }
+
+                        // SAFETY: The slot is converted back from its raw pointer exactly once.
+                        let slot = unsafe { FreeSlabSlot::<64>::from_raw(slot_ptr) };
+                        // The slab cannot be claimed while the slot is in use,
+                        // so the guard is only busy transiently.
+                        let recycle_guard = loop {
+                            if let Some(guard) = slab.try_begin_alloc() {
+                                break guard;
+                            }
+                            core::hint::spin_loop();
+                        };
+                        // SAFETY: The guard excludes the other allocators.
+                        unsafe { (*shared_slab.0).recycle_slot(slot) };
+                        drop(recycle_guard);
+                    }
+                });
+            }
+            scope.spawn(|| {
+                // SAFETY: The slab outlives the threads.
+                let slab = unsafe { &*shared_slab.0 };
+                while !slab.try_begin_reclaim() {
+                    core::hint::spin_loop();
+                }
+                is_reclaimed.store(true, Release);
+            });
+        });
+
+        // The claim is final and counted once.
+        assert!(slab.try_begin_alloc().is_none());
+        assert!(!slab.try_begin_reclaim());
+        assert_eq!(heap_epoch(), epoch_before + 1);
+        assert_eq!(slab.nr_used_slots(), 0);
+    }
+
+    #[test]
+    fn slab_is_dropped_after_slots_are_freed_on_other_threads() {
+        const NR_THREADS: usize = 4;
+        const NR_ROUNDS: usize = 100;
+        let _test_heap = TestHeap::lock(16);
+        for _ in 0..NR_ROUNDS {
+            let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+            // The slots are sent to the threads as addresses, as slots are not `Send`.
+            let mut slot_addrs = Vec::new();
+            while let Some(slot) = slab.new_slot() {
+                slot_addrs.push(slot.into_raw() as usize);
+            }
+
+            // Each thread discards its slots, which decrements the in-use counter
+            // with `Release`. The teardown must observe all the decrements.
+            std::thread::scope(|scope| {
+                for thread_addrs in slot_addrs.chunks(slot_addrs.len().div_ceil(NR_THREADS)) {
+                    scope.spawn(move || {
+                        for &slot_addr in thread_addrs {
+                            // SAFETY: Each slot is converted back from its raw pointer exactly once.
+                            drop(unsafe { FreeSlabSlot::<64>::from_raw(slot_addr as *mut u8) });
+                        }
+                    });
+                }
+            });
+            drop(slab);
+        }
+    }
+
+    #[test]
+    fn values_at_different_offsets_are_read_back() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let mut slot = slab.new_slot().unwrap();
+
+        // SAFETY: The two values do not overlap.
+        unsafe {
+            slot.write_at::<u32>(0, 0xDEAD_BEEF);
+            slot.write_at::<u64>(8, 0x0123_4567_89AB_CDEF);
+        }
+        // SAFETY: The values are written above, and both are `Copy`.
+        unsafe {
+            assert_eq!(slot.read_at::<u32>(0), 0xDEAD_BEEF);
+            assert_eq!(slot.read_at::<u64>(8), 0x0123_4567_89AB_CDEF);
+        }
+        slab.recycle_slot(slot);
+    }
+
+    #[test]
+    #[should_panic(expected = "the value is out of the slot bounds")]
+    fn value_beyond_the_slot_is_rejected() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let mut slot = slab.new_slot().unwrap();
+        // SAFETY: The write panics before touching the memory.
+        unsafe { slot.write_at::<u64>(60, 0) };
+    }
+
+    #[test]
+    fn slab_of_a_class_has_the_configured_pages() {
+        let _test_heap = TestHeap::lock(16);
+        set_pages_per_slab(SizeClass::B64, 4);
+        let slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+
+        assert_eq!(slab.nr_pages(), 4);
+        // All the slots but those overlapping the metadata.
+        let nr_total_slots = slab.nr_total_slots();
+        let nr_meta_slots = slab_meta_region_size::<64, ()>().div_ceil(64);
+        assert_eq!(nr_total_slots, 4 * PAGE_SIZE / 64 - nr_meta_slots);
+        // The size of the slab is fixed once a slab of the class is allocated.
+        assert!(std::panic::catch_unwind(|| set_pages_per_slab(SizeClass::B64, 2)).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "the cache-level free lists must be drained before the slab is dropped")]
+    fn slab_with_borrowed_slots_cannot_be_dropped() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let mut parked_slots = FreeSlabSlotList::new();
+        parked_slots.push(slab.new_slot().unwrap());
+        // The list is leaked, as the slab is required to outlive its slots.
+        mem::forget(parked_slots);
+        drop(slab);
+    }
+
+    #[test]
+    fn slot_round_trips_through_raw_parts() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let slot = slab.new_slot().unwrap();
+        let slot_ptr = slot.as_ptr();
+
+        let (raw_ptr, size) = slot.into_raw_parts();
+        assert_eq!((raw_ptr as *const u8, size), (slot_ptr as *const u8, 64));
+        // SAFETY: The parts are returned by `into_raw_parts` above.
+        let slot = unsafe { FreeSlabSlot::<64>::from_raw_parts(raw_ptr, size) }.unwrap();
+        assert_eq!(slot.as_ptr(), slot_ptr);
+        assert_eq!(slab.nr_used_slots(), 1);
+        slab.recycle_slot(slot);
+    }
+
+    #[test]
+    fn raw_parts_of_a_mismatched_size_are_rejected() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let (raw_ptr, size) = slab.new_slot().unwrap().into_raw_parts();
+
+        // SAFETY: The parts are returned by `into_raw_parts` above.
+        // The rejected pointers are not used.
+        unsafe {
+            assert!(FreeSlabSlot::<128>::from_raw_parts(raw_ptr, size).is_none());
+            assert!(FreeSlabSlot::<64>::from_raw_parts(raw_ptr, 128).is_none());
+            assert!(FreeSlabSlot::<64>::from_raw_parts(raw_ptr, 40).is_none());
+        }
+        // SAFETY: The parts are returned by `into_raw_parts` above.
+        let slot = unsafe { FreeSlabSlot::<64>::from_raw_parts(raw_ptr, size) }.unwrap();
+        slab.recycle_slot(slot);
+    }
+
+    #[test]
+    fn const_class_index_equals_the_runtime_index() {
+        const CONST_INDEXES: [usize; NR_SLAB_CLASSES] = [
+            FreeSlabSlot::<16>::INDEX,
+            FreeSlabSlot::<32>::INDEX,
+            FreeSlabSlot::<64>::INDEX,
+            FreeSlabSlot::<128>::INDEX,
+            FreeSlabSlot::<256>::INDEX,
+            FreeSlabSlot::<512>::INDEX,
+            FreeSlabSlot::<1024>::INDEX,
+            FreeSlabSlot::<2048>::INDEX,
+        ];
+        for (size_class, const_index) in SizeClass::ALL.into_iter().zip(CONST_INDEXES) {
+            // The slot size is opaque to the compiler, so the index is computed at runtime.
+            let slot_size = core::hint::black_box(size_class.bytes());
+            assert_eq!(super::super::size_class_index(slot_size), const_index, "{size_class:?}");
+            assert_eq!(size_class.index(), const_index, "{size_class:?}");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "slab_alloc_poison")]
+    fn raw_slot_is_filled_with_the_alloc_poison() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+
+        let slot_ptr = slab.new_slot().unwrap().into_raw();
+        // SAFETY: The slot is valid for 64 bytes and owned by the test.
+        let bytes = unsafe { core::slice::from_raw_parts(slot_ptr, 64) };
+        assert!(bytes.iter().all(|&byte| byte == ALLOC_POISON_BYTE));
+        // SAFETY: The slot is converted back from its raw pointer exactly once.
+        slab.recycle_slot(unsafe { FreeSlabSlot::from_raw(slot_ptr) });
+
+        // A zeroed slot is not poisoned.
+        let mut slot = slab.new_slot().unwrap().zeroed();
+        // SAFETY: The link in the first word is not accessed.
+        let bytes = unsafe { slot.as_uninit_slice() };
+        // SAFETY: All the bytes are written by `zeroed`.
+        assert!(bytes.iter().all(|byte| unsafe { byte.assume_init() } == 0));
+        slab.recycle_slot(slot);
+    }
+
+    #[test]
+    fn coalesced_free_list_allocates_in_ascending_order() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            slots.push(slot);
+        }
+
+        // Scramble the free list by recycling the slots in a strided order.
+        // The stride is coprime to the number of slots, so every slot is recycled once.
+        let nr_slots = slots.len();
+        let stride = (7..).find(|stride| gcd(*stride, nr_slots) == 1).unwrap();
+        let mut slots: Vec<_> = slots.into_iter().map(Some).collect();
+        for i in 0..nr_slots {
+            slab.recycle_slot(slots[i * stride % nr_slots].take().unwrap());
+        }
+        slab.coalesce_free_list();
+        slab.validate().unwrap();
+
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            slots.push(slot);
+        }
+        assert_eq!(slots.len(), nr_slots);
+        assert!(slots.windows(2).all(|pair| pair[0].as_ptr() < pair[1].as_ptr()));
+        for slot in slots {
+            slab.recycle_slot(slot);
+        }
+    }
+
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 { a } else { gcd(b, a % b) }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn arc_layout_matches_the_std_arc() {
+        let _test_heap = TestHeap::lock(16);
+        check_arc_layout();
+        // The check runs once, so the second call returns right away.
+        check_arc_layout();
+    }
+
+    #[test]
+    fn lifecycle_ext_counts_the_creation_and_destruction_of_slabs() {
+        static NR_CREATED_SLOTS: AtomicUsize = AtomicUsize::new(0);
+        static NR_DESTROYED_SLOTS: AtomicUsize = AtomicUsize::new(0);
+        #[derive(Clone)]
+        struct CountingExt;
+        impl SlabLifecycle for CountingExt {
+            fn on_slab_created(&self, _slab_base: *const u8, nr_slots: usize) {
+                NR_CREATED_SLOTS.fetch_add(nr_slots, Relaxed);
+            }
+            fn on_slab_destroyed(&self, _slab_base: *const u8, nr_slots: usize) {
+                NR_DESTROYED_SLOTS.fetch_add(nr_slots, Relaxed);
+            }
+        }
+
+        let _test_heap = TestHeap::lock(16);
+        let slabs = [(); 3].map(|_| Slab::<64, CountingExt>::alloc_with_lifecycle(recycle_slot, CountingExt).unwrap());
+        let nr_slots = slabs[0].nr_total_slots();
+        assert_eq!(NR_CREATED_SLOTS.load(Relaxed), 3 * nr_slots);
+        assert_eq!(NR_DESTROYED_SLOTS.load(Relaxed), 0);
+
+        drop(slabs);
+        assert_eq!(NR_DESTROYED_SLOTS.load(Relaxed), 3 * nr_slots);
+
+        // The slabs without the hooks notify nothing.
+        drop(Slab::<64, CountingExt>::alloc(recycle_slot, CountingExt).unwrap());
+        assert_eq!(NR_CREATED_SLOTS.load(Relaxed), 3 * nr_slots);
+        assert_eq!(NR_DESTROYED_SLOTS.load(Relaxed), 3 * nr_slots);
+    }
+
+    #[test]
+    fn slot_casts_to_the_same_size_only() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let slot = slab.new_slot().unwrap();
+        let slot_ptr = slot.as_ptr();
+
+        // The original slot is returned on mismatch.
+        let slot = slot.cast::<128>().unwrap_err();
+        assert_eq!(slot.as_ptr(), slot_ptr);
+        let slot = slot.cast::<32>().unwrap_err();
+        assert_eq!(slot.as_ptr(), slot_ptr);
+
+        let slot: FreeSlabSlot<64> = slot.cast::<64>().unwrap();
+        assert_eq!(slot.as_ptr(), slot_ptr);
+        assert_eq!(slab.nr_used_slots(), 1);
+        slab.recycle_slot(slot);
+        assert_eq!(slab.nr_used_slots(), 0);
+    }
+
+    #[test]
+    fn spliced_lists_keep_all_their_slots() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let mut new_list = |nr_slots: usize| {
+            let mut list = FreeSlabSlotList::new();
+            let mut slot_addrs = Vec::new();
+            for _ in 0..nr_slots {
+                let slot = slab.new_slot().unwrap();
+                slot_addrs.push(slot.as_ptr() as usize);
+                list.push(slot);
+            }
+            // The slots are popped in the reverse order of the pushes.
+            slot_addrs.reverse();
+            (list, slot_addrs)
+        };
+
+        let (mut list, mut expected_addrs) = new_list(3);
+        let (other_list, other_addrs) = new_list(2);
+        list.splice(other_list);
+        expected_addrs.extend(other_addrs);
+        assert_eq!(list.len(), 5);
+        // Splicing onto or from an empty list keeps the length, too.
+        list.splice(FreeSlabSlotList::new());
+        let mut empty_list = FreeSlabSlotList::new();
+        empty_list.splice(list);
+        let (other_list, other_addrs) = new_list(1);
+        empty_list.splice(other_list);
+        expected_addrs.extend(other_addrs);
+        let mut list = empty_list;
+        assert_eq!(list.len(), 6);
+        assert_eq!(slab.nr_borrowed_slots(), 6);
+
+        let mut popped_addrs = Vec::new();
+        while let Some(slot) = list.pop() {
+            popped_addrs.push(slot.as_ptr() as usize);
+            slab.recycle_slot(slot);
+        }
+        assert_eq!(popped_addrs, expected_addrs);
+        assert!(list.is_empty());
+        assert_eq!(slab.nr_borrowed_slots(), 0);
+        assert_eq!(slab.nr_used_slots(), 0);
+    }
+
+    #[test]
+    fn spanned_slots_are_excluded_from_single_slot_allocation() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let nr_total_slots = slab.nr_total_slots();
+        let span_ptr = slab.alloc_span(2).unwrap();
+        let span_range = span_ptr.as_ptr() as usize..span_ptr.as_ptr() as usize + 2 * 64;
+        // The span is contiguous and writable.
+        // SAFETY: The span is valid for 128 bytes and owned by the test.
+        unsafe { span_ptr.as_ptr().write_bytes(0x5A, 2 * 64) };
+
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            assert!(!span_range.contains(&(slot.as_ptr() as usize)));
+            slots.push(slot);
+        }
+        assert_eq!(slots.len(), nr_total_slots - 2);
+        slab.validate().unwrap();
+
+        // SAFETY: The span is allocated above with the same number of slots.
+        unsafe { slab.free_span(span_ptr, 2) };
+        // Only the spanned slots are free, so they are allocated next.
+        let mut spanned_slots = [slab.new_slot().unwrap(), slab.new_slot().unwrap()];
+        spanned_slots.sort_unstable_by_key(|slot| slot.as_ptr() as usize);
+        assert_eq!(spanned_slots[0].as_ptr() as usize, span_range.start);
+        assert_eq!(spanned_slots[1].as_ptr() as usize, span_range.start + 64);
+        assert!(slab.new_slot().is_none());
+
+        for slot in slots.into_iter().chain(spanned_slots) {
+            slab.recycle_slot(slot);
+        }
+        slab.validate().unwrap();
+    }
+
+    #[test]
+    fn hot_fields_of_the_metadata_share_a_cache_line_of_their_own() {
+        macro_rules! offsets_of {
+            ($ext:ty) => {
+                [
+                    mem::offset_of!(SlabMeta<64, $ext>, free_list),
+                    mem::offset_of!(SlabMeta<64, $ext>, nr_inuse_slots),
+                    mem::offset_of!(SlabMeta<64, $ext>, nr_borrowed_slots),
+                    mem::offset_of!(SlabMeta<64, $ext>, reclaim_guard),
+                    mem::offset_of!(SlabMeta<64, $ext>, nr_pages),
+                    mem::offset_of!(SlabMeta<64, $ext>, freed_bitmap),
+                ]
+            };
+        }
+        let offsets = offsets_of!(());
+        let [free_list, nr_inuse_slots, nr_borrowed_slots, reclaim_guard, nr_pages, _] = offsets;
+        assert_eq!(free_list, 0);
+        assert!([nr_inuse_slots, nr_borrowed_slots, reclaim_guard].iter().all(|&offset| offset < CACHE_LINE_SIZE));
+        // The mostly-immutable fields start on the next cache line.
+        assert_eq!(nr_pages, CACHE_LINE_SIZE);
+
+        // The padding does not depend on the extension.
+        assert_eq!(offsets_of!([u64; 4]), offsets);
+        assert_eq!(offsets_of!(AgeExt), offsets);
+    }
+
+    // With `heap_assert_full`, the stale slot is rejected even earlier,
+    // as its page is no longer tagged as a slab page.
+    #[test]
+    #[cfg(not(feature = "heap_assert_full"))]
+    fn recycling_to_a_dead_slab_is_reported() {
+        static DEAD_SLAB_ADDR: AtomicUsize = AtomicUsize::new(0);
+        // The pages of the test arena stay mapped after being freed,
+        // so the dead metadata is still readable, like with a lazy unmapping.
+        let _test_heap = TestHeap::lock(16);
+        super::super::set_bad_free_handler(|bad_free| match bad_free {
+            BadFree::DeadSlab { ptr } => DEAD_SLAB_ADDR.store(ptr as usize, Relaxed),
+            bad_free => panic!("unexpected bad free: {:?}", bad_free),
+        });
+
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let slot = slab.new_slot().unwrap();
+        let slot_ptr = slot.as_ptr() as *mut u8;
+        slab.recycle_slot(slot);
+        drop(slab);
+
+        // A buggy caller frees the slot again after the slab is torn down.
+        // SAFETY: The slot is never used, as the recycle is rejected.
+        let stale_slot = unsafe { FreeSlabSlot::<64>::from_raw(slot_ptr) };
+        stale_slot.recycle(&irq::disable_local());
+        assert_eq!(DEAD_SLAB_ADDR.load(Relaxed), slot_ptr as usize);
+    }
+
+    #[test]
+    fn frees_route_to_the_new_owner_of_a_rehomed_slab() {
+        // The owner (i.e., the extension) and the address of the last recycled slot.
+        static RECYCLED_OWNER: AtomicUsize = AtomicUsize::new(0);
+        static RECYCLED_ADDR: AtomicUsize = AtomicUsize::new(0);
+        fn recycle_to_old_owner(_slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            unreachable!("a slot of the re-homed slab is recycled to the old owner");
+        }
+        fn recycle_to_new_owner(slot: FreeSlabSlot<64>, extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            RECYCLED_OWNER.store(*extension.downcast_ref::<u32>().unwrap() as usize, Relaxed);
+            // The slot stays in use until the test recycles it to the slab.
+            RECYCLED_ADDR.store(slot.into_raw() as usize, Relaxed);
+        }
+
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, u32>::alloc(recycle_to_old_owner, 1).unwrap();
+        let slot = slab.new_slot().unwrap();
+        let slot_addr = slot.as_ptr() as usize;
+
+        // SAFETY: No slot of the slab is recycled concurrently.
+        let old_extension = unsafe { slab.rehome(recycle_to_new_owner, 2) };
+        assert_eq!(old_extension, 1);
+        assert_eq!(*slab.slab_extension(), 2);
+
+        // The slot allocated before the migration is freed to the new owner.
+        slot.recycle(&irq::disable_local());
+        assert_eq!(RECYCLED_OWNER.load(Relaxed), 2);
+        assert_eq!(RECYCLED_ADDR.load(Relaxed), slot_addr);
+
+        // SAFETY: The slot is converted back from its raw pointer exactly once.
+        slab.recycle_slot(unsafe { FreeSlabSlot::from_raw(slot_addr as *mut u8) });
+    }
+
+    #[test]
+    fn freed_slots_are_reused_in_the_order_of_the_policy() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        // Fill the slab, so that only the recycled slots are free.
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            slots.push(slot);
+        }
+        let [first_addr, second_addr, third_addr] = [0, 1, 2].map(|i| slots[i].as_ptr() as usize);
+        for slot in slots.drain(..3) {
+            slab.recycle_slot(slot);
+        }
+
+        let reused_addrs = [(); 3].map(|_| {
+            let slot = slab.new_slot().unwrap();
+            let slot_addr = slot.as_ptr() as usize;
+            slots.push(slot);
+            slot_addr
+        });
+        #[cfg(not(feature = "slab_fifo_free_list"))]
+        assert_eq!(reused_addrs, [third_addr, second_addr, first_addr]);
+        #[cfg(feature = "slab_fifo_free_list")]
+        assert_eq!(reused_addrs, [first_addr, second_addr, third_addr]);
+
+        assert!(slab.new_slot().is_none());
+        for slot in slots {
+            slab.recycle_slot(slot);
+        }
+    }
+
+    #[test]
+    fn unlinking_the_middle_slab_joins_its_neighbors() {
+        let _test_heap = TestHeap::lock(16);
+        let [mut first, mut middle, mut last] = [(); 3].map(|_| Slab::<64, ()>::alloc(recycle_slot, ()).unwrap());
+        let base_of = |slab: &Slab<64, ()>| NonNull::new(slab.base_ptr() as *mut u8).unwrap();
+
+        middle.link_after(&mut first);
+        last.link_after(&mut middle);
+        assert_eq!(first.next_slab(), Some(base_of(&middle)));
+        assert_eq!(middle.next_slab(), Some(base_of(&last)));
+        assert_eq!(last.next_slab(), None);
+        assert!([&first, &middle, &last].iter().all(|slab| slab.is_linked()));
+
+        middle.unlink();
+        assert!(!middle.is_linked());
+        assert_eq!(first.next_slab(), Some(base_of(&last)));
+        assert_eq!(last.next_slab(), None);
+        // The back link of the last slab is fixed, too, so it unlinks cleanly.
+        last.unlink();
+        assert!(!first.is_linked() && !last.is_linked());
+        drop((first, middle, last));
+    }
+
+    #[test]
+    fn slots_lie_past_the_metadata() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let slab_base = slab.base_ptr() as usize;
+        let slab_size = slab.nr_pages() * PAGE_SIZE;
+        let meta_end = slab_meta_addr(slab.base_ptr()) as usize + mem::size_of::<SlabMeta<64, ()>>();
+
+        let first_slot_addr = slab.slot_ptr(0) as usize;
+        assert!(first_slot_addr >= meta_end);
+        assert_eq!(first_slot_addr % 64, 0);
+        // The slots overlapping the metadata are not counted.
+        let nr_total_slots = slab.nr_total_slots();
+        assert_eq!(nr_total_slots, (slab_base + slab_size - first_slot_addr) / 64);
+        assert!(nr_total_slots < slab_size / 64);
+
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            let slot_addr = slot.as_ptr() as usize;
+            assert!(slot_addr >= first_slot_addr && slot_addr + 64 <= slab_base + slab_size);
+            slots.push(slot);
+        }
+        assert_eq!(slots.len(), nr_total_slots);
+        for slot in slots {
+            slab.recycle_slot(slot);
+        }
+    }
+
+    #[test]
+    fn slab_aslr_shuffles_the_hand_out_order() {
+        let _test_heap = TestHeap::lock(16);
+        // The hand-out order of a fresh slab, as slot offsets from the slab base.
+        let hand_out_order = || {
+            let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+            let mut slots = Vec::new();
+            while let Some(slot) = slab.new_slot() {
+                slots.push(slot);
+            }
+            let offsets: Vec<_> = slots
+                .iter()
+                .map(|slot| slot.as_ptr() as usize - slab.base_ptr() as usize)
+                .collect();
+            for slot in slots {
+                slab.recycle_slot(slot);
+            }
+            offsets
+        };
+
+        let first_order = hand_out_order();
+        let second_order = hand_out_order();
+        #[cfg(feature = "slab_aslr")]
+        assert_ne!(first_order, second_order);
+        #[cfg(not(feature = "slab_aslr"))]
+        {
+            assert_eq!(first_order, second_order);
+            assert!(first_order.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+    }
+
+    #[test]
+    fn slot_round_trips_through_nonnull() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let slot = slab.new_slot().unwrap();
+        let slot_ptr = slot.as_ptr();
+
+        let obj_ptr = slot.into_nonnull([7u64; 8]);
+        assert_eq!(obj_ptr.as_ptr() as *const u8, slot_ptr as *const u8);
+        // SAFETY: The object is written by `into_nonnull` above and is not used afterwards.
+        let obj = unsafe { obj_ptr.as_ptr().read() };
+        assert_eq!(obj, [7u64; 8]);
+        // SAFETY: The pointer is returned by `into_nonnull` and the object is moved out.
+        let slot = unsafe { FreeSlabSlot::<64>::from_nonnull(obj_ptr) };
+        assert_eq!(slot.as_ptr(), slot_ptr);
+
+        // The recovered slot can be boxed, as any other slot.
+        let boxed_obj = slot.into_box(obj);
+        assert_eq!(*boxed_obj, [7u64; 8]);
+        let slot = FreeSlabSlot::<64>::from_box(boxed_obj);
+        assert_eq!(slot.as_ptr(), slot_ptr);
+        assert_eq!(slab.nr_used_slots(), 1);
+        slab.recycle_slot(slot);
+    }
+
+    #[test]
+    fn multi_page_slab_stores_the_expected_slot_count() {
+        let _test_heap = TestHeap::lock(16);
+        set_pages_per_slab(SizeClass::B256, 2);
+        let mut slab = Slab::<256, ()>::alloc(recycle_slot, ()).unwrap();
+
+        let nr_meta_slots = slab_meta_region_size::<256, ()>().div_ceil(256);
+        let nr_expected_slots = 2 * PAGE_SIZE / 256 - nr_meta_slots;
+        assert_eq!(slab.nr_total_slots(), nr_expected_slots);
+        assert_eq!(slab.slab_meta().nr_total_slots as usize, nr_expected_slots);
+
+        // The stored count bounds the slots handed out and the validation.
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            slots.push(slot);
+        }
+        assert_eq!(slots.len(), nr_expected_slots);
+        slab.validate().unwrap();
+        for slot in slots {
+            slab.recycle_slot(slot);
+        }
+        slab.validate().unwrap();
+    }
+
+    #[test]
+    fn drain_filter_removes_the_slots_of_one_slab() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab_a = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let mut slab_b = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let slab_a_base = slab_a.base_ptr();
+
+        // Interleave the slots of the two slabs on a list.
+        let mut list = FreeSlabSlotList::new();
+        for _ in 0..3 {
+            list.push(slab_a.new_slot().unwrap());
+            list.push(slab_b.new_slot().unwrap());
+        }
+        let mut drained = list.drain_filter(|slot| slot.slab_view().base_ptr() == slab_a_base);
+        assert_eq!((drained.len(), list.len()), (3, 3));
+        assert_eq!((slab_a.nr_borrowed_slots(), slab_b.nr_borrowed_slots()), (3, 3));
+
+        while let Some(slot) = drained.pop() {
+            assert_eq!(slot.slab_view().base_ptr(), slab_a_base);
+            slab_a.recycle_slot(slot);
+        }
+        while let Some(slot) = list.pop() {
+            assert_eq!(slot.slab_view().base_ptr(), slab_b.base_ptr());
+            slab_b.recycle_slot(slot);
+        }
+        assert_eq!((slab_a.nr_used_slots(), slab_b.nr_used_slots()), (0, 0));
+    }
+
+    #[test]
+    fn slab_created_hook_fires_with_the_class_and_the_pages() {
+        // The hook must not allocate, so it records the last slab in statics.
+        static CREATED_SLOT_SIZE: AtomicUsize = AtomicUsize::new(0);
+        static CREATED_BASE: AtomicUsize = AtomicUsize::new(0);
+        static CREATED_NR_PAGES: AtomicUsize = AtomicUsize::new(0);
+        fn recycle_slot(_slot: FreeSlabSlot<128>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            unreachable!("the slots of the test are recycled to their slab directly");
+        }
+
+        let _test_heap = TestHeap::lock(16);
+        super::super::set_slab_created_hook(|slot_size, base, nr_pages| {
+            CREATED_SLOT_SIZE.store(slot_size, Relaxed);
+            CREATED_BASE.store(base as usize, Relaxed);
+            CREATED_NR_PAGES.store(nr_pages, Relaxed);
+        });
+        set_pages_per_slab(SizeClass::B128, 4);
+
+        let slab = Slab::<128, ()>::alloc(recycle_slot, ()).unwrap();
+        assert_eq!(CREATED_SLOT_SIZE.load(Relaxed), 128);
+        assert_eq!(CREATED_BASE.load(Relaxed), slab.base_ptr() as usize);
+        assert_eq!(CREATED_NR_PAGES.load(Relaxed), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "slab_ro_meta")]
+    fn recycle_fn_is_read_from_the_read_only_header() {
+        static RECYCLED_ADDR: AtomicUsize = AtomicUsize::new(0);
+        fn recycle_slot(slot: FreeSlabSlot<64>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            // The slot stays in use until the test recycles it to the slab.
+            RECYCLED_ADDR.store(slot.into_raw() as usize, Relaxed);
+        }
+
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let base_addr = slab.base_ptr() as usize;
+        // The header takes the first page, and the mutable metadata the second.
+        let header_addr = slab.slab_meta().header() as *const SlabMetaHeader as usize;
+        assert_eq!(header_addr, base_addr);
+        assert_eq!(slab.slab_meta() as *const _ as usize, base_addr + PAGE_SIZE);
+
+        // Both parts of the metadata are recovered from a slot by masking.
+        let slot = slab.new_slot().unwrap();
+        let slot_addr = slot.as_ptr() as usize;
+        assert!(slot_addr >= base_addr + slab_meta_region_size::<64, ()>());
+        assert_eq!(slot.slab_view().base_ptr() as usize, base_addr);
+        assert_eq!(slot.slab_meta().header() as *const SlabMetaHeader as usize, header_addr);
+
+        slot.recycle(&irq::disable_local());
+        assert_eq!(RECYCLED_ADDR.load(Relaxed), slot_addr);
+        // SAFETY: The slot is converted back from its raw pointer exactly once.
+        slab.recycle_slot(unsafe { FreeSlabSlot::from_raw(slot_addr as *mut u8) });
+    }
+
+    // With `slab_ro_meta`, no slab is of one page, so no slab takes a stashed page.
+    #[test]
+    #[cfg(not(feature = "slab_ro_meta"))]
+    fn slabs_of_one_page_consume_the_page_stash() {
+        fn recycle_slot(_slot: FreeSlabSlot<256>, _extension: &dyn Any, _pin_cpu_guard: &dyn PinCurrentCpu) {
+            unreachable!("the slabs of the test allocate no slots");
+        }
+        const STASH_SIZE: usize = 4;
+
+        let _test_heap = TestHeap::lock(32);
+        assert_eq!(pages_per_slab(SizeClass::B256), 1);
+        super::super::set_page_stash_size(STASH_SIZE);
+        let nr_free_pages = page_alloc::nr_free_pages();
+        let mut slabs = Vec::new();
+
+        // The first slab finds the stash empty, which is refilled to its size.
+        slabs.push(Slab::<256, ()>::alloc(recycle_slot, ()).unwrap());
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages - STASH_SIZE);
+        // The following slabs take the rest of the stash.
+        for _ in 1..STASH_SIZE {
+            slabs.push(Slab::<256, ()>::alloc(recycle_slot, ()).unwrap());
+            assert_eq!(page_alloc::nr_free_pages(), nr_free_pages - STASH_SIZE);
+        }
+        // Then the stash is refilled to its size again.
+        slabs.push(Slab::<256, ()>::alloc(recycle_slot, ()).unwrap());
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages - 2 * STASH_SIZE);
+
+        // Warming fills up the stash ahead of the slabs.
+        super::super::warm_page_cache();
+        let nr_warm_free_pages = page_alloc::nr_free_pages();
+        assert_eq!(nr_warm_free_pages, nr_free_pages - 2 * STASH_SIZE - 1);
+        slabs.push(Slab::<256, ()>::alloc(recycle_slot, ()).unwrap());
+        assert_eq!(page_alloc::nr_free_pages(), nr_warm_free_pages);
+    }
+
+    #[test]
+    fn dropped_slab_returns_its_pages() {
+        let _test_heap = TestHeap::lock(16);
+        let nr_free_pages = page_alloc::nr_free_pages();
+        let slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let nr_pages = slab.nr_pages();
+        let base_ptr = slab.base_ptr();
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages - nr_pages);
+        assert_eq!(slot_size_of(base_ptr.wrapping_add(nr_pages * PAGE_SIZE - 1)), Some(64));
+
+        drop(slab);
+        assert_eq!(page_alloc::nr_free_pages(), nr_free_pages);
+        // The pages are no longer tagged as slab pages.
+        assert_eq!(slot_size_of(base_ptr), None);
+        assert_eq!(slot_size_of(base_ptr.wrapping_add(nr_pages * PAGE_SIZE - 1)), None);
+    }
+
+    // The prefetch is issued for the new head of the free list on every allocation,
+    // including the last one, which leaves the free list empty.
+    #[test]
+    #[cfg(feature = "slab_prefetch")]
+    fn prefetching_allocations_drain_the_free_list_in_order() {
+        let _test_heap = TestHeap::lock(16);
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        let nr_total_slots = slab.nr_total_slots();
+
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            slots.push(slot);
+        }
+        assert_eq!(slots.len(), nr_total_slots);
+        assert_eq!(slab.nr_used_slots(), nr_total_slots);
+        let mut slot_addrs: Vec<usize> = slots.iter().map(|slot| slot.as_ptr() as usize).collect();
+        slot_addrs.sort_unstable();
+        slot_addrs.dedup();
+        assert_eq!(slot_addrs.len(), nr_total_slots);
+
+        for slot in slots {
+            slab.recycle_slot(slot);
+        }
+        assert_eq!(slab.nr_used_slots(), 0);
+        slab.validate().unwrap();
+    }
+
+    // Reports the TSC cycles per allocation of allocating all the slots of a fresh slab,
+    // i.e., a walk of the free list. Compare the runs with and without `slab_prefetch`:
+    //
+    //     cargo test --release [--features slab_prefetch] -- --ignored --nocapture bench_allocating_a_whole_slab
+    #[test]
+    #[ignore = "a benchmark, whose result depends on the host"]
+    fn bench_allocating_a_whole_slab() {
+        const NR_BENCH_RUNS: usize = 100;
+
+        let _test_heap = TestHeap::lock(16);
+        let mut slots = Vec::with_capacity(PAGE_SIZE / 64);
+        let min_cycles_per_alloc = (0..NR_BENCH_RUNS)
+            .map(|_| {
+                let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+                let start = crate::arch::read_tsc();
+                while let Some(slot) = slab.new_slot() {
+                    slots.push(slot);
+                }
+                let cycles = crate::arch::read_tsc() - start;
+                let cycles_per_alloc = cycles / slots.len() as u64;
+                for slot in slots.drain(..) {
+                    slab.recycle_slot(slot);
+                }
+                cycles_per_alloc
+            })
+            .min()
+            .unwrap();
+        std::println!(
+            "slab_prefetch {}: {} cycles per allocation",
+            if cfg!(feature = "slab_prefetch") { "on" } else { "off" },
+            min_cycles_per_alloc,
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "page_size_16k")]
+    fn slabs_follow_the_16k_page_size() {
+        let _test_heap = TestHeap::lock(32);
+        assert_eq!(PAGE_SIZE, 16 * 1024);
+        let nr_pages = pages_per_slab(SizeClass::B64);
+        let slab_size = nr_pages * 16 * 1024;
+
+        let mut slab = Slab::<64, ()>::alloc(recycle_slot, ()).unwrap();
+        assert_eq!(slab.base_ptr() as usize % slab_size, 0);
+        assert_eq!(SLAB_BASE_MASKS[SizeClass::B64.index()].load(Relaxed), !(slab_size - 1));
+        assert_eq!(
+            slab.nr_total_slots(),
+            (slab_size - slab_meta_region_size::<64, ()>()) / 64,
+        );
+
+        // Every slot, up to the one in the last 4KB of the slab, is masked to the slab.
+        let mut slots = Vec::new();
+        while let Some(slot) = slab.new_slot() {
+            assert_eq!(slab_base_of::<64>(slot.as_ptr()), slab.base_ptr());
+            slots.push(slot);
+        }
+        assert_eq!(slots.len(), slab.nr_total_slots());
+        let last_slot_addr = slots.iter().map(|slot| slot.as_ptr() as usize).max().unwrap();
+        assert_eq!(last_slot_addr + 64, slab.base_ptr() as usize + slab_size);
+        for slot in slots {
+            slab.recycle_slot(slot);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! The tracking of live heap allocations.
+//!
+//! Each allocation is tracked along with its layout and the call site,
+//! until it is freed. The live allocations can be walked with
+//! [`for_each_live_allocation`], e.g., to report leaks.
+//!
+//! The live allocations are kept in a fixed-size table, so that tracking
+//! never allocates memory from the heap. The allocations that do not fit
+//! in the table are not tracked. Tracking costs a linear search on each free,
+//! which is acceptable for a debugging feature.
+
+const MAX_NR_LIVE_ALLOCS: usize = 4096;
+
+/// The call site of an allocation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallSite {
+    /// The call-site tag given to [`alloc_tagged`](super::alloc_tagged),
+    /// or the empty tag for untagged allocations.
+    pub tag: &'static str,
+}
+
+#[derive(Clone, Copy)]
+struct LiveAlloc {
+    ptr: *mut u8,
+    layout: Layout,
+    call_site: CallSite,
+}
+
+struct LiveAllocTable {
+    allocs: [Option<LiveAlloc>; MAX_NR_LIVE_ALLOCS],
+    len: usize,
+}
+
+// SAFETY: The pointers are only used as the identities of the allocations,
+// never dereferenced.
+unsafe impl Send for LiveAllocTable {}
+
+static LIVE_ALLOCS: SpinLock<LiveAllocTable> = SpinLock::new(LiveAllocTable {
+    allocs: [None; MAX_NR_LIVE_ALLOCS],
+    len: 0,
+});
+
+/// Starts tracking a live allocation.
+pub(super) fn track(ptr: *mut u8, layout: Layout, call_site: CallSite) {
+    let mut table = LIVE_ALLOCS.lock();
+    if table.len == MAX_NR_LIVE_ALLOCS {
+        return;
+    }
+    let len = table.len;
+    table.allocs[len] = Some(LiveAlloc { ptr, layout, call_site });
+    table.len += 1;
+}
+
+/// Stops tracking a freed allocation.
+pub(super) fn untrack(ptr: *mut u8) {
+    let mut table = LIVE_ALLOCS.lock();
+    let len = table.len;
+    let Some(index) = table.allocs[..len].iter().position(|alloc| alloc.is_some_and(|alloc| alloc.ptr == ptr)) else {
+        // The allocation did not fit in the table.
+        return;
+    };
+    table.allocs.swap(index, len - 1);
+    table.allocs[len - 1] = None;
+    table.len -= 1;
+}
+
+/// Updates the size of a live allocation that has been reallocated in place.
+pub(super) fn resize(ptr: *mut u8, new_size: usize) {
+    let mut table = LIVE_ALLOCS.lock();
+    let len = table.len;
+    if let Some(alloc) = table.allocs[..len].iter_mut().flatten().find(|alloc| alloc.ptr == ptr) {
+        // SAFETY: The new size has been validated by the reallocation.
+        alloc.layout = unsafe { Layout::from_size_align_unchecked(new_size, alloc.layout.align()) };
+    }
+}
+
+/// Invokes `f` on each live allocation that is tracked,
+/// with the pointer, the layout and the call site of the allocation.
+///
+/// The walk holds the lock of the tracking table, so that the set of
+/// live allocations does not change in the middle of the walk.
+/// The allocations made by `f` are not tracked. They are served by the early heap,
+/// or fail with the `no_early_heap` feature (see `HeapAlloc::alloc_reentrant`).
+///
+/// The function never blocks, so it can be called from
+/// a stop-the-world debugging context. If the table is locked,
+/// e.g., by a CPU that has been stopped in the middle of an allocation,
+/// the walk is skipped and `false` is returned.
+pub fn for_each_live_allocation(mut f: impl FnMut(*mut u8, Layout, CallSite)) -> bool {
+    let irq_disabled_guard = irq::disable_local();
+    // Mark the current CPU as inside the allocator, so that
+    // the allocations made by `f` do not lock the table again.
+    let _entry = AllocatorEntry::enter(&irq_disabled_guard);
+
+    let Some(table) = LIVE_ALLOCS.try_lock() else {
+        return false;
+    };
+    for alloc in table.allocs[..table.len].iter().flatten() {
+        f(alloc.ptr, alloc.layout, alloc.call_site);
+    }
+    true
+}